@@ -0,0 +1,704 @@
+// File: src/api.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use roll_lang::macros::Macros;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::budget::EvaluationBudget;
+use super::evaluator::RollMode;
+use super::output::{Output, RolledDie};
+use super::profile::HistoryEntry;
+use super::roll::{QueryAnswers, RollContext};
+use super::roll_worker::{evaluate, WORKER_STACK_SIZE};
+
+// Only programs on this computer can reach it unless told otherwise
+pub static DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+// Bodies larger than this are turned away
+const MAX_BODY: usize = 1024 * 1024;
+// How long a client has to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+// How long the window has to answer, it is only asked for quick things
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(60);
+
+// What the API asks of the window, on the GTK main thread. Rolls are
+// evaluated on the client's thread in between so a long one does not hold
+// up the window.
+pub trait Window {
+	fn roll_setup(&self) -> RollSetup;
+	// Adds a roll to the history like one rolled in the window
+	fn add_roll(&mut self, input: String, output: Result<Output, String>) -> HistoryEntry;
+	// The name and history of the character, the active one without a name
+	fn history(&self, character: Option<&str>) -> Option<(String, Vec<HistoryEntry>)>;
+	fn macros(&self) -> Macros;
+	fn set_macros(&mut self, macros: Macros) -> Result<Macros, String>;
+}
+
+// What a roll is evaluated with, from the window.
+pub struct RollSetup {
+	pub context: RollContext,
+	pub budget: EvaluationBudget,
+	pub mode: RollMode,
+}
+
+// Work for the window, run on the main loop.
+type Job = Box<dyn FnOnce(&mut dyn Window) + Send>;
+
+// What a client asked for.
+#[derive(Debug, Clone)]
+enum Request {
+	// `POST /roll {"input": "/r 1d20+5", "answers": {"Bonus": "2"}}`
+	Roll {
+		input: String,
+		answers: QueryAnswers,
+	},
+	// `GET /history?character=Bob`, the active profile without one
+	History {
+		character: Option<String>,
+	},
+	// `GET /macros` and `PUT /macros {"attack": "/r 1d20+5"}` of the
+	// active profile
+	Macros,
+	SetMacros(Macros),
+}
+
+#[derive(Debug, Clone)]
+struct Response {
+	status: u16,
+	body: Value,
+}
+impl Response {
+	fn ok(body: Value) -> Response {
+		Response { status: 200, body }
+	}
+	// `{"error": "..."}`
+	fn error(status: u16, message: &str) -> Response {
+		Response {
+			status,
+			body: json!({ "error": message }),
+		}
+	}
+}
+
+// Listens on the address and answers requests with the window, which is
+// used on the main loop. The address listened on is returned, with the port
+// picked when it was 0.
+pub fn start<W>(address: &str, mut window: W) -> io::Result<SocketAddr>
+where
+	W: Window + 'static,
+{
+	let listener = TcpListener::bind(address)?;
+	let local_address = listener.local_addr()?;
+
+	let (sender, receiver) = glib::MainContext::channel::<Job>(glib::PRIORITY_DEFAULT);
+	receiver.attach(None, move |job| {
+		job(&mut window);
+		glib::Continue(true)
+	});
+	listen(listener, move |job| sender.send(job).is_ok())?;
+	Ok(local_address)
+}
+
+// Answers every client on a thread of its own, `send` gives jobs to the
+// window and is false once it is gone.
+fn listen<S>(listener: TcpListener, send: S) -> io::Result<()>
+where
+	S: Fn(Job) -> bool + Clone + Send + 'static,
+{
+	let local_address = listener.local_addr()?;
+	thread::Builder::new()
+		.name(String::from("api"))
+		.spawn(move || {
+			for stream in listener.incoming() {
+				let stream = match stream {
+					Ok(stream) => stream,
+					Err(_) => continue,
+				};
+				// A slow client does not hold up the others. Its rolls are
+				// evaluated on its thread, which needs the room of a roll worker
+				let send = send.clone();
+				let _ = thread::Builder::new()
+					.name(String::from("api client"))
+					.stack_size(WORKER_STACK_SIZE)
+					.spawn(move || {
+						if let Err(e) = serve(stream, local_address, &send) {
+							eprintln!("API request failed: {}", e);
+						}
+					});
+			}
+		})?;
+	Ok(())
+}
+
+// Runs `f` with the window and waits for what it gives back.
+fn with_window<T, F>(send: &impl Fn(Job) -> bool, f: F) -> Result<T, Response>
+where
+	T: Send + 'static,
+	F: FnOnce(&mut dyn Window) -> T + Send + 'static,
+{
+	let (reply, answer) = mpsc::channel();
+	let job: Job = Box::new(move |window| {
+		let _ = reply.send(f(window));
+	});
+	if !send(job) {
+		return Err(Response::error(503, "The roll window is closed"));
+	}
+	answer
+		.recv_timeout(ANSWER_TIMEOUT)
+		.map_err(|_| Response::error(503, "The roll window did not answer"))
+}
+
+// A request as it was sent.
+struct HttpRequest {
+	method: String,
+	path: String,
+	query: Vec<(String, String)>,
+	host: Option<String>,
+	origin: Option<String>,
+	body: Vec<u8>,
+}
+
+fn serve(
+	stream: TcpStream,
+	local_address: SocketAddr,
+	send: &impl Fn(Job) -> bool,
+) -> io::Result<()> {
+	stream.set_read_timeout(Some(READ_TIMEOUT))?;
+	let mut writer = stream.try_clone()?;
+	let http = match read_request(&mut BufReader::new(stream)) {
+		Ok(http) => http,
+		Err(response) => return write_response(&mut writer, &response, None),
+	};
+
+	// Other sites can point their names at this computer, a page of theirs
+	// would then be sent here as its own
+	if let Some(host) = http.host.as_ref() {
+		if !is_local_host(host, local_address) {
+			let response = Response::error(403, "The Host is not this computer");
+			return write_response(&mut writer, &response, None);
+		}
+	}
+	// Web pages may only use it when they are served from this computer
+	let origin = http.origin.clone();
+	if let Some(origin) = origin.as_ref() {
+		if !is_local_origin(origin) {
+			let response = Response::error(403, "Requests from other sites are not allowed");
+			return write_response(&mut writer, &response, None);
+		}
+	}
+	if http.method == "OPTIONS" {
+		return write_response(
+			&mut writer,
+			&Response {
+				status: 204,
+				body: Value::Null,
+			},
+			origin.as_deref(),
+		);
+	}
+
+	let response = match route(&http).and_then(|request| answer(request, send)) {
+		Ok(response) | Err(response) => response,
+	};
+	write_response(&mut writer, &response, origin.as_deref())
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<HttpRequest, Response> {
+	let bad_request = |message: &str| Response::error(400, message);
+	let mut line = String::new();
+	reader
+		.read_line(&mut line)
+		.map_err(|_| bad_request("The request could not be read"))?;
+	let mut parts = line.split_whitespace();
+	let (method, target) = match (parts.next(), parts.next()) {
+		(Some(method), Some(target)) => (method.to_owned(), target.to_owned()),
+		_ => return Err(bad_request("The request line is missing")),
+	};
+
+	let mut content_length = 0;
+	let mut host = None;
+	let mut origin = None;
+	loop {
+		let mut header = String::new();
+		reader
+			.read_line(&mut header)
+			.map_err(|_| bad_request("The headers could not be read"))?;
+		let header = header.trim_end();
+		if header.is_empty() {
+			break;
+		}
+		let (name, value) = match header.find(':') {
+			Some(colon) => (header[..colon].trim(), header[colon + 1..].trim()),
+			None => return Err(bad_request("A header is missing its ':'")),
+		};
+		match name.to_lowercase().as_str() {
+			"content-length" => {
+				content_length = value
+					.parse()
+					.map_err(|_| bad_request("The Content-Length is not a number"))?
+			}
+			"host" => host = Some(value.to_owned()),
+			"origin" => origin = Some(value.to_owned()),
+			_ => (),
+		}
+	}
+	if content_length > MAX_BODY {
+		return Err(Response::error(413, "The body is too large"));
+	}
+	let mut body = vec![0; content_length];
+	reader
+		.read_exact(&mut body)
+		.map_err(|_| bad_request("The body is shorter than its Content-Length"))?;
+
+	let (path, query) = match target.find('?') {
+		Some(mark) => (&target[..mark], &target[mark + 1..]),
+		None => (target.as_str(), ""),
+	};
+	let query = query
+		.split('&')
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| match pair.find('=') {
+			Some(equals) => (
+				percent_decode(&pair[..equals]),
+				percent_decode(&pair[equals + 1..]),
+			),
+			None => (percent_decode(pair), String::new()),
+		})
+		.collect();
+	Ok(HttpRequest {
+		method,
+		path: path.trim_end_matches('/').to_owned(),
+		query,
+		host,
+		origin,
+		body,
+	})
+}
+
+// `Sir%20Bob+the%20Bold` is `Sir Bob the Bold`.
+fn percent_decode(text: &str) -> String {
+	let bytes = text.as_bytes();
+	let mut decoded = Vec::new();
+	let mut i = 0;
+	while i < bytes.len() {
+		match bytes[i] {
+			b'+' => decoded.push(b' '),
+			b'%' if i + 2 < bytes.len() => {
+				match std::str::from_utf8(&bytes[i + 1..i + 3])
+					.ok()
+					.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+				{
+					Some(byte) => {
+						decoded.push(byte);
+						i += 2;
+					}
+					None => decoded.push(b'%'),
+				}
+			}
+			byte => decoded.push(byte),
+		}
+		i += 1;
+	}
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// `localhost:7878` is `localhost`, `[::1]:7878` is `[::1]`.
+fn host_name(authority: &str) -> &str {
+	match authority.rfind(':') {
+		Some(colon) if !authority.ends_with(']') => &authority[..colon],
+		_ => authority,
+	}
+}
+// Other names can be pointed anywhere. Addresses are taken when they are
+// this computer's or the one the API was told to listen on.
+fn is_local_host(host: &str, local_address: SocketAddr) -> bool {
+	let name = host_name(host);
+	if name == "localhost" {
+		return true;
+	}
+	match name
+		.trim_start_matches('[')
+		.trim_end_matches(']')
+		.parse::<IpAddr>()
+	{
+		Ok(address) => address.is_loopback() || address == local_address.ip(),
+		Err(_) => false,
+	}
+}
+fn is_local_origin(origin: &str) -> bool {
+	let host = origin
+		.trim_start_matches("http://")
+		.trim_start_matches("https://");
+	["localhost", "127.0.0.1", "[::1]"].contains(&host_name(host))
+}
+
+// A string, or a number taken as its text so query answers can be written
+// as numbers.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+	String(String),
+	Number(serde_json::Number),
+}
+impl Text {
+	fn into_string(self) -> String {
+		match self {
+			Text::String(s) => s,
+			Text::Number(n) => n.to_string(),
+		}
+	}
+}
+fn strings(map: HashMap<String, Text>) -> HashMap<String, String> {
+	map.into_iter()
+		.map(|(key, value)| (key, value.into_string()))
+		.collect()
+}
+
+#[derive(Deserialize)]
+struct RollBody {
+	input: String,
+	#[serde(default)]
+	answers: Option<HashMap<String, Text>>,
+}
+
+fn route(http: &HttpRequest) -> Result<Request, Response> {
+	fn body<'a, T: Deserialize<'a>>(http: &'a HttpRequest) -> Result<T, Response> {
+		serde_json::from_slice(&http.body).map_err(|e| Response::error(400, &e.to_string()))
+	}
+	match (http.method.as_str(), http.path.as_str()) {
+		("POST", "/roll") => {
+			let body: RollBody = body(http)?;
+			Ok(Request::Roll {
+				input: body.input,
+				answers: strings(body.answers.unwrap_or_default()),
+			})
+		}
+		("GET", "/history") => Ok(Request::History {
+			character: http
+				.query
+				.iter()
+				.find(|(key, _)| key == "character")
+				.map(|(_, value)| value.clone()),
+		}),
+		("GET", "/macros") => Ok(Request::Macros),
+		("PUT", "/macros") => Ok(Request::SetMacros(strings(body(http)?))),
+		(_, "/roll") | (_, "/history") | (_, "/macros") => {
+			Err(Response::error(405, "The method is not allowed here"))
+		}
+		_ => Err(Response::error(404, "There is nothing here")),
+	}
+}
+
+fn answer(request: Request, send: &impl Fn(Job) -> bool) -> Result<Response, Response> {
+	match request {
+		Request::Roll { input, answers } => {
+			let setup = with_window(send, |window| window.roll_setup())?;
+			// Unanswered queries take their default
+			let output = evaluate(
+				&input,
+				&answers,
+				&setup.context,
+				setup.budget,
+				setup.mode,
+				Arc::new(AtomicBool::new(false)),
+			);
+			let entry = with_window(send, move |window| window.add_roll(input, output))?;
+			Ok(Response {
+				status: if entry.result.is_ok() { 200 } else { 422 },
+				body: entry_json(&entry),
+			})
+		}
+		Request::History { character } => {
+			let name = character.clone();
+			let found = with_window(send, move |window| window.history(name.as_deref()))?;
+			match (found, character) {
+				(Some((name, history)), _) => Ok(Response::ok(json!({
+					"character": name,
+					"history": history.iter().map(entry_json).collect::<Vec<Value>>(),
+				}))),
+				(None, name) => Err(Response::error(
+					404,
+					&format!("There is no character named {}", name.unwrap_or_default()),
+				)),
+			}
+		}
+		Request::Macros => {
+			let macros = with_window(send, |window| window.macros())?;
+			Ok(Response::ok(json!(macros)))
+		}
+		Request::SetMacros(macros) => {
+			match with_window(send, move |window| window.set_macros(macros))? {
+				Ok(macros) => Ok(Response::ok(json!(macros))),
+				Err(e) => Err(Response::error(
+					500,
+					&format!("Could not save profile: {}", e),
+				)),
+			}
+		}
+	}
+}
+
+fn write_response(
+	writer: &mut impl Write,
+	response: &Response,
+	origin: Option<&str>,
+) -> io::Result<()> {
+	let reason = match response.status {
+		200 => "OK",
+		204 => "No Content",
+		400 => "Bad Request",
+		403 => "Forbidden",
+		404 => "Not Found",
+		405 => "Method Not Allowed",
+		413 => "Payload Too Large",
+		422 => "Unprocessable Entity",
+		500 => "Internal Server Error",
+		503 => "Service Unavailable",
+		_ => "",
+	};
+	let body = match response.status {
+		204 => String::new(),
+		_ => format!("{}\n", response.body),
+	};
+	let mut head = format!(
+		"HTTP/1.1 {} {}\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n",
+		response.status,
+		reason,
+		body.len()
+	);
+	if let Some(origin) = origin {
+		head.push_str(&format!(
+			"Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Methods: GET, POST, PUT\r\nAccess-Control-Allow-Headers: Content-Type\r\nVary: Origin\r\n",
+			origin
+		));
+	}
+	head.push_str("\r\n");
+	writer.write_all(head.as_bytes())?;
+	writer.write_all(body.as_bytes())?;
+	writer.flush()
+}
+
+// `{"input": "/r 1d20", "result": "14=14", "totals": [14.0],
+// "dice": [{"sides": 20, "face": 14}], "time": 1580000000}` with `error`
+// in place of `result` when it failed.
+#[derive(Serialize)]
+struct EntryJson<'a> {
+	input: &'a str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	result: Option<&'a str>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	error: Option<&'a str>,
+	totals: &'a [f32],
+	dice: &'a [RolledDie],
+	time: Option<i64>,
+}
+fn entry_json(entry: &HistoryEntry) -> Value {
+	let json = EntryJson {
+		input: &entry.input,
+		result: entry.result.as_deref().ok(),
+		error: entry.result.as_ref().err().map(String::as_str),
+		totals: &entry.totals,
+		dice: &entry.dice,
+		time: entry.time,
+	};
+	serde_json::to_value(json).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::roll::{output_marked, totals, TextOptions};
+	use super::*;
+	use std::io::Read;
+
+	// Keeps rolls and macros like the window without showing them.
+	#[derive(Default)]
+	struct TestWindow {
+		history: Vec<HistoryEntry>,
+		macros: Macros,
+	}
+	impl Window for TestWindow {
+		fn roll_setup(&self) -> RollSetup {
+			RollSetup {
+				context: RollContext {
+					macros: self.macros.clone(),
+					..RollContext::default()
+				},
+				budget: EvaluationBudget::default(),
+				mode: RollMode::default(),
+			}
+		}
+		fn add_roll(&mut self, input: String, output: Result<Output, String>) -> HistoryEntry {
+			let marked = output
+				.as_ref()
+				.map(|output| output_marked(output, TextOptions::default()))
+				.map_err(String::clone);
+			let entry = HistoryEntry {
+				input,
+				result: marked
+					.as_ref()
+					.map(|marked| marked.text.clone())
+					.map_err(String::clone),
+				marks: marked.map(|marked| marked.marks).unwrap_or_default(),
+				totals: output.as_ref().map(totals).unwrap_or_default(),
+				dice: output.map(|output| output.dice).unwrap_or_default(),
+				time: None,
+			};
+			self.history.push(entry.clone());
+			entry
+		}
+		fn history(&self, character: Option<&str>) -> Option<(String, Vec<HistoryEntry>)> {
+			match character {
+				None | Some("Bob") => Some((String::from("Bob"), self.history.clone())),
+				Some(_) => None,
+			}
+		}
+		fn macros(&self) -> Macros {
+			self.macros.clone()
+		}
+		fn set_macros(&mut self, macros: Macros) -> Result<Macros, String> {
+			self.macros = macros;
+			Ok(self.macros.clone())
+		}
+	}
+
+	// Listens on a free port with the window on a thread of its own in place
+	// of the main loop.
+	fn serve_window() -> SocketAddr {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		let (sender, receiver) = mpsc::channel::<Job>();
+		thread::spawn(move || {
+			let mut window = TestWindow::default();
+			for job in receiver {
+				job(&mut window);
+			}
+		});
+		listen(listener, move |job| sender.send(job).is_ok()).unwrap();
+		address
+	}
+	fn send(address: SocketAddr, host: &str, request: &str, body: &str) -> (u16, Value) {
+		let mut stream = TcpStream::connect(address).unwrap();
+		write!(
+			stream,
+			"{}\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}",
+			request,
+			host,
+			body.len(),
+			body
+		)
+		.unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		let status = response[9..12].parse().unwrap();
+		let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+		(status, serde_json::from_str(body).unwrap_or(Value::Null))
+	}
+	fn request(address: SocketAddr, request: &str, body: &str) -> (u16, Value) {
+		send(address, &address.to_string(), request, body)
+	}
+
+	#[test]
+	fn rolls_are_kept_in_the_history() {
+		let address = serve_window();
+		let (status, body) = request(
+			address,
+			"POST /roll HTTP/1.1",
+			r#"{"input": "/r 1d1+?{Bonus|0}", "answers": {"Bonus": 2}}"#,
+		);
+		assert_eq!(status, 200);
+		assert_eq!(body["input"], "/r 1d1+?{Bonus|0}");
+		assert_eq!(body["totals"], json!([3.0]));
+		assert_eq!(body["dice"], json!([{"sides": 1, "face": 1}]));
+		assert!(body.get("error").is_none());
+
+		let (status, body) = request(address, "POST /roll HTTP/1.1", r#"{"input": "/r @{dex}"}"#);
+		assert_eq!(status, 422);
+		assert!(body["error"].is_string());
+		assert!(body.get("result").is_none());
+
+		let (status, body) = request(address, "GET /history HTTP/1.1", "");
+		assert_eq!(status, 200);
+		assert_eq!(body["character"], "Bob");
+		let history = body["history"].as_array().unwrap();
+		assert_eq!(history.len(), 2);
+		assert_eq!(history[0]["totals"], json!([3.0]));
+		assert!(history[1]["error"].is_string());
+
+		let (status, body) = request(address, "GET /history?character=Bob HTTP/1.1", "");
+		assert_eq!(status, 200);
+		assert_eq!(body["history"].as_array().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn unknown_characters_are_not_found() {
+		let address = serve_window();
+		let (status, body) = request(address, "GET /history?character=Sir%20Al HTTP/1.1", "");
+		assert_eq!(status, 404);
+		assert_eq!(body["error"], "There is no character named Sir Al");
+	}
+
+	#[test]
+	fn macros_are_set_and_used() {
+		let address = serve_window();
+		let (status, body) = request(address, "GET /macros HTTP/1.1", "");
+		assert_eq!(status, 200);
+		assert_eq!(body, json!({}));
+
+		let macros = r#"{"atk": "[[1d1+4]]", "dmg": "[[1d1]]"}"#;
+		let (status, body) = request(address, "PUT /macros HTTP/1.1", macros);
+		assert_eq!(status, 200);
+		assert_eq!(body, json!({"atk": "[[1d1+4]]", "dmg": "[[1d1]]"}));
+
+		let (status, body) = request(address, "GET /macros HTTP/1.1", "");
+		assert_eq!(status, 200);
+		assert_eq!(body["atk"], "[[1d1+4]]");
+
+		let (status, body) = request(address, "POST /roll HTTP/1.1", r##"{"input": "#atk"}"##);
+		assert_eq!(status, 200);
+		assert_eq!(body["totals"], json!([5.0]));
+	}
+
+	#[test]
+	fn bad_requests_are_turned_away() {
+		let address = serve_window();
+		let (status, _) = request(address, "POST /roll HTTP/1.1", "{\"input\": ");
+		assert_eq!(status, 400);
+		let (status, _) = request(address, "PUT /macros HTTP/1.1", r#"{"one": []}"#);
+		assert_eq!(status, 400);
+		let (status, _) = request(address, "DELETE /macros HTTP/1.1", "");
+		assert_eq!(status, 405);
+		let (status, _) = request(address, "GET /nothing HTTP/1.1", "");
+		assert_eq!(status, 404);
+	}
+
+	#[test]
+	fn only_this_computer_is_a_host() {
+		let address = serve_window();
+		let (status, _) = send(address, "evil.example", "GET /macros HTTP/1.1", "");
+		assert_eq!(status, 403);
+		let (status, _) = send(address, "localhost:7878", "GET /macros HTTP/1.1", "");
+		assert_eq!(status, 200);
+
+		let local: SocketAddr = "127.0.0.1:7878".parse().unwrap();
+		assert!(is_local_host("localhost:7878", local));
+		assert!(is_local_host("127.0.0.1", local));
+		assert!(is_local_host("[::1]:7878", local));
+		assert!(!is_local_host("93.184.216.34:7878", local));
+		assert!(!is_local_host("[2606:2800:220:1::]:7878", local));
+		assert!(!is_local_host("127.0.0.1.evil.example", local));
+
+		let lan: SocketAddr = "192.168.1.5:7878".parse().unwrap();
+		assert!(is_local_host("192.168.1.5:7878", lan));
+		assert!(!is_local_host("192.168.1.6:7878", lan));
+	}
+}
@@ -0,0 +1,339 @@
+// File: src/band_editor.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use super::bands::{parse_range, Band, BandTable, BandTables};
+
+type InsertHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn(&str)>>>>;
+
+// The colours offered for a label, any other `#rrggbb` can be typed.
+static COLOURS: [(&str, &str); 6] = [
+	("#c62828", "Red"),
+	("#ef6c00", "Orange"),
+	("#2e7d32", "Green"),
+	("#1565c0", "Blue"),
+	("#6a1b9a", "Purple"),
+	("#616161", "Grey"),
+];
+
+struct Row {
+	range: gtk::Entry,
+	label: gtk::Entry,
+	colour: gtk::ComboBoxText,
+	widgets: Vec<gtk::Widget>,
+}
+impl Row {
+	// The colour picked or typed, nothing when it is not a colour.
+	fn colour(&self) -> Option<String> {
+		if let Some(id) = self.colour.get_active_id() {
+			return Some(id.to_string());
+		}
+		let typed = self.colour.get_active_text()?.trim().to_owned();
+		let is_hex = typed.len() == 7
+			&& typed.starts_with('#')
+			&& typed[1..].chars().all(|c| c.is_ascii_hexdigit());
+		match is_hex {
+			true => Some(typed),
+			false => None,
+		}
+	}
+}
+
+// Editor for the band tables of the active profile. Works like the table
+// editor, one table is shown at a time.
+#[derive(Clone)]
+pub struct BandEditor {
+	selector: gtk::ComboBoxText,
+	name: gtk::Entry,
+	grid: gtk::Grid,
+	rows: Rc<RefCell<Vec<Row>>>,
+	next_row: Rc<Cell<i32>>,
+	status: gtk::Label,
+	tables: Rc<RefCell<Vec<(String, BandTable)>>>,
+	selected: Rc<Cell<Option<usize>>>,
+	// Set while the selector is filled so it does not report a selection
+	updating: Rc<Cell<bool>>,
+	save_button: gtk::Button,
+	on_insert: InsertHandler,
+}
+impl BandEditor {
+	pub fn new(builder: &gtk::Builder) -> BandEditor {
+		let container: gtk::Box = builder
+			.get_object("band_editor")
+			.expect("Could not get box band_editor.");
+
+		let selector = gtk::ComboBoxText::new();
+		let new_button = gtk::Button::with_label("New Bands");
+		let delete_button = gtk::Button::with_label("Delete");
+		let tables_box = gtk::Box::new(Orientation::Horizontal, 5);
+		tables_box.pack_start(&selector, true, true, 0);
+		tables_box.pack_start(&new_button, false, true, 0);
+		tables_box.pack_start(&delete_button, false, true, 0);
+
+		let name = gtk::Entry::new();
+		name.set_placeholder_text(Some("Bands name"));
+		let insert_button = gtk::Button::with_label("Insert");
+		insert_button.set_tooltip_text(Some("Gives the roll these bands, `|bands:name`"));
+		let name_box = gtk::Box::new(Orientation::Horizontal, 5);
+		name_box.pack_start(&name, true, true, 0);
+		name_box.pack_start(&insert_button, false, true, 0);
+
+		let grid = gtk::Grid::new();
+		grid.set_row_spacing(5);
+		grid.set_column_spacing(5);
+
+		let status = gtk::Label::new(None);
+		status.set_halign(Align::Start);
+		status.set_line_wrap(true);
+
+		let add_row = gtk::Button::with_label("Add Band");
+		let save_button = gtk::Button::with_label("Save");
+		let actions = gtk::Box::new(Orientation::Horizontal, 5);
+		actions.pack_start(&add_row, false, true, 0);
+		actions.pack_end(&save_button, false, true, 0);
+
+		container.pack_start(&tables_box, false, true, 0);
+		container.pack_start(&name_box, false, true, 0);
+		container.pack_start(&grid, false, true, 0);
+		container.pack_start(&status, false, true, 0);
+		container.pack_start(&actions, false, true, 0);
+
+		let editor = BandEditor {
+			selector,
+			name,
+			grid,
+			rows: Rc::new(RefCell::new(Vec::new())),
+			next_row: Rc::new(Cell::new(0)),
+			status,
+			tables: Rc::new(RefCell::new(Vec::new())),
+			selected: Rc::new(Cell::new(None)),
+			updating: Rc::new(Cell::new(false)),
+			save_button,
+			on_insert: Rc::new(RefCell::new(None)),
+		};
+
+		{
+			let editor_clone = editor.clone();
+			editor.selector.connect_changed(move |selector| {
+				let editor = &editor_clone;
+				if editor.updating.get() {
+					return;
+				}
+				editor.read_rows();
+				editor.select(selector.get_active().map(|index| index as usize));
+			});
+		}
+		{
+			let editor = editor.clone();
+			new_button.connect_clicked(move |_| {
+				editor.add_table(String::new(), BandTable::default());
+				editor.name.grab_focus();
+			});
+		}
+		{
+			let editor = editor.clone();
+			delete_button.connect_clicked(move |_| {
+				if let Some(index) = editor.selected.get() {
+					editor.tables.borrow_mut().remove(index);
+					let count = editor.tables.borrow().len();
+					editor.select(match count {
+						0 => None,
+						_ => Some(index.min(count - 1)),
+					});
+				}
+			});
+		}
+		{
+			let editor = editor.clone();
+			add_row.connect_clicked(move |_| {
+				if editor.selected.get().is_none() {
+					editor.add_table(String::new(), BandTable::default());
+				}
+				editor.add_row("", "", None);
+				editor.grid.show_all();
+			});
+		}
+		{
+			let editor = editor.clone();
+			insert_button.connect_clicked(move |_| {
+				let name = editor.name.get_text().trim().to_owned();
+				if name.is_empty() {
+					return;
+				}
+				if let Some(handler) = editor.on_insert.borrow().as_ref() {
+					handler(&format!("[] |bands:{}", name));
+				}
+			});
+		}
+
+		editor
+	}
+
+	pub fn show(&self, tables: &BandTables) {
+		let mut tables: Vec<(String, BandTable)> = tables
+			.iter()
+			.map(|(name, table)| (name.clone(), table.clone()))
+			.collect();
+		tables.sort_by(|a, b| a.0.cmp(&b.0));
+		let first = if tables.is_empty() { None } else { Some(0) };
+		*self.tables.borrow_mut() = tables;
+		self.select(first);
+	}
+	// The band tables as they are shown, tables without a name are left out.
+	pub fn tables(&self) -> BandTables {
+		self.read_rows();
+		self.tables
+			.borrow()
+			.iter()
+			.filter(|(name, _)| !name.is_empty())
+			.cloned()
+			.collect()
+	}
+
+	// Called with `[] |bands:name` when the insert button is clicked.
+	pub fn connect_insert<F: Fn(&str) + 'static>(&self, f: F) {
+		*self.on_insert.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+	pub fn save_button(&self) -> &gtk::Button {
+		&self.save_button
+	}
+
+	fn add_table(&self, name: String, table: BandTable) {
+		self.read_rows();
+		self.tables.borrow_mut().push((name, table));
+		let index = self.tables.borrow().len() - 1;
+		self.select(Some(index));
+	}
+	// Writes the shown name and bands back to the selected table. Bands
+	// without a label or with a range that can not be read are left out.
+	fn read_rows(&self) {
+		let index = match self.selected.get() {
+			Some(index) => index,
+			None => return,
+		};
+		let rows = self.rows.borrow();
+		let bands: Vec<Band> = rows
+			.iter()
+			.filter_map(|row| {
+				let (low, high) = parse_range(&row.range.get_text())?;
+				Some(Band {
+					low,
+					high,
+					label: row.label.get_text().trim().to_owned(),
+					colour: row.colour(),
+				})
+			})
+			.filter(|band| !band.label.is_empty())
+			.collect();
+		if bands.len() < rows.len() {
+			self.status
+				.set_text("Bands need a label and a range like 7-9, 10+ or 6-");
+		}
+		if let Some(table) = self.tables.borrow_mut().get_mut(index) {
+			*table = (self.name.get_text().trim().to_owned(), BandTable { bands });
+		}
+	}
+	fn select(&self, index: Option<usize>) {
+		self.selected.set(index);
+		self.status.set_text("");
+
+		self.updating.set(true);
+		self.selector.remove_all();
+		for (name, _) in self.tables.borrow().iter() {
+			self.selector
+				.append_text(if name.is_empty() { "(unnamed)" } else { name });
+		}
+		self.selector.set_active(index.map(|index| index as u32));
+		self.updating.set(false);
+
+		for row in self.rows.borrow_mut().drain(..) {
+			for widget in row.widgets {
+				unsafe {
+					widget.destroy();
+				}
+			}
+		}
+		self.next_row.set(0);
+		let tables = self.tables.borrow();
+		match index.and_then(|index| tables.get(index)) {
+			Some((name, table)) => {
+				self.name.set_text(name);
+				for band in table.bands.iter() {
+					self.add_row(&band.range_text(), &band.label, band.colour.as_deref());
+				}
+			}
+			None => self.name.set_text(""),
+		}
+		self.grid.show_all();
+	}
+
+	fn add_row(&self, range_text: &str, label_text: &str, colour_text: Option<&str>) {
+		// Removed rows leave a gap in the grid, it collapses empty rows
+		let row = self.next_row.get();
+		self.next_row.set(row + 1);
+
+		let range = gtk::Entry::new();
+		range.set_width_chars(5);
+		range.set_placeholder_text(Some("7-9"));
+		range.set_tooltip_text(Some("The totals of the band, 7-9, 10+ or 6-"));
+		range.set_text(range_text);
+		let label = gtk::Entry::new();
+		label.set_width_chars(16);
+		label.set_placeholder_text(Some("Weak hit"));
+		label.set_text(label_text);
+		let colour = gtk::ComboBoxText::with_entry();
+		for &(hex, name) in COLOURS.iter() {
+			colour.append(Some(hex), name);
+		}
+		if let Some(colour_text) = colour_text {
+			if !colour.set_active_id(Some(colour_text)) {
+				if let Some(entry) = colour
+					.get_child()
+					.and_then(|child| child.downcast::<gtk::Entry>().ok())
+				{
+					entry.set_text(colour_text);
+				}
+			}
+		}
+		let remove = gtk::Button::with_label("Remove");
+
+		self.grid.attach(&range, 0, row, 1, 1);
+		self.grid.attach(&label, 1, row, 1, 1);
+		self.grid.attach(&colour, 2, row, 1, 1);
+		self.grid.attach(&remove, 3, row, 1, 1);
+
+		{
+			let label = label.clone();
+			let rows = Rc::clone(&self.rows);
+			remove.connect_clicked(move |_| {
+				let mut rows = rows.borrow_mut();
+				if let Some(index) = rows.iter().position(|row| row.label == label) {
+					for widget in rows.remove(index).widgets {
+						unsafe {
+							widget.destroy();
+						}
+					}
+				}
+			});
+		}
+
+		let widgets = vec![
+			range.clone().upcast::<gtk::Widget>(),
+			label.clone().upcast(),
+			colour.clone().upcast(),
+			remove.upcast(),
+		];
+		self.rows.borrow_mut().push(Row {
+			range,
+			label,
+			colour,
+			widgets,
+		});
+	}
+}
@@ -0,0 +1,152 @@
+// File: src/bands.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::collections::HashMap;
+use std::fmt;
+
+use toml::value::Table;
+use toml::Value;
+
+use super::storage;
+
+// A range of totals and what they mean, `7-9: Weak hit`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Band {
+	// No bound is open ended, `6-` and `10+`
+	pub low: Option<i32>,
+	pub high: Option<i32>,
+	pub label: String,
+	// The label is shown in it, `#2e7d32`
+	pub colour: Option<String>,
+}
+impl Band {
+	// Reads `7-9: Weak hit`, `6-: Miss`, `10+: Strong hit` and `6: Critical`.
+	pub fn parse(text: &str) -> Option<Band> {
+		let colon = text.find(':')?;
+		let (low, high) = parse_range(&text[..colon])?;
+		Some(Band {
+			low,
+			high,
+			label: text[(colon + 1)..].trim().to_owned(),
+			colour: None,
+		})
+	}
+	pub fn contains(&self, total: f32) -> bool {
+		self.low.is_none_or(|low| total >= low as f32)
+			&& self.high.is_none_or(|high| total <= high as f32)
+	}
+	// `7-9`, `6-`, `10+` or `6`.
+	pub fn range_text(&self) -> String {
+		match (self.low, self.high) {
+			(Some(low), Some(high)) if low == high => low.to_string(),
+			(Some(low), Some(high)) => format!("{}-{}", low, high),
+			(Some(low), None) => format!("{}+", low),
+			(None, Some(high)) => format!("{}-", high),
+			(None, None) => String::new(),
+		}
+	}
+
+	pub fn to_value(&self) -> Value {
+		let mut table = Table::new();
+		table.insert(String::from("range"), Value::String(self.range_text()));
+		table.insert(String::from("label"), Value::String(self.label.clone()));
+		if let Some(colour) = &self.colour {
+			table.insert(String::from("colour"), Value::String(colour.clone()));
+		}
+		Value::Table(table)
+	}
+	// A band is a line, `7-9: Weak hit`, or a table with a range, a label
+	// and an optional colour.
+	pub fn from_value(value: &Value) -> Option<Band> {
+		if let Some(line) = value.as_str() {
+			return Band::parse(line);
+		}
+		let table = value.as_table()?;
+		let (low, high) = parse_range(&storage::get_str(table, "range"))?;
+		Some(Band {
+			low,
+			high,
+			label: storage::get_str(table, "label"),
+			colour: table
+				.get("colour")
+				.and_then(Value::as_str)
+				.map(str::to_owned),
+		})
+	}
+}
+// Written the way it is parsed.
+impl fmt::Display for Band {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.range_text(), self.label)
+	}
+}
+
+// Reads the bounds of `7-9`, `6-`, `10+` and `6`.
+pub fn parse_range(range: &str) -> Option<(Option<i32>, Option<i32>)> {
+	let range = range.trim();
+	if let Some(low) = range.strip_suffix('+') {
+		return Some((Some(low.trim().parse().ok()?), None));
+	}
+	if let Some(high) = range.strip_suffix('-') {
+		return Some((None, Some(high.trim().parse().ok()?)));
+	}
+	// The dash is searched for after the first character so a negative low
+	// bound is not taken for a range, `-1-1`
+	match range.char_indices().skip(1).find(|&(_, c)| c == '-') {
+		Some((dash, _)) => Some((
+			Some(range[..dash].trim().parse().ok()?),
+			Some(range[(dash + 1)..].trim().parse().ok()?),
+		)),
+		None => {
+			let value = range.parse().ok()?;
+			Some((Some(value), Some(value)))
+		}
+	}
+}
+
+// What the totals of a roll mean, used with `[[2d6+2]]|bands:pbta`.
+#[derive(Debug, Clone, Default)]
+pub struct BandTable {
+	pub bands: Vec<Band>,
+}
+impl BandTable {
+	// The first band holding the total.
+	pub fn band(&self, total: f32) -> Option<&Band> {
+		self.bands.iter().find(|band| band.contains(total))
+	}
+
+	pub fn to_value(&self) -> Value {
+		Value::Array(self.bands.iter().map(Band::to_value).collect())
+	}
+	// Bands that can not be read are left out.
+	pub fn from_value(value: &Value) -> BandTable {
+		let bands = value
+			.as_array()
+			.map(|bands| bands.iter().filter_map(Band::from_value).collect())
+			.unwrap_or_default();
+		BandTable { bands }
+	}
+}
+
+pub type BandTables = HashMap<String, BandTable>;
+
+pub fn tables_to_value(tables: &BandTables) -> Value {
+	Value::Table(
+		tables
+			.iter()
+			.map(|(name, table)| (name.clone(), table.to_value()))
+			.collect(),
+	)
+}
+pub fn tables_from_value(value: &Value) -> BandTables {
+	value
+		.as_table()
+		.map(|tables| {
+			tables
+				.iter()
+				.map(|(name, bands)| (name.clone(), BandTable::from_value(bands)))
+				.collect()
+		})
+		.unwrap_or_default()
+}
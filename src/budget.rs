@@ -0,0 +1,164 @@
+// File: src/budget.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone)]
+pub struct EvaluationBudget {
+	pub max_dice: u32,
+	pub max_sides: u32,
+	pub max_explosions: u32,
+	pub max_macro_nesting: u32,
+	// Every repeated roll is its own line of the result
+	pub max_repeat: u32,
+	pub max_time: Duration,
+}
+impl Default for EvaluationBudget {
+	fn default() -> Self {
+		EvaluationBudget {
+			max_dice: 10_000,
+			max_sides: 10_000,
+			max_explosions: 1_000,
+			max_macro_nesting: 16,
+			max_repeat: 100,
+			max_time: Duration::from_millis(5_000),
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum BudgetError {
+	TooManyDice(u32),
+	TooManyExplosions(u32),
+	TookTooLong(Duration),
+	Cancelled,
+}
+impl fmt::Display for BudgetError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			BudgetError::TooManyDice(max) => {
+				write!(f, "Roll aborted: more than {} dice rolled", max)
+			}
+			BudgetError::TooManyExplosions(max) => {
+				write!(
+					f,
+					"Roll aborted: more than {} rerolls and explosions of one die",
+					max
+				)
+			}
+			BudgetError::TookTooLong(max) => {
+				write!(f, "Roll aborted: took longer than {} ms", max.as_millis())
+			}
+			BudgetError::Cancelled => write!(f, "Roll cancelled"),
+		}
+	}
+}
+
+struct Guard {
+	budget: EvaluationBudget,
+	rolled: u32,
+	started: Instant,
+	cancelled: Arc<AtomicBool>,
+}
+impl Guard {
+	fn check(&mut self) -> Result<(), BudgetError> {
+		self.rolled += 1;
+		self.check_running()?;
+		if self.rolled > self.budget.max_dice {
+			return Err(BudgetError::TooManyDice(self.budget.max_dice));
+		}
+		Ok(())
+	}
+	fn check_running(&self) -> Result<(), BudgetError> {
+		if self.cancelled.load(Ordering::Relaxed) {
+			return Err(BudgetError::Cancelled);
+		}
+		if self.started.elapsed() > self.budget.max_time {
+			return Err(BudgetError::TookTooLong(self.budget.max_time));
+		}
+		Ok(())
+	}
+}
+
+thread_local! {
+	static GUARD: RefCell<Option<Guard>> = const { RefCell::new(None) };
+}
+
+// The interpreter only takes a plain function for its random numbers, so
+// this is where the budget is enforced. Once it runs out the interpreter is
+// unwound back to `run_within`.
+pub fn budgeted_rand() -> f64 {
+	let checked = GUARD.with(|guard| match guard.borrow_mut().as_mut() {
+		Some(guard) => guard.check(),
+		None => Ok(()),
+	});
+	stop_on(checked);
+	roll_lang::default_rand()
+}
+// Rolls that roll few dice, like a long repeat or macros calling macros,
+// still have to stop when cancelled or out of time. The evaluator calls
+// this as it walks the roll.
+pub fn checkpoint() {
+	let checked = GUARD.with(|guard| match guard.borrow().as_ref() {
+		Some(guard) => guard.check_running(),
+		None => Ok(()),
+	});
+	stop_on(checked);
+}
+// `chain` is the number of rerolls and explosions of one die so far. The
+// evaluator rolls every die that can reroll or explode and calls this for
+// each of them.
+pub fn check_explosions(chain: u32) {
+	let checked = GUARD.with(|guard| match guard.borrow().as_ref() {
+		Some(guard) if chain > guard.budget.max_explosions => {
+			Err(BudgetError::TooManyExplosions(guard.budget.max_explosions))
+		}
+		_ => Ok(()),
+	});
+	stop_on(checked);
+}
+// The budget of the roll being run on this thread. Limits that are only
+// known while rolling, like the sides of computed dice, are checked
+// against it by the evaluator.
+pub fn current_budget() -> EvaluationBudget {
+	GUARD.with(|guard| match guard.borrow().as_ref() {
+		Some(guard) => guard.budget,
+		None => EvaluationBudget::default(),
+	})
+}
+fn stop_on(checked: Result<(), BudgetError>) {
+	if let Err(error) = checked {
+		panic::resume_unwind(Box::new(error));
+	}
+}
+
+// Runs `f` on this thread with every dice roll counted against the budget.
+pub fn run_within<T>(
+	budget: EvaluationBudget,
+	cancelled: Arc<AtomicBool>,
+	f: impl FnOnce() -> T,
+) -> Result<T, BudgetError> {
+	GUARD.with(|guard| {
+		*guard.borrow_mut() = Some(Guard {
+			budget,
+			rolled: 0,
+			started: Instant::now(),
+			cancelled,
+		})
+	});
+	let result = panic::catch_unwind(AssertUnwindSafe(f));
+	GUARD.with(|guard| guard.borrow_mut().take());
+	match result {
+		Ok(value) => Ok(value),
+		Err(payload) => match payload.downcast::<BudgetError>() {
+			Ok(error) => Err(*error),
+			Err(payload) => panic::resume_unwind(payload),
+		},
+	}
+}
@@ -0,0 +1,83 @@
+// File: src/character.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use toml::value::Table;
+use toml::Value;
+
+use super::roll::Attributes;
+use super::storage;
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+	pub name: String,
+	pub value: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Character {
+	pub name: String,
+	pub attributes: Vec<Attribute>,
+}
+impl Default for Character {
+	fn default() -> Self {
+		Character {
+			name: String::from("New character"),
+			attributes: Vec::new(),
+		}
+	}
+}
+
+impl Character {
+	// By name, the first attribute with a name is the one used.
+	pub fn attribute_values(&self) -> Attributes {
+		let mut values = Attributes::new();
+		for attribute in self.attributes.iter() {
+			values
+				.entry(attribute.name.clone())
+				.or_insert_with(|| attribute.value.clone());
+		}
+		values
+	}
+
+	pub fn to_table(&self) -> Table {
+		let attributes = self
+			.attributes
+			.iter()
+			.map(|attribute| {
+				let mut table = Table::new();
+				table.insert(String::from("name"), Value::String(attribute.name.clone()));
+				table.insert(
+					String::from("value"),
+					Value::String(attribute.value.clone()),
+				);
+				Value::Table(table)
+			})
+			.collect();
+
+		let mut table = Table::new();
+		table.insert(String::from("name"), Value::String(self.name.clone()));
+		table.insert(String::from("attributes"), Value::Array(attributes));
+		table
+	}
+	pub fn from_table(table: &Table) -> Character {
+		let attributes = table
+			.get("attributes")
+			.and_then(Value::as_array)
+			.map(|attributes| {
+				attributes
+					.iter()
+					.filter_map(Value::as_table)
+					.map(|attribute| Attribute {
+						name: storage::get_str(attribute, "name"),
+						value: storage::get_str(attribute, "value"),
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		Character {
+			name: storage::get_str(table, "name"),
+			attributes,
+		}
+	}
+}
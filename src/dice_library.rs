@@ -0,0 +1,247 @@
+// File: src/dice_library.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::fmt;
+use std::path::PathBuf;
+
+use toml::value::Table;
+use toml::Value;
+
+use super::storage;
+
+// A symbol on the faces of custom dice, `success` of Genesys' dice.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+	pub name: String,
+	// Shown in place of the name in the rolled faces, `✹`
+	pub icon: String,
+	pub colour: Option<String>,
+	// One of each cancel out, success cancels failure
+	pub cancels: Option<String>,
+}
+impl Symbol {
+	fn from_value(name: &str, value: &Value) -> Symbol {
+		let get = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_owned);
+		Symbol {
+			name: name.to_owned(),
+			icon: get("icon").unwrap_or_else(|| name.to_owned()),
+			colour: get("colour"),
+			cancels: get("cancels"),
+		}
+	}
+}
+
+// The faces of a custom die, numbers like `[2, 3, 3, 4, 4, 5]` or symbols.
+// Every symbol face holds any number of symbols, a blank face none.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Faces {
+	Numbers(Vec<i32>),
+	Symbols(Vec<Vec<String>>),
+}
+impl Faces {
+	pub fn len(&self) -> usize {
+		match self {
+			Faces::Numbers(faces) => faces.len(),
+			Faces::Symbols(faces) => faces.len(),
+		}
+	}
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+// A die rolled with `3d{ability}` or `2d{avg}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomDie {
+	pub name: String,
+	pub faces: Faces,
+}
+impl CustomDie {
+	// A die with only whole numbers for faces has number faces. Otherwise a
+	// face is a list of symbols or one symbol, `["success", "advantage"]`.
+	fn from_value(name: &str, value: &Value) -> CustomDie {
+		let values = value.as_array().cloned().unwrap_or_default();
+		let numbers: Vec<i32> = values
+			.iter()
+			.filter_map(|face| face.as_integer().map(|face| face as i32))
+			.collect();
+		if !values.is_empty() && numbers.len() == values.len() {
+			return CustomDie {
+				name: name.to_owned(),
+				faces: Faces::Numbers(numbers),
+			};
+		}
+		let face = |value: &Value| -> Vec<String> {
+			match value {
+				Value::String(symbol) => vec![symbol.clone()],
+				Value::Array(symbols) => symbols
+					.iter()
+					.filter_map(Value::as_str)
+					.map(str::to_owned)
+					.collect(),
+				_ => Vec::new(),
+			}
+		};
+		CustomDie {
+			name: name.to_owned(),
+			faces: Faces::Symbols(values.iter().map(face).collect()),
+		}
+	}
+}
+
+// The symbols and dice the presets and the dice library file define.
+#[derive(Debug, Clone, Default)]
+pub struct DiceLibrary {
+	pub symbols: Vec<Symbol>,
+	pub dice: Vec<CustomDie>,
+}
+impl DiceLibrary {
+	// Reads the `symbols` and `custom_dice` tables.
+	pub fn from_table(table: &Table) -> DiceLibrary {
+		let named = |key: &str| -> Vec<(String, Value)> {
+			table
+				.get(key)
+				.and_then(Value::as_table)
+				.map(|named| {
+					named
+						.iter()
+						.map(|(name, value)| (name.clone(), value.clone()))
+						.collect()
+				})
+				.unwrap_or_default()
+		};
+		DiceLibrary {
+			symbols: named("symbols")
+				.iter()
+				.map(|(name, value)| Symbol::from_value(name, value))
+				.collect(),
+			dice: named("custom_dice")
+				.iter()
+				.map(|(name, value)| CustomDie::from_value(name, value))
+				.collect(),
+		}
+	}
+	// Adds the symbols and dice of another library, replacing ones with the
+	// same name.
+	pub fn add(&mut self, library: &DiceLibrary) {
+		for symbol in library.symbols.iter() {
+			self.symbols.retain(|other| other.name != symbol.name);
+			self.symbols.push(symbol.clone());
+		}
+		for die in library.dice.iter() {
+			self.dice.retain(|other| other.name != die.name);
+			self.dice.push(die.clone());
+		}
+	}
+	pub fn die(&self, name: &str) -> Option<&CustomDie> {
+		self.dice.iter().find(|die| die.name == name)
+	}
+	// A symbol that is not in the library is shown by its name.
+	pub fn symbol(&self, name: &str) -> Symbol {
+		self.symbols
+			.iter()
+			.find(|symbol| symbol.name == name)
+			.cloned()
+			.unwrap_or_else(|| Symbol {
+				name: name.to_owned(),
+				icon: name.to_owned(),
+				colour: None,
+				cancels: None,
+			})
+	}
+	// The icons of the symbols on a face, a dash for a blank face.
+	pub fn face_text(&self, face: &[String]) -> String {
+		match face.is_empty() {
+			true => String::from("–"),
+			false => face.iter().map(|name| self.symbol(name).icon).collect(),
+		}
+	}
+}
+
+// How many of each symbol a roll came up with, in the order they came up.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolCount {
+	pub counts: Vec<(Symbol, u32)>,
+}
+impl SymbolCount {
+	pub fn add(&mut self, symbol: &Symbol, count: u32) {
+		match self
+			.counts
+			.iter_mut()
+			.find(|(other, _)| other.name == symbol.name)
+		{
+			Some((_, total)) => *total += count,
+			None => self.counts.push((symbol.clone(), count)),
+		}
+	}
+	pub fn count(&self, symbol: &str) -> u32 {
+		self.counts
+			.iter()
+			.find(|(other, _)| other.name == symbol)
+			.map_or(0, |(_, count)| *count)
+	}
+	// Takes away one of each symbol and the symbol it cancels until one of
+	// them is gone. Symbols that are gone are left out and the rest are put
+	// in the order of the library, by name within the file they are from.
+	pub fn cancel(&mut self, library: &DiceLibrary) {
+		for symbol in library.symbols.iter() {
+			let other = match &symbol.cancels {
+				Some(other) => other,
+				None => continue,
+			};
+			let cancelled = self.count(&symbol.name).min(self.count(other));
+			for (counted, count) in self.counts.iter_mut() {
+				if counted.name == symbol.name || &counted.name == other {
+					*count -= cancelled;
+				}
+			}
+		}
+		self.counts.retain(|(_, count)| *count > 0);
+		self.counts.sort_by_key(|(counted, _)| {
+			library
+				.symbols
+				.iter()
+				.position(|symbol| symbol.name == counted.name)
+				.unwrap_or(library.symbols.len())
+		});
+	}
+}
+// `✹ 2 success, ⬢ 1 advantage`, or that nothing is left.
+impl fmt::Display for SymbolCount {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.counts.is_empty() {
+			return write!(f, "no symbols");
+		}
+		let counts: Vec<String> = self
+			.counts
+			.iter()
+			.map(|(symbol, count)| format!("{} {} {}", symbol.icon, count, symbol.name))
+			.collect();
+		write!(f, "{}", counts.join(", "))
+	}
+}
+
+static BUILTIN: &str = include_str!("../data/dice_library.toml");
+
+fn path() -> PathBuf {
+	storage::data_dir().join("dice_library.toml")
+}
+
+// The built in dice followed by the ones defined by the user, which
+// replace built in dice with the same name.
+pub fn load() -> Vec<DiceLibrary> {
+	let mut libraries = Vec::new();
+	if let Ok(Value::Table(table)) = BUILTIN.parse::<Value>() {
+		libraries.push(DiceLibrary::from_table(&table));
+	}
+	let path = path();
+	if !path.exists() {
+		return libraries;
+	}
+	match storage::load_table(&path) {
+		Ok(table) => libraries.push(DiceLibrary::from_table(&table)),
+		Err(e) => eprintln!("Could not load dice library {}: {}", path.display(), e),
+	}
+	libraries
+}
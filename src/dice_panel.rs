@@ -0,0 +1,182 @@
+// File: src/dice_panel.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use toml::Value;
+
+use super::profile::DicePreset;
+use super::storage;
+
+static LAYOUT: &str = include_str!("../data/dice_panel.toml");
+
+type InsertHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn(&str)>>>>;
+
+struct DiceRow {
+	die: String,
+	count: gtk::Entry,
+}
+
+// The rows of dice with how many of each to insert and the rows of
+// operator and function buttons, read from dice_panel.toml. The dice of the
+// game system replace its dice.
+#[derive(Clone)]
+pub struct DicePanel {
+	grid: gtk::Grid,
+	rows: Rc<RefCell<Vec<DiceRow>>>,
+	// The counts of every die shown since the profile was, so a die keeps
+	// its count when another system is picked and back
+	counts: Rc<RefCell<DicePreset>>,
+	dice: Vec<String>,
+	on_insert: InsertHandler,
+}
+impl DicePanel {
+	pub fn new(builder: &gtk::Builder) -> DicePanel {
+		let grid: gtk::Grid = builder
+			.get_object("dice_grid")
+			.expect("Could not get grid dice_grid.");
+		let insert_rows: gtk::Box = builder
+			.get_object("insert_rows")
+			.expect("Could not get box insert_rows.");
+
+		let layout = match LAYOUT.parse::<Value>() {
+			Ok(Value::Table(table)) => table,
+			_ => panic!("Could not read dice_panel.toml."),
+		};
+		let strings = |value: &Value| -> Vec<String> {
+			value
+				.as_array()
+				.map(|values| {
+					values
+						.iter()
+						.filter_map(Value::as_str)
+						.map(str::to_owned)
+						.collect()
+				})
+				.unwrap_or_default()
+		};
+
+		let panel = DicePanel {
+			grid,
+			rows: Rc::new(RefCell::new(Vec::new())),
+			counts: Rc::new(RefCell::new(DicePreset::new())),
+			dice: layout.get("dice").map(strings).unwrap_or_default(),
+			on_insert: Rc::new(RefCell::new(None)),
+		};
+
+		let rows = layout
+			.get("rows")
+			.and_then(Value::as_array)
+			.cloned()
+			.unwrap_or_default();
+		for row in rows.iter().filter_map(Value::as_table) {
+			let buttons = gtk::Box::new(Orientation::Horizontal, 5);
+			buttons.set_border_width(5);
+			let label = storage::get_str(row, "label");
+			if !label.is_empty() {
+				buttons.pack_start(&gtk::Label::new(Some(&label)), false, true, 0);
+			}
+			for insert in row.get("buttons").map(strings).unwrap_or_default() {
+				let button = gtk::Button::with_label(&insert);
+				let on_insert = Rc::clone(&panel.on_insert);
+				button.connect_clicked(move |_| {
+					if let Some(handler) = on_insert.borrow().as_ref() {
+						handler(&insert);
+					}
+				});
+				buttons.pack_start(&button, false, true, 0);
+			}
+			insert_rows.pack_start(&buttons, false, true, 0);
+		}
+		panel.show_dice(&[]);
+		panel
+	}
+
+	// Called with what to insert into the roll entry, `2d6` or `[] + []`.
+	pub fn connect_insert<F: Fn(&str) + 'static>(&self, f: F) {
+		*self.on_insert.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+
+	// Shows a row for each die, those of dice_panel.toml when there are none.
+	pub fn show_dice(&self, dice: &[String]) {
+		let dice = match dice.is_empty() {
+			true => &self.dice,
+			false => dice,
+		};
+		self.keep_counts();
+		let mut rows = self.rows.borrow_mut();
+		for _ in rows.drain(..) {
+			self.grid.remove_row(1);
+		}
+		// The rows go between the modifier and the die of any sides
+		for (i, die) in dice.iter().enumerate() {
+			let top = i as i32 + 1;
+			self.grid.insert_row(top);
+
+			let count = gtk::Entry::new();
+			count.set_max_length(5);
+			count.set_width_chars(5);
+			count.set_input_purpose(InputPurpose::Number);
+			let counts = self.counts.borrow();
+			count.set_text(counts.get(&count_name(die)).map_or("1", String::as_str));
+
+			let button = gtk::Button::with_label("Insert Into Roll");
+			{
+				let die = die.clone();
+				let count = count.clone();
+				let on_insert = Rc::clone(&self.on_insert);
+				button.connect_clicked(move |_| {
+					let count = count.get_text().to_string();
+					if count.parse::<u32>().is_err() && count.parse::<f32>().is_err() {
+						return;
+					}
+					if let Some(handler) = on_insert.borrow().as_ref() {
+						handler(&format!("{}{}", count, die));
+					}
+				});
+			}
+
+			self.grid.attach(&count, 0, top, 1, 1);
+			self.grid.attach(&gtk::Label::new(Some(die)), 1, top, 1, 1);
+			self.grid.attach(&button, 3, top, 1, 1);
+			rows.push(DiceRow {
+				die: die.clone(),
+				count,
+			});
+		}
+		self.grid.show_all();
+	}
+
+	// The counts of the dice, `d6_count`, along with those not shown.
+	pub fn counts(&self) -> DicePreset {
+		self.keep_counts();
+		self.counts.borrow().clone()
+	}
+	// Counts missing from the preset are left as they are.
+	pub fn set_counts(&self, preset: &DicePreset) {
+		let mut counts = self.counts.borrow_mut();
+		for (name, value) in preset.iter() {
+			counts.insert(name.clone(), value.clone());
+		}
+		for row in self.rows.borrow().iter() {
+			if let Some(value) = counts.get(&count_name(&row.die)) {
+				row.count.set_text(value);
+			}
+		}
+	}
+	fn keep_counts(&self) {
+		let mut counts = self.counts.borrow_mut();
+		for row in self.rows.borrow().iter() {
+			counts.insert(count_name(&row.die), row.count.get_text().to_string());
+		}
+	}
+}
+
+fn count_name(die: &str) -> String {
+	format!("{}_count", die)
+}
@@ -0,0 +1,519 @@
+// File: src/encounter.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use toml::value::Table;
+use toml::Value;
+
+use super::budget::EvaluationBudget;
+use super::evaluator::RollMode;
+use super::roll::{totals, QueryAnswers, RollContext};
+use super::roll_worker::evaluate;
+use super::state::{Command, State};
+use super::storage;
+
+// How combatants with the same initiative are ordered.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TieBreaker {
+	RollOff,
+	Alphabetical,
+	ListOrder,
+}
+impl TieBreaker {
+	pub const ALL: [TieBreaker; 3] = [
+		TieBreaker::RollOff,
+		TieBreaker::Alphabetical,
+		TieBreaker::ListOrder,
+	];
+	pub fn key(self) -> &'static str {
+		match self {
+			TieBreaker::RollOff => "roll_off",
+			TieBreaker::Alphabetical => "alphabetical",
+			TieBreaker::ListOrder => "list_order",
+		}
+	}
+	pub fn from_key(key: &str) -> Option<TieBreaker> {
+		TieBreaker::ALL.iter().copied().find(|t| t.key() == key)
+	}
+}
+impl fmt::Display for TieBreaker {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			TieBreaker::RollOff => write!(f, "Roll off"),
+			TieBreaker::Alphabetical => write!(f, "Alphabetical"),
+			TieBreaker::ListOrder => write!(f, "List order"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Combatant {
+	pub name: String,
+	// A roll_lang expression such as `1d20+3`
+	pub initiative: String,
+	pub result: Option<f32>,
+	pub hp: i32,
+	// Zero when there is no maximum
+	pub max_hp: i32,
+	pub temp_hp: i32,
+	pub conditions: Vec<String>,
+	// Whether damage and healing are applied to it, not saved
+	pub selected: bool,
+}
+impl Default for Combatant {
+	fn default() -> Self {
+		Combatant {
+			name: String::new(),
+			initiative: String::from("1d20"),
+			result: None,
+			hp: 0,
+			max_hp: 0,
+			temp_hp: 0,
+			conditions: Vec::new(),
+			selected: false,
+		}
+	}
+}
+impl Combatant {
+	// Temporary hit points are lost first. Hit points do not go below zero.
+	// Amounts past the range of hit points stop at its ends.
+	pub fn take_damage(&mut self, amount: i32) {
+		let absorbed = amount.min(self.temp_hp).max(0);
+		self.temp_hp = self.temp_hp.saturating_sub(absorbed);
+		self.hp = self
+			.hp
+			.saturating_sub(amount.saturating_sub(absorbed))
+			.max(0);
+	}
+	pub fn heal(&mut self, amount: i32) {
+		self.hp = self.hp.saturating_add(amount);
+		if self.max_hp > 0 {
+			self.hp = self.hp.min(self.max_hp);
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encounter {
+	pub combatants: Vec<Combatant>,
+	pub turn: usize,
+	// Zero until initiative has been rolled
+	pub round: u32,
+	pub tie_breaker: TieBreaker,
+}
+impl Default for Encounter {
+	fn default() -> Self {
+		Encounter {
+			combatants: Vec::new(),
+			turn: 0,
+			round: 0,
+			tie_breaker: TieBreaker::RollOff,
+		}
+	}
+}
+impl Encounter {
+	// Rolls every combatant's initiative and sorts them, highest first.
+	// Attributes in the expressions are those of the context. Nothing is
+	// changed if any of the expressions can not be rolled. Run on a roll
+	// worker, `cancelled` stops it.
+	pub fn rolled(
+		&self,
+		context: &RollContext,
+		budget: EvaluationBudget,
+		cancelled: Arc<AtomicBool>,
+	) -> Result<Encounter, String> {
+		let mut rolled: Vec<(Combatant, f32)> = Vec::with_capacity(self.combatants.len());
+		for combatant in self.combatants.iter() {
+			let result = roll_total(&combatant.initiative, context, budget, &cancelled)
+				.map_err(|e| format!("{}: {}", combatant.name, e))?;
+			let tie = match self.tie_breaker {
+				TieBreaker::RollOff => roll_total("1d20", context, budget, &cancelled)?,
+				TieBreaker::Alphabetical | TieBreaker::ListOrder => 0.0,
+			};
+			let combatant = Combatant {
+				result: Some(result),
+				..combatant.clone()
+			};
+			rolled.push((combatant, tie));
+		}
+
+		let tie_breaker = self.tie_breaker;
+		// The sort is stable so list order is kept when nothing else decides
+		rolled.sort_by(|(a, a_tie), (b, b_tie)| {
+			let by_result = b.result.partial_cmp(&a.result).unwrap_or(Ordering::Equal);
+			by_result.then_with(|| match tie_breaker {
+				TieBreaker::RollOff => b_tie.partial_cmp(a_tie).unwrap_or(Ordering::Equal),
+				TieBreaker::Alphabetical => a.name.cmp(&b.name),
+				TieBreaker::ListOrder => Ordering::Equal,
+			})
+		});
+		Ok(Encounter {
+			combatants: rolled.into_iter().map(|(combatant, _)| combatant).collect(),
+			turn: 0,
+			round: 1,
+			tie_breaker,
+		})
+	}
+
+	pub fn to_table(&self) -> Table {
+		let combatants = self
+			.combatants
+			.iter()
+			.map(|combatant| {
+				let mut table = Table::new();
+				table.insert(String::from("name"), Value::String(combatant.name.clone()));
+				table.insert(
+					String::from("initiative"),
+					Value::String(combatant.initiative.clone()),
+				);
+				if let Some(result) = combatant.result {
+					table.insert(String::from("result"), Value::Float(result as f64));
+				}
+				table.insert(String::from("hp"), Value::Integer(combatant.hp as i64));
+				table.insert(
+					String::from("max_hp"),
+					Value::Integer(combatant.max_hp as i64),
+				);
+				table.insert(
+					String::from("temp_hp"),
+					Value::Integer(combatant.temp_hp as i64),
+				);
+				let conditions = combatant
+					.conditions
+					.iter()
+					.map(|condition| Value::String(condition.clone()))
+					.collect();
+				table.insert(String::from("conditions"), Value::Array(conditions));
+				Value::Table(table)
+			})
+			.collect();
+
+		let mut table = Table::new();
+		table.insert(String::from("combatants"), Value::Array(combatants));
+		table.insert(String::from("turn"), Value::Integer(self.turn as i64));
+		table.insert(String::from("round"), Value::Integer(self.round as i64));
+		table.insert(
+			String::from("tie_breaker"),
+			Value::String(self.tie_breaker.key().to_owned()),
+		);
+		table
+	}
+	pub fn from_table(table: &Table) -> Encounter {
+		let combatants: Vec<Combatant> = table
+			.get("combatants")
+			.and_then(Value::as_array)
+			.map(|combatants| {
+				combatants
+					.iter()
+					.filter_map(Value::as_table)
+					.map(|combatant| {
+						let get_int = |key| {
+							combatant.get(key).and_then(Value::as_integer).unwrap_or(0) as i32
+						};
+						Combatant {
+							name: storage::get_str(combatant, "name"),
+							initiative: storage::get_str(combatant, "initiative"),
+							result: combatant
+								.get("result")
+								.and_then(Value::as_float)
+								.map(|result| result as f32),
+							hp: get_int("hp"),
+							max_hp: get_int("max_hp"),
+							temp_hp: get_int("temp_hp"),
+							conditions: combatant
+								.get("conditions")
+								.and_then(Value::as_array)
+								.map(|conditions| {
+									conditions
+										.iter()
+										.filter_map(Value::as_str)
+										.map(str::to_owned)
+										.collect()
+								})
+								.unwrap_or_default(),
+							selected: false,
+						}
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		let get_int = |key| {
+			table
+				.get(key)
+				.and_then(Value::as_integer)
+				.unwrap_or(0)
+				.max(0)
+		};
+		Encounter {
+			turn: (get_int("turn") as usize).min(combatants.len().saturating_sub(1)),
+			round: get_int("round") as u32,
+			tie_breaker: table
+				.get("tie_breaker")
+				.and_then(Value::as_str)
+				.and_then(TieBreaker::from_key)
+				.unwrap_or(TieBreaker::RollOff),
+			combatants,
+		}
+	}
+}
+
+fn roll_total(
+	expression: &str,
+	context: &RollContext,
+	budget: EvaluationBudget,
+	cancelled: &Arc<AtomicBool>,
+) -> Result<f32, String> {
+	let output = evaluate(
+		&format!("[[{}]]", expression),
+		&QueryAnswers::new(),
+		context,
+		budget,
+		RollMode::default(),
+		Arc::clone(cancelled),
+	)?;
+	totals(&output)
+		.first()
+		.copied()
+		.ok_or_else(|| String::from("Initiative is not a roll"))
+}
+
+// Replaces the whole encounter, used for rolling initiative and editing the
+// list of combatants.
+pub struct SetEncounterCommand {
+	encounter: Encounter,
+}
+impl SetEncounterCommand {
+	pub fn new(encounter: Encounter) -> SetEncounterCommand {
+		SetEncounterCommand { encounter }
+	}
+}
+impl Command for SetEncounterCommand {
+	fn execute(&mut self, state: &State) -> State {
+		let mut next = state.clone();
+		next.encounter = self.encounter.clone();
+		next
+	}
+}
+
+pub struct NextTurnCommand {}
+impl Command for NextTurnCommand {
+	fn execute(&mut self, state: &State) -> State {
+		let mut next = state.clone();
+		let encounter = &mut next.encounter;
+		if encounter.combatants.is_empty() {
+			return next;
+		}
+		encounter.turn += 1;
+		if encounter.turn >= encounter.combatants.len() {
+			encounter.turn = 0;
+			encounter.round += 1;
+		}
+		next
+	}
+}
+pub struct PreviousTurnCommand {}
+impl Command for PreviousTurnCommand {
+	fn execute(&mut self, state: &State) -> State {
+		let mut next = state.clone();
+		let encounter = &mut next.encounter;
+		if encounter.combatants.is_empty() {
+			return next;
+		}
+		if encounter.turn > 0 {
+			encounter.turn -= 1;
+		} else if encounter.round > 1 {
+			encounter.turn = encounter.combatants.len() - 1;
+			encounter.round -= 1;
+		}
+		next
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DamageModifier {
+	Normal,
+	// Half, rounded down
+	Resistant,
+	Vulnerable,
+}
+impl DamageModifier {
+	pub fn apply(self, amount: i32) -> i32 {
+		match self {
+			DamageModifier::Normal => amount,
+			DamageModifier::Resistant => amount / 2,
+			DamageModifier::Vulnerable => amount.saturating_mul(2),
+		}
+	}
+}
+
+// Damages or heals the combatants that were selected in the panel, by
+// their index in the encounter.
+pub struct ApplyDamageCommand {
+	amount: i32,
+	modifier: DamageModifier,
+	healing: bool,
+	selected: Vec<usize>,
+}
+impl ApplyDamageCommand {
+	pub fn damage(
+		amount: i32,
+		modifier: DamageModifier,
+		selected: Vec<usize>,
+	) -> ApplyDamageCommand {
+		ApplyDamageCommand {
+			amount,
+			modifier,
+			healing: false,
+			selected,
+		}
+	}
+	pub fn healing(amount: i32, selected: Vec<usize>) -> ApplyDamageCommand {
+		ApplyDamageCommand {
+			amount,
+			modifier: DamageModifier::Normal,
+			healing: true,
+			selected,
+		}
+	}
+}
+impl Command for ApplyDamageCommand {
+	fn execute(&mut self, state: &State) -> State {
+		let mut next = state.clone();
+		let amount = self.modifier.apply(self.amount.max(0));
+		for (i, combatant) in next.encounter.combatants.iter_mut().enumerate() {
+			if !self.selected.contains(&i) {
+				continue;
+			}
+			if self.healing {
+				combatant.heal(amount);
+			} else {
+				combatant.take_damage(amount);
+			}
+		}
+		next
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn combatant(name: &str, initiative: &str) -> Combatant {
+		Combatant {
+			name: name.to_owned(),
+			initiative: initiative.to_owned(),
+			..Combatant::default()
+		}
+	}
+	fn encounter(tie_breaker: TieBreaker) -> Encounter {
+		Encounter {
+			combatants: vec![
+				combatant("Orc", "12"),
+				combatant("Bard", "8+4"),
+				combatant("Cleric", "15"),
+				combatant("Archer", "1d1+11"),
+			],
+			tie_breaker,
+			..Encounter::default()
+		}
+	}
+	fn names(encounter: &Encounter) -> Vec<&str> {
+		encounter
+			.combatants
+			.iter()
+			.map(|combatant| combatant.name.as_str())
+			.collect()
+	}
+	fn rolled(encounter: &Encounter) -> Encounter {
+		let cancelled = Arc::new(AtomicBool::new(false));
+		encounter
+			.rolled(
+				&RollContext::default(),
+				EvaluationBudget::default(),
+				cancelled,
+			)
+			.expect("initiative")
+	}
+
+	#[test]
+	fn rolled_ties_are_broken() {
+		let by_list = rolled(&encounter(TieBreaker::ListOrder));
+		assert_eq!(names(&by_list), ["Cleric", "Orc", "Bard", "Archer"]);
+		assert_eq!((by_list.turn, by_list.round), (0, 1));
+		assert_eq!(by_list.combatants[1].result, Some(12.0));
+
+		let by_name = rolled(&encounter(TieBreaker::Alphabetical));
+		assert_eq!(names(&by_name), ["Cleric", "Archer", "Bard", "Orc"]);
+
+		let rolled_off = rolled(&encounter(TieBreaker::RollOff));
+		assert_eq!(names(&rolled_off)[0], "Cleric");
+		let mut tied = names(&rolled_off)[1..].to_vec();
+		tied.sort_unstable();
+		assert_eq!(tied, ["Archer", "Bard", "Orc"]);
+	}
+
+	#[test]
+	fn turns_wrap_into_the_next_round() {
+		let mut state = State::default();
+		state.encounter = rolled(&encounter(TieBreaker::ListOrder));
+		for _ in 0..4 {
+			state = NextTurnCommand {}.execute(&state);
+		}
+		assert_eq!((state.encounter.turn, state.encounter.round), (0, 2));
+		state = PreviousTurnCommand {}.execute(&state);
+		assert_eq!((state.encounter.turn, state.encounter.round), (3, 1));
+		// There is no turn before the first one
+		for _ in 0..4 {
+			state = PreviousTurnCommand {}.execute(&state);
+		}
+		assert_eq!((state.encounter.turn, state.encounter.round), (0, 1));
+	}
+
+	#[test]
+	fn temporary_hit_points_are_lost_first() {
+		let mut fighter = Combatant {
+			hp: 20,
+			max_hp: 20,
+			temp_hp: 5,
+			..Combatant::default()
+		};
+		fighter.take_damage(3);
+		assert_eq!((fighter.hp, fighter.temp_hp), (20, 2));
+		fighter.take_damage(7);
+		assert_eq!((fighter.hp, fighter.temp_hp), (15, 0));
+		fighter.take_damage(40);
+		assert_eq!(fighter.hp, 0);
+		fighter.heal(50);
+		assert_eq!(fighter.hp, 20);
+	}
+
+	#[test]
+	fn resistance_rounds_down() {
+		let mut state = State::default();
+		state.encounter.combatants = vec![
+			Combatant {
+				hp: 30,
+				..Combatant::default()
+			};
+			3
+		];
+		let resistant = ApplyDamageCommand::damage(7, DamageModifier::Resistant, vec![0]);
+		let vulnerable = ApplyDamageCommand::damage(7, DamageModifier::Vulnerable, vec![1]);
+		for mut command in [resistant, vulnerable] {
+			state = command.execute(&state);
+		}
+		let hp: Vec<i32> = state
+			.encounter
+			.combatants
+			.iter()
+			.map(|combatant| combatant.hp)
+			.collect();
+		assert_eq!(hp, [27, 16, 30]);
+		assert_eq!(DamageModifier::Resistant.apply(1), 0);
+	}
+}
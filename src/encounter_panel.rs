@@ -0,0 +1,370 @@
+// File: src/encounter_panel.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use super::encounter::{Combatant, DamageModifier, Encounter, TieBreaker};
+
+type ChangedHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn()>>>>;
+type RemoveHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn(usize)>>>>;
+
+struct CombatantRow {
+	selected: gtk::CheckButton,
+	name: gtk::Entry,
+	initiative: gtk::Entry,
+	hp: gtk::Entry,
+	max_hp: gtk::Entry,
+	temp_hp: gtk::Entry,
+	conditions: gtk::Entry,
+}
+
+// Shows the encounter of the current state. The rows are only rebuilt when
+// the encounter differs from the one shown, so typing in them is not lost.
+pub struct EncounterPanel {
+	rows_grid: gtk::Grid,
+	rows: RefCell<Vec<CombatantRow>>,
+	shown: RefCell<Option<Encounter>>,
+	tie_breaker: gtk::ComboBoxText,
+	round: gtk::Label,
+	add_button: gtk::Button,
+	roll_button: gtk::Button,
+	previous_button: gtk::Button,
+	next_button: gtk::Button,
+	amount: gtk::SpinButton,
+	resistant: gtk::CheckButton,
+	vulnerable: gtk::CheckButton,
+	damage_button: gtk::Button,
+	heal_button: gtk::Button,
+	on_changed: ChangedHandler,
+	on_remove: RemoveHandler,
+	// Set while the panel is filled so it does not report changes
+	updating: Rc<Cell<bool>>,
+}
+impl EncounterPanel {
+	pub fn new(builder: &gtk::Builder) -> EncounterPanel {
+		let container: gtk::Box = builder
+			.get_object("encounter_panel")
+			.expect("Could not get box encounter_panel.");
+
+		let rows_grid = gtk::Grid::new();
+		rows_grid.set_row_spacing(5);
+		rows_grid.set_column_spacing(5);
+
+		let tie_breaker = gtk::ComboBoxText::new();
+		for tie_breaker_kind in TieBreaker::ALL.iter() {
+			tie_breaker.append(Some(tie_breaker_kind.key()), &tie_breaker_kind.to_string());
+		}
+		let add_button = gtk::Button::with_label("Add Combatant");
+		let roll_button = gtk::Button::with_label("Roll Initiative");
+		let setup = gtk::Box::new(Orientation::Horizontal, 5);
+		setup.pack_start(&add_button, false, true, 0);
+		setup.pack_start(&gtk::Label::new(Some("Ties:")), false, true, 0);
+		setup.pack_start(&tie_breaker, false, true, 0);
+		setup.pack_end(&roll_button, false, true, 0);
+
+		let round = gtk::Label::new(None);
+		let previous_button = gtk::Button::with_label("Previous");
+		let next_button = gtk::Button::with_label("Next");
+		let turns = gtk::Box::new(Orientation::Horizontal, 5);
+		turns.pack_start(&previous_button, false, true, 0);
+		turns.pack_start(&round, true, true, 0);
+		turns.pack_end(&next_button, false, true, 0);
+
+		let amount = gtk::SpinButton::with_range(0.0, 100_000.0, 1.0);
+		amount.set_tooltip_text(Some("Click a roll in the history to use its total"));
+		let resistant = gtk::CheckButton::with_label("Resistant");
+		let vulnerable = gtk::CheckButton::with_label("Vulnerable");
+		let damage_button = gtk::Button::with_label("Damage");
+		let heal_button = gtk::Button::with_label("Heal");
+		let damage = gtk::Box::new(Orientation::Horizontal, 5);
+		damage.pack_start(&amount, false, true, 0);
+		damage.pack_start(&resistant, false, true, 0);
+		damage.pack_start(&vulnerable, false, true, 0);
+		damage.pack_end(&heal_button, false, true, 0);
+		damage.pack_end(&damage_button, false, true, 0);
+
+		// Header row of the combatant grid
+		for (column, title) in [
+			"",
+			"",
+			"Name",
+			"Initiative",
+			"",
+			"HP",
+			"Max",
+			"Temp",
+			"Conditions",
+		]
+		.iter()
+		.enumerate()
+		{
+			rows_grid.attach(&gtk::Label::new(Some(title)), column as i32, 0, 1, 1);
+		}
+
+		container.pack_start(&rows_grid, false, true, 0);
+		container.pack_start(&setup, false, true, 0);
+		container.pack_start(&turns, false, true, 0);
+		container.pack_start(&damage, false, true, 0);
+
+		let panel = EncounterPanel {
+			rows_grid,
+			rows: RefCell::new(Vec::new()),
+			shown: RefCell::new(None),
+			tie_breaker,
+			round,
+			add_button,
+			roll_button,
+			previous_button,
+			next_button,
+			amount,
+			resistant,
+			vulnerable,
+			damage_button,
+			heal_button,
+			on_changed: Rc::new(RefCell::new(None)),
+			on_remove: Rc::new(RefCell::new(None)),
+			updating: Rc::new(Cell::new(false)),
+		};
+		{
+			let on_changed = Rc::clone(&panel.on_changed);
+			let updating = Rc::clone(&panel.updating);
+			panel.tie_breaker.connect_changed(move |_| {
+				EncounterPanel::report_change(&on_changed, &updating);
+			});
+		}
+		// Resistance and vulnerability cancel out, only one can be set
+		{
+			let vulnerable = panel.vulnerable.clone();
+			panel.resistant.connect_toggled(move |resistant| {
+				if resistant.get_active() {
+					vulnerable.set_active(false);
+				}
+			});
+		}
+		{
+			let resistant = panel.resistant.clone();
+			panel.vulnerable.connect_toggled(move |vulnerable| {
+				if vulnerable.get_active() {
+					resistant.set_active(false);
+				}
+			});
+		}
+		panel
+	}
+
+	pub fn show(&self, encounter: &Encounter) {
+		if self.shown.borrow().as_ref() == Some(encounter) {
+			return;
+		}
+		self.updating.set(true);
+		// Everything below the header row
+		for child in self.rows_grid.get_children() {
+			if self.rows_grid.get_cell_top_attach(&child) > 0 {
+				unsafe {
+					child.destroy();
+				}
+			}
+		}
+		let mut rows = self.rows.borrow_mut();
+		rows.clear();
+		for (index, combatant) in encounter.combatants.iter().enumerate() {
+			rows.push(self.add_row(index, combatant, encounter));
+		}
+		self.rows_grid.show_all();
+
+		self.tie_breaker
+			.set_active_id(Some(encounter.tie_breaker.key()));
+		if encounter.round == 0 {
+			self.round.set_text("Not rolled");
+		} else {
+			self.round.set_text(&format!("Round {}", encounter.round));
+		}
+		self.updating.set(false);
+		*self.shown.borrow_mut() = Some(encounter.clone());
+	}
+	fn add_row(&self, index: usize, combatant: &Combatant, encounter: &Encounter) -> CombatantRow {
+		let row = index as i32 + 1;
+		let selected = gtk::CheckButton::new();
+		selected.set_active(combatant.selected);
+		selected.set_tooltip_text(Some("Apply damage and healing"));
+		let is_turn = encounter.round > 0 && encounter.turn == index;
+		let marker = gtk::Label::new(Some(if is_turn { "▶" } else { "" }));
+		marker.set_width_chars(2);
+
+		let name = gtk::Entry::new();
+		name.set_width_chars(10);
+		name.set_placeholder_text(Some("Goblin"));
+		name.set_text(&combatant.name);
+		let initiative = gtk::Entry::new();
+		initiative.set_width_chars(8);
+		initiative.set_placeholder_text(Some("1d20+2"));
+		initiative.set_text(&combatant.initiative);
+		let result = gtk::Label::new(Some(
+			&combatant
+				.result
+				.map(|result| result.to_string())
+				.unwrap_or_default(),
+		));
+		result.set_width_chars(4);
+		let number_entry = |value: i32| {
+			let entry = gtk::Entry::new();
+			entry.set_width_chars(4);
+			entry.set_text(&value.to_string());
+			entry
+		};
+		let hp = number_entry(combatant.hp);
+		let max_hp = number_entry(combatant.max_hp);
+		let temp_hp = number_entry(combatant.temp_hp);
+		let conditions = gtk::Entry::new();
+		conditions.set_width_chars(12);
+		conditions.set_placeholder_text(Some("prone, poisoned"));
+		conditions.set_text(&combatant.conditions.join(", "));
+		let remove = gtk::Button::with_label("Remove");
+
+		self.rows_grid.attach(&selected, 0, row, 1, 1);
+		self.rows_grid.attach(&marker, 1, row, 1, 1);
+		self.rows_grid.attach(&name, 2, row, 1, 1);
+		self.rows_grid.attach(&initiative, 3, row, 1, 1);
+		self.rows_grid.attach(&result, 4, row, 1, 1);
+		self.rows_grid.attach(&hp, 5, row, 1, 1);
+		self.rows_grid.attach(&max_hp, 6, row, 1, 1);
+		self.rows_grid.attach(&temp_hp, 7, row, 1, 1);
+		self.rows_grid.attach(&conditions, 8, row, 1, 1);
+		self.rows_grid.attach(&remove, 9, row, 1, 1);
+
+		{
+			let on_changed = Rc::clone(&self.on_changed);
+			let updating = Rc::clone(&self.updating);
+			selected.connect_toggled(move |_| {
+				EncounterPanel::report_change(&on_changed, &updating);
+			});
+		}
+		for entry in [&name, &initiative, &hp, &max_hp, &temp_hp, &conditions].iter() {
+			let on_changed = Rc::clone(&self.on_changed);
+			let updating = Rc::clone(&self.updating);
+			entry.connect_changed(move |_| {
+				EncounterPanel::report_change(&on_changed, &updating);
+			});
+		}
+		{
+			let on_remove = Rc::clone(&self.on_remove);
+			remove.connect_clicked(move |_| {
+				if let Some(handler) = on_remove.borrow().as_ref() {
+					handler(index);
+				}
+			});
+		}
+		CombatantRow {
+			selected,
+			name,
+			initiative,
+			hp,
+			max_hp,
+			temp_hp,
+			conditions,
+		}
+	}
+	fn report_change(on_changed: &ChangedHandler, updating: &Cell<bool>) {
+		if updating.get() {
+			return;
+		}
+		if let Some(handler) = on_changed.borrow().as_ref() {
+			handler();
+		}
+	}
+
+	// The shown encounter with everything that can be typed in the panel.
+	// Numbers that do not parse are left as they were.
+	pub fn encounter(&self) -> Encounter {
+		let mut encounter = self.shown.borrow().clone().unwrap_or_default();
+		for (combatant, row) in encounter
+			.combatants
+			.iter_mut()
+			.zip(self.rows.borrow().iter())
+		{
+			let parse_or =
+				|entry: &gtk::Entry, old: i32| entry.get_text().trim().parse().unwrap_or(old);
+			combatant.selected = row.selected.get_active();
+			combatant.name = row.name.get_text().trim().to_owned();
+			combatant.initiative = row.initiative.get_text().trim().to_owned();
+			combatant.hp = parse_or(&row.hp, combatant.hp);
+			combatant.max_hp = parse_or(&row.max_hp, combatant.max_hp);
+			combatant.temp_hp = parse_or(&row.temp_hp, combatant.temp_hp);
+			combatant.conditions = row
+				.conditions
+				.get_text()
+				.split(',')
+				.map(str::trim)
+				.filter(|condition| !condition.is_empty())
+				.map(str::to_owned)
+				.collect();
+		}
+		if let Some(tie_breaker) = self
+			.tie_breaker
+			.get_active_id()
+			.and_then(|id| TieBreaker::from_key(&id))
+		{
+			encounter.tie_breaker = tie_breaker;
+		}
+		*self.shown.borrow_mut() = Some(encounter.clone());
+		encounter
+	}
+
+	pub fn set_amount(&self, amount: f32) {
+		self.amount.set_value(amount.max(0.0).round() as f64);
+	}
+	pub fn amount(&self) -> i32 {
+		self.amount.get_value_as_int()
+	}
+	// The index of each combatant damage and healing are applied to.
+	pub fn selected(&self) -> Vec<usize> {
+		self.rows
+			.borrow()
+			.iter()
+			.enumerate()
+			.filter(|(_, row)| row.selected.get_active())
+			.map(|(i, _)| i)
+			.collect()
+	}
+	pub fn damage_modifier(&self) -> DamageModifier {
+		if self.resistant.get_active() {
+			DamageModifier::Resistant
+		} else if self.vulnerable.get_active() {
+			DamageModifier::Vulnerable
+		} else {
+			DamageModifier::Normal
+		}
+	}
+
+	// Called when anything that can be typed in the panel is edited.
+	pub fn connect_changed<F: Fn() + 'static>(&self, f: F) {
+		*self.on_changed.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+	// Called with the index of the combatant to remove.
+	pub fn connect_remove<F: Fn(usize) + 'static>(&self, f: F) {
+		*self.on_remove.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+	pub fn add_button(&self) -> &gtk::Button {
+		&self.add_button
+	}
+	pub fn roll_button(&self) -> &gtk::Button {
+		&self.roll_button
+	}
+	pub fn previous_button(&self) -> &gtk::Button {
+		&self.previous_button
+	}
+	pub fn next_button(&self) -> &gtk::Button {
+		&self.next_button
+	}
+	pub fn damage_button(&self) -> &gtk::Button {
+		&self.damage_button
+	}
+	pub fn heal_button(&self) -> &gtk::Button {
+		&self.heal_button
+	}
+}
@@ -0,0 +1,1714 @@
+// File: src/evaluator.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use roll_lang::ast;
+use roll_lang::interpreter::output::*;
+use roll_lang::interpreter::*;
+
+use super::bands::BandTable;
+use super::budget::{budgeted_rand, check_explosions, checkpoint, current_budget};
+use super::dice_library::{Faces, SymbolCount};
+use super::functions;
+use super::limits::LimitError;
+use super::output::{Card, Outcome, Output, OutputFragment, RolledDie};
+use super::parser;
+use super::roll::RollContext;
+use super::syntax::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Advantage {
+	Normal,
+	Advantage,
+	Disadvantage,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RollMode {
+	pub advantage: Advantage,
+	// Roll each whole expression twice instead of rewriting its d20s
+	pub roll_twice: bool,
+}
+impl Default for RollMode {
+	fn default() -> Self {
+		RollMode {
+			advantage: Advantage::Normal,
+			roll_twice: false,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum EvaluationError {
+	Interpret(InterpretError),
+	Limit(LimitError),
+	NoTableNamed(String),
+	EmptyTable(String),
+	ErrorInMacro(String, Box<EvaluationError>),
+	ErrorInTable(String, Box<EvaluationError>),
+	NoVariableNamed(String),
+	NoBandsNamed(String),
+	NoDiceNamed(String),
+	DieWithoutFaces(String),
+	NoTemplateNamed(String),
+	ErrorInField(String, Box<EvaluationError>),
+	NoAttributeNamed(String),
+	ErrorInAttribute(String, Box<EvaluationError>),
+}
+// Written like roll_lang's errors have always been shown.
+impl fmt::Display for EvaluationError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			EvaluationError::Interpret(error) => write!(f, "{:?}", error),
+			EvaluationError::Limit(error) => write!(f, "{}", error),
+			EvaluationError::NoTableNamed(name) => write!(f, "NoTableNamed({:?})", name),
+			EvaluationError::EmptyTable(name) => write!(f, "EmptyTable({:?})", name),
+			EvaluationError::ErrorInMacro(name, error) => {
+				write!(f, "ErrorInMacro({:?}, {})", name, error)
+			}
+			EvaluationError::ErrorInTable(name, error) => {
+				write!(f, "ErrorInTable({:?}, {})", name, error)
+			}
+			EvaluationError::NoVariableNamed(name) => write!(f, "NoVariableNamed({:?})", name),
+			EvaluationError::NoBandsNamed(name) => write!(f, "NoBandsNamed({:?})", name),
+			EvaluationError::NoDiceNamed(name) => write!(f, "NoDiceNamed({:?})", name),
+			EvaluationError::DieWithoutFaces(name) => write!(f, "DieWithoutFaces({:?})", name),
+			EvaluationError::NoTemplateNamed(name) => write!(f, "NoTemplateNamed({:?})", name),
+			EvaluationError::ErrorInField(name, error) => {
+				write!(f, "ErrorInField({:?}, {})", name, error)
+			}
+			EvaluationError::NoAttributeNamed(name) => write!(f, "NoAttributeNamed({:?})", name),
+			EvaluationError::ErrorInAttribute(name, error) => {
+				write!(f, "ErrorInAttribute({:?}, {})", name, error)
+			}
+		}
+	}
+}
+impl From<InterpretError> for EvaluationError {
+	fn from(error: InterpretError) -> Self {
+		EvaluationError::Interpret(error)
+	}
+}
+impl From<LimitError> for EvaluationError {
+	fn from(error: LimitError) -> Self {
+		EvaluationError::Limit(error)
+	}
+}
+
+// Walks the syntax tree itself so the roll mode reaches every roll,
+// including the ones in macros and computed dice. roll_lang's interpreter
+// is only used to roll the dice, keeping, dropping and counting successes
+// is done here since roll_lang ignores it.
+//
+// The errors are roll_lang's and formatted the same way, the output is
+// roll_lang's with the fragments it does not have.
+pub struct Evaluator<'m> {
+	dice: Interpreter<'static, 'm>,
+	context: &'m RollContext,
+	roll_queries: HashMap<String, Expression>,
+	mode: RollMode,
+	// Rolls nested in a roll are not rolled twice on their own
+	depth: u32,
+	// The macros being rolled, outermost first. Query answers can call
+	// macros so they are not all checked before rolling.
+	macro_stack: Vec<String>,
+	// The let bindings of the roll being rolled
+	variables: HashMap<String, Number>,
+	// The symbols of the custom dice of the roll being rolled
+	symbols: Option<SymbolCount>,
+	// Every die with sides rolled so far
+	rolled: Vec<RolledDie>,
+}
+impl<'m> Evaluator<'m> {
+	pub fn new(
+		context: &'m RollContext,
+		roll_queries: HashMap<String, Expression>,
+		mode: RollMode,
+	) -> Evaluator<'m> {
+		Evaluator {
+			dice: Interpreter::new(
+				"",
+				HashMap::new(),
+				Some(&context.macros),
+				budgeted_rand,
+				no_prompt,
+			),
+			context,
+			roll_queries,
+			mode,
+			depth: 0,
+			macro_stack: Vec::new(),
+			variables: HashMap::new(),
+			symbols: None,
+			rolled: Vec::new(),
+		}
+	}
+
+	pub fn evaluate(&mut self, source: &str) -> Result<Output, EvaluationError> {
+		let fragments = self.source(source)?;
+		Ok(Output {
+			source: source.to_owned(),
+			fragments,
+			dice: std::mem::take(&mut self.rolled),
+		})
+	}
+
+	fn source(&mut self, source: &str) -> Result<Vec<OutputFragment>, EvaluationError> {
+		if let Some(call) = parser::template_call(source) {
+			let mut fragments = vec![OutputFragment::Card(self.card(&call)?)];
+			if !call.unclosed.is_empty() {
+				fragments.push(OutputFragment::StringLit(call.unclosed));
+			}
+			return Ok(fragments);
+		}
+		let mut fragments = Vec::new();
+		for node in parser::parse(source) {
+			checkpoint();
+			match node {
+				Node::StringLiteral(s) => fragments.push(OutputFragment::StringLit(s)),
+				Node::Macro(my_macro, None) => {
+					fragments.append(&mut self.macro_fragments(&my_macro)?)
+				}
+				Node::Macro(my_macro, Some(name)) => {
+					let table = self.band_table(&name)?;
+					let macro_fragments = self.macro_fragments(&my_macro)?;
+					fragments.append(&mut with_bands(macro_fragments, &table));
+				}
+				Node::Attribute(name) => fragments.append(&mut self.attribute_fragments(&name)?),
+				Node::Roll(roll) => fragments.append(&mut self.roll(&roll)?),
+				Node::ParseError(e) => return Err(InterpretError::ParseError(e).into()),
+			}
+		}
+		Ok(fragments)
+	}
+	fn macro_fragments(
+		&mut self,
+		my_macro: &Macro,
+	) -> Result<Vec<OutputFragment>, EvaluationError> {
+		let macros = &self.context.macros;
+		let source = macros
+			.get(&my_macro.name)
+			.ok_or_else(|| InterpretError::NoMacroNamed(my_macro.name.clone()))?;
+		let name = my_macro.name.clone();
+		if self.macro_stack.contains(&name) {
+			let mut cycle = self.macro_stack.clone();
+			cycle.push(name);
+			return Err(LimitError::MacroCycle(cycle).into());
+		}
+		let max_nesting = current_budget().max_macro_nesting;
+		if self.macro_stack.len() as u32 >= max_nesting {
+			return Err(LimitError::MacroNestingTooDeep(name, max_nesting).into());
+		}
+		self.macro_stack.push(name.clone());
+		let fragments = self.source(source);
+		self.macro_stack.pop();
+		// Limits are shown on their own, not once for every macro they
+		// were reached through
+		fragments.map_err(|e| match e {
+			EvaluationError::Interpret(e) => InterpretError::ErrorInMacro(name, Box::new(e)).into(),
+			EvaluationError::Limit(e) => e.into(),
+			e => EvaluationError::ErrorInMacro(name, Box::new(e)),
+		})
+	}
+	fn attribute_value(&self, name: &str) -> Result<&'m str, EvaluationError> {
+		let attributes = &self.context.attributes;
+		attributes
+			.get(name)
+			.map(String::as_str)
+			.ok_or_else(|| EvaluationError::NoAttributeNamed(name.to_owned()))
+	}
+	// In text the attribute is rolled like text, it can hold rolls.
+	fn attribute_fragments(&mut self, name: &str) -> Result<Vec<OutputFragment>, EvaluationError> {
+		let value = self.attribute_value(name)?;
+		self.source(value)
+			.map_err(|e| EvaluationError::ErrorInAttribute(name.to_owned(), Box::new(e)))
+	}
+	// In a roll the attribute is an expression, written with its name,
+	// `(3)[str_mod]`.
+	fn attribute(
+		&mut self,
+		name: &str,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let in_attribute = |e| EvaluationError::ErrorInAttribute(name.to_owned(), Box::new(e));
+		let value = self.attribute_value(name)?;
+		let expression = parser::parse_expression(value)
+			.map_err(|e| in_attribute(InterpretError::ParseError(e).into()))?;
+		formula.push_str("(");
+		let result = self
+			.expression(&expression, formula)
+			.map_err(in_attribute)?;
+		formula.push_str(&format!(")[{}]", name));
+		Ok(result)
+	}
+	// Every field of a template is rolled on its own, in the order given.
+	fn card(&mut self, call: &TemplateCall) -> Result<Card, EvaluationError> {
+		let template = self
+			.context
+			.templates
+			.get(&call.name)
+			.cloned()
+			.ok_or_else(|| EvaluationError::NoTemplateNamed(call.name.clone()))?;
+		let mut fields = Vec::new();
+		for (name, value) in call.fields.iter() {
+			checkpoint();
+			let fragments = self
+				.source(value)
+				.map_err(|e| EvaluationError::ErrorInField(name.clone(), Box::new(e)))?;
+			fields.push((name.clone(), fragments));
+		}
+		Ok(template.card(fields))
+	}
+	// The rows picked by a table roll, in the order they were picked.
+	fn table_rows(&mut self, table: &TableRoll) -> Result<Vec<String>, EvaluationError> {
+		let tables = &self.context.tables;
+		let rows = tables
+			.get(&table.name)
+			.ok_or_else(|| EvaluationError::NoTableNamed(table.name.clone()))?;
+		(0..table.count.value().max(0))
+			.map(|_| {
+				rows.pick(budgeted_rand())
+					.map(|row| row.text.clone())
+					.ok_or_else(|| EvaluationError::EmptyTable(table.name.clone()))
+			})
+			.collect()
+	}
+	// A roll of nothing but a table is replaced by the rows it picks, with
+	// the rolls in them rolled.
+	fn table_fragments(
+		&mut self,
+		table: &TableRoll,
+	) -> Result<Vec<OutputFragment>, EvaluationError> {
+		let mut fragments = Vec::new();
+		for (i, row) in self.table_rows(table)?.iter().enumerate() {
+			if i > 0 {
+				fragments.push(OutputFragment::StringLit(String::from(", ")));
+			}
+			let mut row = self
+				.source(row)
+				.map_err(|e| EvaluationError::ErrorInTable(table.name.clone(), Box::new(e)))?;
+			fragments.append(&mut row);
+		}
+		Ok(fragments)
+	}
+	// The bands of the profile or of a game system with the name.
+	fn band_table(&self, name: &str) -> Result<BandTable, EvaluationError> {
+		self.context
+			.bands
+			.get(name)
+			.cloned()
+			.ok_or_else(|| EvaluationError::NoBandsNamed(name.to_owned()))
+	}
+
+	fn roll(&mut self, roll: &Roll) -> Result<Vec<OutputFragment>, EvaluationError> {
+		let check = match roll {
+			Roll::ExplicitRoll(check) | Roll::InlineRoll(check) => check,
+		};
+		match check.repeat {
+			Some(count) => {
+				let mut rolls = Vec::new();
+				for _ in 0..count.value().max(0) {
+					checkpoint();
+					rolls.push(self.check(roll, check)?);
+				}
+				Ok(vec![OutputFragment::Repeated(rolls)])
+			}
+			None => self.check(roll, check),
+		}
+	}
+	// Every roll has its own variables and symbols, macros rolled in it can
+	// not see or change them.
+	fn check(
+		&mut self,
+		roll: &Roll,
+		check: &Check,
+	) -> Result<Vec<OutputFragment>, EvaluationError> {
+		let outer = std::mem::take(&mut self.variables);
+		let outer_symbols = self.symbols.take();
+		let fragments = self.check_fragments(roll, check);
+		self.variables = outer;
+		self.symbols = outer_symbols;
+		fragments
+	}
+	fn check_fragments(
+		&mut self,
+		roll: &Roll,
+		check: &Check,
+	) -> Result<Vec<OutputFragment>, EvaluationError> {
+		let expression = &check.expression;
+		if let (Some(Atom::Table(table)), None, true) = (
+			expression.single_atom(),
+			&check.target,
+			check.bindings.is_empty(),
+		) {
+			return self.table_fragments(table);
+		}
+		let mut bindings = FormulaFragments::new();
+		for binding in check.bindings.iter() {
+			self.bind(binding, &mut bindings)?;
+		}
+		let twice = self.mode.roll_twice && self.mode.advantage != Advantage::Normal;
+		let output = if twice && self.depth == 0 {
+			let keep = match self.mode.advantage {
+				Advantage::Disadvantage => PostModifier::KeepLowest(Integer::new(1)),
+				_ => PostModifier::KeepHighest(Integer::new(1)),
+			};
+			let members = vec![
+				self.expression_output(expression)?,
+				self.expression_output(expression)?,
+			];
+			group_output(members, &[keep])
+		} else {
+			self.expression_output(expression)?
+		};
+		// The bindings are written before the expression, `$atk=17+5=22; `
+		let output = ExpressionOutput {
+			formula_fragments: bindings
+				.into_iter()
+				.chain(output.formula_fragments)
+				.collect(),
+			..output
+		};
+		// One of each symbol that cancels out is taken away
+		let symbols = self.symbols.take().map(|mut symbols| {
+			symbols.cancel(&self.context.dice);
+			symbols
+		});
+		let outcome = match &check.target {
+			Some(target) => Some(self.outcome(target, output.result)?),
+			None => None,
+		};
+		let band = match &check.bands {
+			Some(name) => self
+				.band_table(name)?
+				.band(number_value(output.result))
+				.cloned(),
+			None => None,
+		};
+		let mut fragments = vec![OutputFragment::Roll(match roll {
+			Roll::ExplicitRoll(_) => RollType::ExplicitRoll(output),
+			Roll::InlineRoll(_) => RollType::InlineRoll(output),
+		})];
+		fragments.extend(symbols.map(OutputFragment::Symbols));
+		fragments.extend(outcome.map(OutputFragment::Outcome));
+		fragments.extend(band.map(OutputFragment::Band));
+		Ok(fragments)
+	}
+	fn bind(
+		&mut self,
+		binding: &Binding,
+		formula: &mut FormulaFragments,
+	) -> Result<(), EvaluationError> {
+		let mut value_formula = FormulaFragments::new();
+		self.depth += 1;
+		let value = self.expression(&binding.expression, &mut value_formula);
+		self.depth -= 1;
+		let value = value?;
+		// A value that is only a number is not written twice
+		let written: String = value_formula.iter().map(|f| f.to_string()).collect();
+		formula.push_str(&format!("${}=", binding.name));
+		formula.extend(value_formula);
+		if written != value.to_string() {
+			formula.push_str(&format!("={}", value));
+		}
+		formula.push_str("; ");
+		self.variables.insert(binding.name.clone(), value);
+		Ok(())
+	}
+	// The target is rolled once, after the roll it is compared to.
+	fn outcome(&mut self, target: &Target, result: Number) -> Result<Outcome, EvaluationError> {
+		let target_output = self.expression_output(&target.expression)?;
+		let success = compare(
+			number_value(result),
+			target.comparison,
+			number_value(target_output.result),
+		);
+		let margin = result - target_output.result;
+		// A raise for every full step over the target
+		let raises = match (success, target.raises) {
+			(true, Some(step)) if step.value() > 0 => {
+				Some((number_value(margin).max(0.0) / step.value() as f32).floor() as i32)
+			}
+			_ => None,
+		};
+		Ok(Outcome {
+			comparison: target.comparison,
+			success,
+			margin: margin.abs(),
+			raises,
+			target: target_output,
+		})
+	}
+	fn expression_output(
+		&mut self,
+		expression: &Expression,
+	) -> Result<ExpressionOutput, EvaluationError> {
+		let mut formula = FormulaFragments::new();
+		self.depth += 1;
+		let result = self.expression(expression, &mut formula);
+		self.depth -= 1;
+		Ok(ExpressionOutput {
+			formula_fragments: formula,
+			result: result?,
+		})
+	}
+
+	fn expression(
+		&mut self,
+		expression: &Expression,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		checkpoint();
+		match expression {
+			Expression::Add(lhs, rhs) => {
+				let lhs = self.expression(lhs, formula)?;
+				formula.push_str("+");
+				Ok(lhs + self.mul_div(rhs, formula)?)
+			}
+			Expression::Subtract(lhs, rhs) => {
+				let lhs = self.expression(lhs, formula)?;
+				formula.push_str("-");
+				Ok(lhs - self.mul_div(rhs, formula)?)
+			}
+			Expression::MulDiv(mul_div) => self.mul_div(mul_div, formula),
+		}
+	}
+	fn mul_div(
+		&mut self,
+		mul_div: &MulDiv,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		match mul_div {
+			MulDiv::Multiply(lhs, rhs) => {
+				let lhs = self.mul_div(lhs, formula)?;
+				formula.push_str("*");
+				Ok(lhs * self.power(rhs, formula)?)
+			}
+			MulDiv::Divide(lhs, rhs) => {
+				let lhs = self.mul_div(lhs, formula)?;
+				formula.push_str("/");
+				(lhs / self.power(rhs, formula)?)
+					.map_err(|e| InterpretError::OperatorError(e).into())
+			}
+			MulDiv::Power(power) => self.power(power, formula),
+		}
+	}
+	fn power(
+		&mut self,
+		power: &Power,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		match power {
+			Power::Pow(base, exponent) => {
+				let base = self.unary(base, formula)?;
+				formula.push_str("^");
+				Ok(base.pow(&self.power(exponent, formula)?))
+			}
+			Power::Unary(unary) => self.unary(unary, formula),
+		}
+	}
+	fn unary(
+		&mut self,
+		unary: &Unary,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		match unary {
+			Unary::Minus(comment, unary) => {
+				push_comment(comment, formula);
+				formula.push_str("-");
+				Ok(-self.unary(unary, formula)?)
+			}
+			Unary::Atom(comment_before, atom, comment_after) => {
+				push_comment(comment_before, formula);
+				let result = self.atom(atom, formula)?;
+				push_comment(comment_after, formula);
+				Ok(result)
+			}
+		}
+	}
+	fn atom(
+		&mut self,
+		atom: &Atom,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		match atom {
+			Atom::Number(number) => {
+				formula.push_str(&String::from(*number));
+				Ok(*number)
+			}
+			Atom::Dice(dice) => self.dice(dice, formula),
+			Atom::Function(function) => self.function(function, formula),
+			Atom::RollQuery(query) => self.roll_query(query, formula),
+			Atom::ParenthesesExpression(expression) => {
+				formula.push_str("(");
+				let result = self.expression(expression, formula)?;
+				formula.push_str(")");
+				Ok(result)
+			}
+			Atom::InlineRoll(expression) => {
+				let result = self.expression(expression, &mut FormulaFragments::new())?;
+				formula.push_str(&format!("({})", result));
+				Ok(result)
+			}
+			// The band of a nested macro is left out, only its total is used
+			Atom::Macro(my_macro) => match self.macro_fragments(my_macro)?.as_slice() {
+				[OutputFragment::Roll(RollType::ExplicitRoll(output))]
+				| [OutputFragment::Roll(RollType::InlineRoll(output))]
+				| [OutputFragment::Roll(RollType::ExplicitRoll(output)), OutputFragment::Band(_)]
+				| [OutputFragment::Roll(RollType::InlineRoll(output)), OutputFragment::Band(_)] => {
+					formula.push_str("{");
+					formula.extend(output.formula_fragments.iter().cloned());
+					formula.push_str("}");
+					Ok(output.result)
+				}
+				_ => Err(InterpretError::ThisMacroCannotBeNested(my_macro.name.clone()).into()),
+			},
+			Atom::Table(table) => self.table_number(table, formula),
+			Atom::Group(group) => self.group(group, formula),
+			Atom::Variable(name) => {
+				let value = *self
+					.variables
+					.get(name)
+					.ok_or_else(|| EvaluationError::NoVariableNamed(name.clone()))?;
+				formula.push_str(&format!("${}({})", name, value));
+				Ok(value)
+			}
+			Atom::Conditional(conditional) => self.conditional(conditional, formula),
+			Atom::Attribute(name) => self.attribute(name, formula),
+		}
+	}
+	// Written with the branch that was chosen, `$atk(22)>=20 ? 4+3+6+1`.
+	fn conditional(
+		&mut self,
+		conditional: &Conditional,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let lhs = self.expression(&conditional.lhs, formula)?;
+		formula.push_str(comparison_text(conditional.comparison));
+		let rhs = self.expression(&conditional.rhs, formula)?;
+		match compare(number_value(lhs), conditional.comparison, number_value(rhs)) {
+			true => {
+				formula.push_str(" ? ");
+				self.expression(&conditional.then, formula)
+			}
+			false => {
+				formula.push_str(" : ");
+				self.expression(&conditional.otherwise, formula)
+			}
+		}
+	}
+	fn function(
+		&mut self,
+		function: &Function,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		// Only functions that are registered are parsed
+		let definition = functions::lookup(&function.name).ok_or(InterpretError::Unkown)?;
+		formula.push_str(&function.name);
+		formula.push_str("(");
+		let mut arguments = Vec::new();
+		for (i, argument) in function.arguments.iter().enumerate() {
+			if i > 0 {
+				formula.push_str(", ");
+			}
+			arguments.push(self.expression(argument, formula)?);
+		}
+		formula.push_str(")");
+		Ok((definition.call)(&arguments)?)
+	}
+	// In arithmetic the rows picked are rolled as expressions and added up.
+	fn table_number(
+		&mut self,
+		table: &TableRoll,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let mut total = Number::Integer(Integer::new(0));
+		for (i, row) in self.table_rows(table)?.iter().enumerate() {
+			if i > 0 {
+				formula.push_str("+");
+			}
+			let expression = parser::parse_expression(row).map_err(|e| {
+				EvaluationError::ErrorInTable(
+					table.name.clone(),
+					Box::new(InterpretError::ParseError(e).into()),
+				)
+			})?;
+			formula.push_str("(");
+			total = total + self.expression(&expression, formula)?;
+			formula.push_str(")");
+		}
+		formula.push_str(&format!("[{}]", table.name));
+		Ok(total)
+	}
+	// Unanswered queries take their default.
+	fn roll_query(
+		&mut self,
+		query: &RollQuery,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let expression = match self.roll_queries.get(&query.prompt) {
+			Some(expression) => expression.clone(),
+			None => {
+				let expression =
+					parser::parse_expression(&query.default).map_err(InterpretError::ParseError)?;
+				self.roll_queries
+					.insert(query.prompt.clone(), expression.clone());
+				expression
+			}
+		};
+		self.expression(&expression, formula)
+	}
+
+	fn dice(
+		&mut self,
+		dice: &Dice,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		match dice {
+			Dice::Normal(normal, modifiers, tooltip) => {
+				self.normal_dice(*normal, modifiers, tooltip, formula)
+			}
+			Dice::Computed(computed, modifiers, tooltip) => {
+				// Only the result of the count and sides is shown
+				let count = self.expression(&computed.count, &mut FormulaFragments::new())?;
+				let sides = self.expression(&computed.sides, &mut FormulaFragments::new())?;
+				let normal = match (count, sides) {
+					(Number::Integer(count), Number::Integer(sides)) => Normal { count, sides },
+					(Number::Float(_), _) => {
+						return Err(InterpretError::DiceCountMustBeAnInteger.into())
+					}
+					(_, Number::Float(_)) => {
+						return Err(InterpretError::DiceSidesMustBeAnInteger.into())
+					}
+				};
+				self.normal_dice(normal, modifiers, tooltip, formula)
+			}
+			Dice::Custom(custom, modifiers, tooltip) => {
+				self.custom_dice(custom, modifiers, tooltip, formula)
+			}
+			// roll_lang reports that it can not roll these
+			Dice::Fate(fate, modifiers, tooltip) => Ok(self.dice.interpret_dice(
+				&ast::Dice::Fate(*fate, modifiers.dice_modifiers(), tooltip.clone()),
+				formula,
+			)?),
+		}
+	}
+	fn normal_dice(
+		&mut self,
+		normal: Normal,
+		modifiers: &Modifiers,
+		tooltip: &Option<String>,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		// Computed dice only have their sides once they are rolled
+		check_sides(normal.sides.value())?;
+		if let Some(sides) = modifiers.wild {
+			check_sides(sides.value())?;
+			return self.wild_dice(normal, modifiers, sides, tooltip, formula);
+		}
+		let before = rolled_faces(formula).len();
+		let result = self.rolled_normal_dice(normal, modifiers, tooltip, formula);
+		let sides = normal.sides.value();
+		self.rolled.extend(
+			rolled_faces(formula)
+				.into_iter()
+				.skip(before)
+				.map(|face| RolledDie { sides, face }),
+		);
+		result
+	}
+	fn rolled_normal_dice(
+		&mut self,
+		normal: Normal,
+		modifiers: &Modifiers,
+		tooltip: &Option<String>,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let (normal, modifiers) = self.with_advantage(normal, modifiers);
+		// Dice that reroll or explode are rolled here so every chain of
+		// rerolls and explosions is counted
+		if modifiers.expanding.is_some() || !modifiers.reroll_modifiers.is_empty() {
+			let sides = normal.sides.value();
+			if sides < 1 {
+				return Err(InterpretError::DiceWithFewerThanOneSides.into());
+			}
+			let faces = NumberFaces::Sides(sides);
+			return self.number_faces(normal.count.value(), faces, &modifiers, tooltip, formula);
+		}
+		let (counting, post_modifiers): (Vec<PostModifier>, Vec<PostModifier>) = modifiers
+			.post_modifiers
+			.iter()
+			.partition(|modifier| is_keep_drop(modifier) || is_success(modifier));
+		if counting.is_empty() && modifiers.sort.is_none() {
+			return Ok(self.dice.interpret_normal_dice(
+				&normal,
+				&modifiers.dice_modifiers(),
+				tooltip,
+				formula,
+			)?);
+		}
+
+		let modifiers = Modifiers {
+			post_modifiers,
+			..modifiers
+		};
+		let mut rolled = FormulaFragments::new();
+		let result = self.dice.interpret_normal_dice(
+			&normal,
+			&modifiers.dice_modifiers(),
+			tooltip,
+			&mut rolled,
+		)?;
+		Ok(kept_rolls(
+			rolled,
+			result,
+			&counting,
+			modifiers.sort,
+			tooltip,
+			formula,
+		))
+	}
+	// Custom dice with symbols add the symbols they come up with to the roll
+	// and are worth nothing themselves: `3d{ability}(✹, ✹⬢, –)`. Dice with
+	// number faces are rolled like other dice.
+	fn custom_dice(
+		&mut self,
+		custom: &CustomDice,
+		modifiers: &Modifiers,
+		tooltip: &Option<String>,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let count = match self.expression(&custom.count, &mut FormulaFragments::new())? {
+			Number::Integer(count) => count.value().max(0),
+			Number::Float(_) => return Err(InterpretError::DiceCountMustBeAnInteger.into()),
+		};
+		let (name, faces) = match &custom.faces {
+			CustomFaces::Named(name) => {
+				let die = self.context.dice.die(name);
+				let die = die.ok_or_else(|| EvaluationError::NoDiceNamed(name.clone()))?;
+				(name.clone(), die.faces.clone())
+			}
+			CustomFaces::Listed(faces) => (String::new(), Faces::Numbers(faces.clone())),
+		};
+		if faces.is_empty() {
+			return Err(EvaluationError::DieWithoutFaces(name));
+		}
+		check_sides(faces.len().min(i32::MAX as usize) as i32)?;
+		match faces {
+			Faces::Numbers(faces) => {
+				let faces = NumberFaces::Listed(&faces);
+				self.number_faces(count, faces, modifiers, tooltip, formula)
+			}
+			Faces::Symbols(faces) => {
+				self.symbol_faces(count, &name, &faces, formula);
+				// There are no number rolls to give the tooltip to
+				push_comment(tooltip, formula);
+				Ok(Number::Integer(Integer::new(0)))
+			}
+		}
+	}
+	fn symbol_faces(
+		&mut self,
+		count: i32,
+		name: &str,
+		faces: &[Vec<String>],
+		formula: &mut FormulaFragments,
+	) {
+		let library = &self.context.dice;
+		let mut symbols = self.symbols.take().unwrap_or_default();
+		let mut rolled = Vec::new();
+		for _ in 0..count {
+			let face = pick(faces);
+			for symbol in face.iter() {
+				symbols.add(&library.symbol(symbol), 1);
+			}
+			rolled.push(library.face_text(face));
+		}
+		self.symbols = Some(symbols);
+		formula.push_str(&format!("{}d{{{}}}", count, name));
+		if !rolled.is_empty() {
+			formula.push_str(&format!("({})", rolled.join(", ")));
+		}
+	}
+	// roll_lang only rolls 1 to N, dice with other faces are rolled here the
+	// way it rolls them. Rerolls and explosions compare the face values and
+	// the highest face explodes when no point is given.
+	fn number_faces(
+		&mut self,
+		count: i32,
+		faces: NumberFaces,
+		modifiers: &Modifiers,
+		tooltip: &Option<String>,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let highest = faces.highest();
+		if faces.all_roll_again(highest, modifiers) {
+			return Err(InterpretError::InfiniteRerollsDetected.into());
+		}
+		let mut rolled = FormulaFragments::new();
+		let mut result = 0;
+		for _ in 0..count {
+			let rolls = roll_face(faces, highest, modifiers, &mut 0);
+			let counted: i32 = rolls
+				.iter()
+				.map(|roll| match roll {
+					NumberRoll::Counted(value) => value.value(),
+					NumberRoll::NotCounted(_) => 0,
+				})
+				.sum();
+			result += counted;
+			match modifiers.expanding {
+				// Compounded dice are one roll
+				Some(Expanding::Compounding(_)) => {
+					rolled.push_number_roll(&NumberRoll::Counted(Integer::new(counted)))
+				}
+				// Every penetrating roll after the first is one less
+				Some(Expanding::Penetrating(_)) => {
+					let mut penetration = 0;
+					for roll in rolls.iter() {
+						match roll {
+							NumberRoll::Counted(value) => {
+								let value = value.value() - penetration;
+								result -= penetration;
+								penetration = 1;
+								rolled.push_number_roll(&NumberRoll::Counted(Integer::new(value)));
+							}
+							roll => rolled.push_number_roll(roll),
+						}
+					}
+				}
+				_ => {
+					for roll in rolls.iter() {
+						rolled.push_number_roll(roll);
+					}
+				}
+			}
+		}
+		let (counting, _): (Vec<PostModifier>, Vec<PostModifier>) = modifiers
+			.post_modifiers
+			.iter()
+			.partition(|modifier| is_keep_drop(modifier) || is_success(modifier));
+		Ok(kept_rolls(
+			rolled,
+			Number::Integer(Integer::new(result)),
+			&counting,
+			modifiers.sort,
+			tooltip,
+			formula,
+		))
+	}
+	// Savage Worlds trait dice, `1d8w`. The trait dice and the wild die ace,
+	// exploding on their highest side unless they are given another way to
+	// explode, and the highest are kept: `{8+3=11, 4=~4~}kh1`.
+	fn wild_dice(
+		&mut self,
+		normal: Normal,
+		modifiers: &Modifiers,
+		wild_sides: Integer,
+		tooltip: &Option<String>,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		let acing =
+			Modifiers {
+				expanding: Some(modifiers.expanding.unwrap_or_else(|| {
+					Expanding::Exploding(Exploding::new(Comparison::Equal, None))
+				})),
+				reroll_modifiers: modifiers.reroll_modifiers.clone(),
+				..Modifiers::new()
+			};
+		let die = |sides: Integer| {
+			let normal = Normal {
+				count: Integer::new(1),
+				sides,
+			};
+			Expression::from(Atom::Dice(Dice::Normal(normal, acing.clone(), None)))
+		};
+		let mut members: Vec<Expression> = (0..normal.count.value().max(0))
+			.map(|_| die(normal.sides))
+			.collect();
+		members.push(die(wild_sides));
+		let mut group_modifiers = vec![PostModifier::KeepHighest(normal.count)];
+		group_modifiers.extend(modifiers.post_modifiers.iter().cloned());
+		let result = self.group(
+			&Group {
+				members,
+				modifiers: group_modifiers,
+			},
+			formula,
+		)?;
+		push_comment(tooltip, formula);
+		Ok(result)
+	}
+	// A group of one dice term keeps, drops or counts its dice, as in
+	// `{4d6}kh3`. Other groups do that with their members.
+	fn group(
+		&mut self,
+		group: &Group,
+		formula: &mut FormulaFragments,
+	) -> Result<Number, EvaluationError> {
+		if let [member] = group.members.as_slice() {
+			if let Some(Atom::Dice(dice)) = member.single_atom() {
+				let dice = with_post_modifiers(dice, &group.modifiers);
+				formula.push_str("{");
+				let result = self.dice(&dice, formula)?;
+				formula.push_str(&format!("}}{}", modifiers_text(&group.modifiers)));
+				return Ok(result);
+			}
+		}
+		let mut members = Vec::new();
+		for member in group.members.iter() {
+			members.push(self.expression_output(member)?);
+		}
+		let output = group_output(members, &group.modifiers);
+		formula.extend(output.formula_fragments);
+		Ok(output.result)
+	}
+	// With advantage `Nd20` is rolled as `2Nd20khN`, with disadvantage as
+	// `2Nd20klN`. Dice that already keep or drop are left alone.
+	fn with_advantage(&self, normal: Normal, modifiers: &Modifiers) -> (Normal, Modifiers) {
+		let mut modifiers = modifiers.clone();
+		if self.mode.roll_twice
+			|| normal.sides.value() != 20
+			|| modifiers.post_modifiers.iter().any(is_keep_drop)
+		{
+			return (normal, modifiers);
+		}
+		let keep = match self.mode.advantage {
+			Advantage::Normal => return (normal, modifiers),
+			Advantage::Advantage => PostModifier::KeepHighest(normal.count),
+			Advantage::Disadvantage => PostModifier::KeepLowest(normal.count),
+		};
+		modifiers.post_modifiers.push(keep);
+		let normal = Normal {
+			count: Integer::new(normal.count.value().saturating_mul(2)),
+			sides: normal.sides,
+		};
+		(normal, modifiers)
+	}
+}
+
+fn no_prompt(_prompt: &str, _default: &str) -> Option<String> {
+	None
+}
+
+fn push_comment(comment: &Option<String>, formula: &mut FormulaFragments) {
+	if let Some(comment) = comment {
+		formula.push_str(&format!("[{}]", comment));
+	}
+}
+
+fn is_keep_drop(modifier: &PostModifier) -> bool {
+	matches!(
+		modifier,
+		PostModifier::KeepHighest(_)
+			| PostModifier::KeepLowest(_)
+			| PostModifier::DropHighest(_)
+			| PostModifier::DropLowest(_)
+	)
+}
+
+fn is_success(modifier: &PostModifier) -> bool {
+	matches!(modifier, PostModifier::Success(_, _))
+}
+
+fn with_post_modifiers(dice: &Dice, post_modifiers: &[PostModifier]) -> Dice {
+	let add = |modifiers: &Modifiers| {
+		let mut modifiers = modifiers.clone();
+		modifiers.post_modifiers.extend_from_slice(post_modifiers);
+		modifiers
+	};
+	match dice {
+		Dice::Normal(normal, modifiers, tooltip) => {
+			Dice::Normal(*normal, add(modifiers), tooltip.clone())
+		}
+		Dice::Fate(fate, modifiers, tooltip) => Dice::Fate(*fate, add(modifiers), tooltip.clone()),
+		Dice::Computed(computed, modifiers, tooltip) => {
+			Dice::Computed(computed.clone(), add(modifiers), tooltip.clone())
+		}
+		Dice::Custom(custom, modifiers, tooltip) => {
+			Dice::Custom(custom.clone(), add(modifiers), tooltip.clone())
+		}
+	}
+}
+
+// The faces of every die in the formula, counted or not.
+fn rolled_faces(formula: &FormulaFragments) -> Vec<i32> {
+	let mut faces = Vec::new();
+	for fragment in formula.iter() {
+		if let FormulaFragment::NumberRolls(first, rest, _) = fragment {
+			for roll in std::iter::once(first).chain(rest.iter()) {
+				match roll {
+					NumberRoll::Counted(value) | NumberRoll::NotCounted(value) => {
+						faces.push(value.value())
+					}
+				}
+			}
+		}
+	}
+	faces
+}
+
+// Keeps, drops, counts and sorts rolled dice, the way roll_lang would if
+// it did.
+fn kept_rolls(
+	rolled: FormulaFragments,
+	result: Number,
+	counting: &[PostModifier],
+	sort: Option<Sort>,
+	tooltip: &Option<String>,
+	formula: &mut FormulaFragments,
+) -> Number {
+	let mut rolls = Vec::new();
+	for fragment in rolled {
+		if let FormulaFragment::NumberRolls(first, mut rest, _) = fragment {
+			rolls.push(first);
+			rolls.append(&mut rest);
+		}
+	}
+	let roll_value = |roll: &NumberRoll| match roll {
+		NumberRoll::Counted(value) | NumberRoll::NotCounted(value) => value.value(),
+	};
+	match sort {
+		Some(Sort::Ascending) => rolls.sort_by_key(roll_value),
+		Some(Sort::Descending) => rolls.sort_by_key(|roll| -roll_value(roll)),
+		None => (),
+	}
+	let values: Vec<f32> = rolls.iter().map(|roll| roll_value(roll) as f32).collect();
+	let mut kept: Vec<bool> = rolls
+		.iter()
+		.map(|roll| matches!(roll, NumberRoll::Counted(_)))
+		.collect();
+	keep_and_drop(&values, &mut kept, counting);
+
+	for (roll, kept) in rolls.iter().zip(kept.iter()) {
+		match (roll, kept) {
+			(NumberRoll::Counted(value), false) => {
+				formula.push_number_roll(&NumberRoll::NotCounted(*value))
+			}
+			_ => formula.push_number_roll(roll),
+		}
+	}
+	if let Some(tooltip) = tooltip {
+		formula.push_tooltip(tooltip);
+	}
+	if counting.is_empty() {
+		return result;
+	}
+	Number::Integer(Integer::new(match counting.iter().any(is_success) {
+		true => count_successes(&values, &kept, counting),
+		false => values
+			.iter()
+			.zip(kept.iter())
+			.filter(|(_, &kept)| kept)
+			.map(|(&value, _)| value as i32)
+			.sum(),
+	}))
+}
+
+fn check_sides(sides: i32) -> Result<(), EvaluationError> {
+	let max_sides = current_budget().max_sides;
+	match i64::from(sides) > i64::from(max_sides) {
+		true => Err(LimitError::TooManySides(max_sides).into()),
+		false => Ok(()),
+	}
+}
+
+// The faces of a die with numbers. Dice with sides are 1 to N and are not
+// listed out, N can be as large as the budget allows.
+#[derive(Debug, Copy, Clone)]
+enum NumberFaces<'f> {
+	Sides(i32),
+	Listed(&'f [i32]),
+}
+impl NumberFaces<'_> {
+	fn highest(self) -> i32 {
+		match self {
+			NumberFaces::Sides(sides) => sides,
+			NumberFaces::Listed(faces) => faces.iter().copied().max().unwrap_or(0),
+		}
+	}
+	fn pick(self) -> i32 {
+		match self {
+			NumberFaces::Sides(sides) => {
+				let index = (budgeted_rand() * sides as f64) as i32;
+				1 + index.min(sides - 1)
+			}
+			NumberFaces::Listed(faces) => *pick(faces),
+		}
+	}
+	// Whether every face rerolls or explodes. Between two comparison points
+	// the faces of 1 to N all do the same, so only the faces at and next
+	// to the points and the ends are tried.
+	fn all_roll_again(self, highest: i32, modifiers: &Modifiers) -> bool {
+		let again =
+			|face: i32| rerolls(face, highest, modifiers) || explodes(face, highest, modifiers);
+		match self {
+			NumberFaces::Sides(sides) => {
+				let exploding = match &modifiers.expanding {
+					Some(Expanding::Exploding(exploding))
+					| Some(Expanding::Compounding(exploding))
+					| Some(Expanding::Penetrating(exploding)) => Some(exploding),
+					None => None,
+				};
+				modifiers
+					.reroll_modifiers
+					.iter()
+					.chain(exploding)
+					.map(|reroll| {
+						reroll
+							.comparison_point
+							.map_or(highest, |point| point.value())
+					})
+					.flat_map(|point| vec![point - 1, point, point + 1])
+					.chain(vec![1, sides])
+					.filter(|face| (1..=sides).contains(face))
+					.all(again)
+			}
+			NumberFaces::Listed(faces) => faces.iter().all(|&face| again(face)),
+		}
+	}
+}
+
+fn pick<T>(faces: &[T]) -> &T {
+	let index = (budgeted_rand() * faces.len() as f64) as usize;
+	&faces[index.min(faces.len() - 1)]
+}
+// Rolls one die with the faces, with its rerolls and explosions, like
+// roll_lang's apply_exploding_and_reroll_modifiers. `chain` counts the
+// rerolls and explosions of the die.
+fn roll_face(
+	faces: NumberFaces,
+	highest: i32,
+	modifiers: &Modifiers,
+	chain: &mut u32,
+) -> Vec<NumberRoll> {
+	let face = faces.pick();
+	let mut rolls = Vec::new();
+	match rerolls(face, highest, modifiers) {
+		true => {
+			rolls.push(NumberRoll::NotCounted(Integer::new(face)));
+			*chain += 1;
+			check_explosions(*chain);
+			rolls.append(&mut roll_face(faces, highest, modifiers, chain));
+		}
+		false => rolls.push(NumberRoll::Counted(Integer::new(face))),
+	}
+	if explodes(face, highest, modifiers) {
+		*chain += 1;
+		check_explosions(*chain);
+		rolls.append(&mut roll_face(faces, highest, modifiers, chain));
+	}
+	rolls
+}
+fn rerolls(face: i32, highest: i32, modifiers: &Modifiers) -> bool {
+	modifiers
+		.reroll_modifiers
+		.iter()
+		.any(|reroll| meets(face, highest, reroll))
+}
+fn explodes(face: i32, highest: i32, modifiers: &Modifiers) -> bool {
+	match &modifiers.expanding {
+		Some(Expanding::Exploding(exploding))
+		| Some(Expanding::Compounding(exploding))
+		| Some(Expanding::Penetrating(exploding)) => meets(face, highest, exploding),
+		None => false,
+	}
+}
+// The point is the highest face when none is given, `d[1,2,2,3]!` explodes
+// on 3.
+fn meets(face: i32, highest: i32, reroll: &Reroll) -> bool {
+	let point = reroll
+		.comparison_point
+		.map_or(highest, |point| point.value());
+	compare(face as f32, reroll.comparison, point as f32)
+}
+// Unmarks the values that are dropped. Each modifier applies to the values
+// the ones before it kept.
+fn keep_and_drop(values: &[f32], kept: &mut [bool], modifiers: &[PostModifier]) {
+	for modifier in modifiers {
+		let mut candidates: Vec<usize> = (0..values.len()).filter(|&i| kept[i]).collect();
+		// Lowest first
+		candidates.sort_by(|&a, &b| values[a].partial_cmp(&values[b]).unwrap_or(Ordering::Equal));
+		let len = candidates.len();
+		let amount = |n: &Integer| (n.value().max(0) as usize).min(len);
+		let dropped = match modifier {
+			PostModifier::KeepHighest(n) => &candidates[..len - amount(n)],
+			PostModifier::KeepLowest(n) => &candidates[amount(n)..],
+			PostModifier::DropHighest(n) => &candidates[len - amount(n)..],
+			PostModifier::DropLowest(n) => &candidates[..amount(n)],
+			_ => &[],
+		};
+		for &i in dropped {
+			kept[i] = false;
+		}
+	}
+}
+// The number of kept values that meet any of the success modifiers.
+fn count_successes(values: &[f32], kept: &[bool], modifiers: &[PostModifier]) -> i32 {
+	values
+		.iter()
+		.zip(kept.iter())
+		.filter(|(&value, &kept)| {
+			kept && modifiers.iter().any(|modifier| match modifier {
+				PostModifier::Success(comparison, target) => {
+					compare(value, *comparison, target.value() as f32)
+				}
+				_ => false,
+			})
+		})
+		.count() as i32
+}
+// Bands given to a macro replace the bands of the rolls in it. A band goes
+// after the symbols and outcome of its roll.
+fn with_bands(fragments: Vec<OutputFragment>, table: &BandTable) -> Vec<OutputFragment> {
+	let mut banded = Vec::new();
+	let mut pending = None;
+	for fragment in fragments.into_iter() {
+		if !matches!(
+			fragment,
+			OutputFragment::Symbols(_) | OutputFragment::Outcome(_)
+		) {
+			banded.extend(pending.take().map(OutputFragment::Band));
+		}
+		match fragment {
+			OutputFragment::Band(_) => (),
+			OutputFragment::Roll(RollType::ExplicitRoll(ref output))
+			| OutputFragment::Roll(RollType::InlineRoll(ref output)) => {
+				pending = table.band(number_value(output.result)).cloned();
+				banded.push(fragment);
+			}
+			fragment => banded.push(fragment),
+		}
+	}
+	banded.extend(pending.map(OutputFragment::Band));
+	banded
+}
+
+fn compare(value: f32, comparison: Comparison, target: f32) -> bool {
+	match comparison {
+		Comparison::LessThan => value < target,
+		Comparison::GreaterThan => value > target,
+		Comparison::LessThanEqual => value <= target,
+		Comparison::GreaterThanEqual => value >= target,
+		Comparison::Equal => (value - target).abs() < f32::EPSILON,
+	}
+}
+
+// Every member is shown with its result, dropped results struck through
+// like dropped dice: `{4+6+1=11, 3+2+2=~7~}kh1`.
+fn group_output(members: Vec<ExpressionOutput>, modifiers: &[PostModifier]) -> ExpressionOutput {
+	let values: Vec<f32> = members
+		.iter()
+		.map(|member| number_value(member.result))
+		.collect();
+	let mut kept = vec![true; members.len()];
+	keep_and_drop(&values, &mut kept, modifiers);
+	let result = match modifiers.iter().any(is_success) {
+		true => Number::Integer(Integer::new(count_successes(&values, &kept, modifiers))),
+		false => members
+			.iter()
+			.zip(kept.iter())
+			.filter(|(_, &kept)| kept)
+			.fold(Number::Integer(Integer::new(0)), |sum, (member, _)| {
+				sum + member.result
+			}),
+	};
+
+	let mut formula = FormulaFragments::new();
+	formula.push_str("{");
+	for (i, (member, kept)) in members.into_iter().zip(kept).enumerate() {
+		if i > 0 {
+			formula.push_str(", ");
+		}
+		formula.extend(member.formula_fragments);
+		// Whole results are struck like dice so they are marked the same
+		match (kept, member.result) {
+			(true, result) => formula.push_str(&format!("={}", result)),
+			(false, Number::Integer(result)) => {
+				formula.push_str("=");
+				formula.push(FormulaFragment::NumberRolls(
+					NumberRoll::NotCounted(result),
+					Vec::new(),
+					None,
+				));
+			}
+			(false, result) => formula.push_str(&format!("=~{}~", result)),
+		}
+	}
+	formula.push_str(&format!("}}{}", modifiers_text(modifiers)));
+	ExpressionOutput {
+		formula_fragments: formula,
+		result,
+	}
+}
+
+fn modifiers_text(modifiers: &[PostModifier]) -> String {
+	modifiers
+		.iter()
+		.map(|modifier| match modifier {
+			PostModifier::KeepHighest(n) => format!("kh{}", n.value()),
+			PostModifier::KeepLowest(n) => format!("kl{}", n.value()),
+			PostModifier::DropHighest(n) => format!("dh{}", n.value()),
+			PostModifier::DropLowest(n) => format!("dl{}", n.value()),
+			PostModifier::Success(comparison, n) => {
+				format!("{}{}", comparison_text(*comparison), n.value())
+			}
+			PostModifier::CriticalSuccess(comparison, n) => {
+				format!("cs{}{}", comparison_text(*comparison), n.value())
+			}
+			PostModifier::CriticalFailure(comparison, n) => {
+				format!("cf{}{}", comparison_text(*comparison), n.value())
+			}
+		})
+		.collect()
+}
+pub fn comparison_text(comparison: Comparison) -> &'static str {
+	match comparison {
+		Comparison::LessThan => "<",
+		Comparison::GreaterThan => ">",
+		Comparison::LessThanEqual => "<=",
+		Comparison::GreaterThanEqual => ">=",
+		Comparison::Equal => "=",
+	}
+}
+
+pub fn number_value(number: Number) -> f32 {
+	match number {
+		Number::Integer(int) => int.value() as f32,
+		Number::Float(float) => float.value(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::bands::Band;
+	use super::super::dice_library::DiceLibrary;
+	use super::super::output::MarkStyle;
+	use super::super::presets::Preset;
+	use super::super::roll::RollLibrary;
+	use super::super::roll::{output_marked, output_text, totals, TextOptions, FAIL_COLOUR};
+	use super::super::templates::Template;
+	use super::*;
+
+	fn evaluate(input: &str, context: &RollContext, answers: &[(&str, &str)]) -> Output {
+		let roll_queries = answers
+			.iter()
+			.map(|(prompt, answer)| {
+				let answer = parser::parse_expression(answer).expect("an expression");
+				(prompt.to_string(), answer)
+			})
+			.collect();
+		Evaluator::new(context, roll_queries, RollMode::default())
+			.evaluate(input)
+			.unwrap_or_else(|e| panic!("{:?} could not be rolled: {}", input, e))
+	}
+	fn text(input: &str) -> String {
+		let output = evaluate(input, &RollContext::default(), &[]);
+		output_text(&output, TextOptions::default())
+	}
+	fn text_with(input: &str, context: &RollContext) -> String {
+		output_text(&evaluate(input, context, &[]), TextOptions::default())
+	}
+	fn total(input: &str) -> f32 {
+		total_with(input, &RollContext::default())
+	}
+	fn total_with(input: &str, context: &RollContext) -> f32 {
+		let output = evaluate(input, context, &[]);
+		match totals(&output).as_slice() {
+			[total] => *total,
+			totals => panic!("{:?} has totals {:?}", input, totals),
+		}
+	}
+
+	#[test]
+	fn groups_keep_and_drop_members() {
+		assert_eq!(total("/r {3, 5, 1}kh2"), 8.0);
+		assert_eq!(total("/r {3, 5, 1}kl1"), 1.0);
+		assert_eq!(total("/r {3, 5, 1}dh1"), 4.0);
+		assert_eq!(total("/r {3, 5, 1}dl1"), 8.0);
+		assert_eq!(text("/r {3, 5, 1}kh2"), "{3=3, 5=5, 1=~1~}kh2=8");
+	}
+	#[test]
+	fn groups_count_successes() {
+		assert_eq!(total("/r {3, 5, 1}>4"), 1.0);
+		assert_eq!(total("/r {3, 5, 1}>0"), 3.0);
+		assert_eq!(total("/r {2d1, 4d1}>3"), 1.0);
+	}
+	#[test]
+	fn keep_the_higher_of_a_die_and_a_number() {
+		for _ in 0..100 {
+			let output = evaluate("/r {1d20, 10}kh1", &RollContext::default(), &[]);
+			let total = totals(&output)[0];
+			assert!((10.0..=20.0).contains(&total));
+			// One member is dropped, the number when the die is higher
+			let text = output_text(&output, TextOptions::default());
+			assert_eq!(text.matches('~').count(), 2, "{}", text);
+			assert_eq!(text.contains(", 10=~10~}"), total > 10.0, "{}", text);
+		}
+	}
+
+	#[test]
+	fn penetrating_rolls_are_one_less() {
+		// Threes penetrate and count two, the one that stops them nothing
+		for _ in 0..100 {
+			let text = text("/r 1d[1,3]!p");
+			let (rolls, total) = text.split_at(text.find('=').expect("a total"));
+			let rolls: Vec<&str> = rolls.split('+').collect();
+			let expected = match rolls.as_slice() {
+				["1"] => 1,
+				["3", rest @ ..] => {
+					let (last, twos) = rest.split_last().expect("a roll after a three");
+					assert!(twos.iter().all(|&roll| roll == "2"), "{}", text);
+					assert_eq!(*last, "0", "{}", text);
+					3 + 2 * twos.len() as i32
+				}
+				_ => panic!("{}", text),
+			};
+			assert_eq!(total, format!("={}", expected));
+		}
+	}
+
+	#[test]
+	fn precedence() {
+		assert_eq!(total("/r 2+3*4"), 14.0);
+		assert_eq!(total("/r (2+3)*4"), 20.0);
+		assert_eq!(total("/r 10-2-3"), 5.0);
+		assert_eq!(total("/r 2*3^2"), 18.0);
+		assert_eq!(total("/r -2+5"), 3.0);
+	}
+
+	#[test]
+	fn comments_are_kept_and_not_counted() {
+		assert_eq!(text("/r 2d1[fire]+3[bonus]"), "1+1[fire]+3[bonus]=5");
+		assert_eq!(text("[[2d1]] and [[3]]"), "1+1=2 and 3=3");
+	}
+
+	#[test]
+	fn roll_queries() {
+		let context = RollContext::default();
+		let output = evaluate("/r 1d1+?{Bonus|1}", &context, &[("Bonus", "3")]);
+		assert_eq!(totals(&output), [4.0]);
+		let output = evaluate("/r 1d1+?{Bonus|1}", &context, &[]);
+		assert_eq!(totals(&output), [2.0]);
+	}
+
+	#[test]
+	fn functions() {
+		assert_eq!(total("/r sqrt(16)"), 4.0);
+		assert_eq!(total("/r mod(-1, 6)"), 5.0);
+		assert_eq!(total("/r clamp(1d1+8, 2, 5)"), 5.0);
+		let negative = Evaluator::new(&RollContext::default(), HashMap::new(), RollMode::default())
+			.evaluate("/r sqrt(-4)")
+			.is_err();
+		assert!(negative);
+	}
+
+	#[test]
+	fn repeats() {
+		assert_eq!(text("/r 3x(2+2)"), "2+2=4\n2+2=4\n2+2=4");
+		let output = evaluate("/r 4# 1d1", &RollContext::default(), &[]);
+		assert_eq!(totals(&output), [1.0, 1.0, 1.0, 1.0]);
+	}
+
+	#[test]
+	fn targets() {
+		assert_eq!(text("/r 1d1+10 vs 11"), "1+10=11 >= 11: Success by 0");
+		assert_eq!(text("/r 1d1+10 vs 12"), "1+10=11 >= 12: Fail by 1");
+		assert_eq!(text("/r 1d1 < 2"), "1=1 < 2: Success by 1");
+	}
+	#[test]
+	fn outcomes_and_dropped_dice_are_marked() {
+		let output = evaluate("/r {3, 1}kh1 vs 4", &RollContext::default(), &[]);
+		let marked = output_marked(&output, TextOptions::default());
+		let parts: Vec<(&str, &MarkStyle)> = marked
+			.marks
+			.iter()
+			.map(|mark| (&marked.text[mark.start..mark.end], &mark.style))
+			.collect();
+		assert_eq!(
+			parts,
+			[
+				("~1~", &MarkStyle::Struck),
+				("Fail by 1", &MarkStyle::Colour(FAIL_COLOUR.to_owned())),
+			]
+		);
+		let html = marked.render(str::to_owned, |style| match style {
+			MarkStyle::Struck => Some((String::from("<s>"), String::from("</s>"))),
+			_ => None,
+		});
+		assert_eq!(html, "{3=3, 1=<s>1</s>}kh1=3 >= 4: Fail by 1");
+	}
+	#[test]
+	fn symbols_come_from_the_context() {
+		let library = "[symbols]\n\
+			success = { icon = \"+\", colour = \"#2e7d32\", cancels = \"failure\" }\n\
+			failure = { icon = \"-\" }\n\
+			[custom_dice]\n\
+			good = [\"success\"]\n\
+			bad = [\"failure\"]\n";
+		let library = match library.parse() {
+			Ok(toml::Value::Table(table)) => DiceLibrary::from_table(&table),
+			_ => panic!("a dice library"),
+		};
+		let mut context = RollContext::default();
+		context.dice.add(&library);
+		let output = evaluate("/r 3d{good}+1d{bad}", &context, &[]);
+		let marked = output_marked(&output, TextOptions::default());
+		assert_eq!(marked.text, "3d{good}(+, +, +)+1d{bad}(-): + 2 success");
+		let marks: Vec<(&str, &MarkStyle)> = marked
+			.marks
+			.iter()
+			.map(|mark| (&marked.text[mark.start..mark.end], &mark.style))
+			.collect();
+		assert_eq!(marks, [("+", &MarkStyle::Colour(String::from("#2e7d32")))]);
+		let missing = Evaluator::new(&RollContext::default(), HashMap::new(), RollMode::default())
+			.evaluate("/r 1d{good}");
+		assert!(missing.is_err());
+	}
+	#[test]
+	fn templates_come_from_the_context() {
+		let template = match "name = \"default\"\ntitle = \"name\"".parse() {
+			Ok(toml::Value::Table(table)) => Template::from_table(&table),
+			_ => panic!("a template"),
+		};
+		let mut context = RollContext::default();
+		context.templates.insert(template.name.clone(), template);
+		let input = "&{template:default} {{name=Sword}} {{hit=[[1d1+4]]}}";
+		let marked = output_marked(&evaluate(input, &context, &[]), TextOptions::default());
+		assert_eq!(marked.text, "┏━ Sword\n┃ hit: 1+4=5\n┗━");
+		let styles: Vec<&MarkStyle> = marked.marks.iter().map(|mark| &mark.style).collect();
+		assert_eq!(styles, [&MarkStyle::Title, &MarkStyle::Label]);
+		let missing = Evaluator::new(&RollContext::default(), HashMap::new(), RollMode::default())
+			.evaluate(input);
+		assert!(missing.is_err());
+	}
+	#[test]
+	fn year_zero_counts_successes_and_banes() {
+		// Genesys has a success too, the two must not clash
+		let mut library = RollLibrary::default();
+		for source in [
+			include_str!("../data/presets/genesys.toml"),
+			include_str!("../data/presets/year_zero.toml"),
+		] {
+			match source.parse() {
+				Ok(toml::Value::Table(table)) => Preset::from_table(&table).add_to(&mut library),
+				_ => panic!("a preset"),
+			}
+		}
+		let context = RollContext {
+			dice: library.dice,
+			..RollContext::default()
+		};
+		for _ in 0..20 {
+			let text = text_with("/r 10d{base}+5d{skill}", &context);
+			let (faces, symbols) = text.split_once(": ").expect("symbols");
+			let count = |icon: char, name: &str| match faces.matches(icon).count() {
+				0 => String::new(),
+				count => format!("{} {} {}", icon, count, name),
+			};
+			// In the order of the library, the success Year Zero adds again last
+			let expected: Vec<String> = vec![count('☣', "bane"), count('✹', "success")]
+				.into_iter()
+				.filter(|count| !count.is_empty())
+				.collect();
+			match expected.is_empty() {
+				true => assert_eq!(symbols, "no symbols", "{}", text),
+				false => assert_eq!(symbols, expected.join(", "), "{}", text),
+			}
+		}
+	}
+	#[test]
+	fn bands_come_from_the_context() {
+		let band = |text: &str, colour: Option<&str>| Band {
+			colour: colour.map(str::to_owned),
+			..Band::parse(text).expect("a band")
+		};
+		let mut context = RollContext::default();
+		let bands = vec![band("6-: Miss", None), band("7+: Hit", Some("#2e7d32"))];
+		context.bands.insert("pbta".into(), BandTable { bands });
+		let output = evaluate("[[1d1+6]]|bands:pbta", &context, &[]);
+		let marked = output_marked(&output, TextOptions::default());
+		assert_eq!(marked.text, "1+6=7 (Hit)");
+		let colour = MarkStyle::Colour(String::from("#2e7d32"));
+		let marks: Vec<(&str, &MarkStyle)> = marked
+			.marks
+			.iter()
+			.map(|mark| (&marked.text[mark.start..mark.end], &mark.style))
+			.collect();
+		assert_eq!(marks, [("(Hit)", &colour)]);
+		let missing = Evaluator::new(&RollContext::default(), HashMap::new(), RollMode::default())
+			.evaluate("[[1d1]]|bands:pbta");
+		assert!(missing.is_err());
+	}
+
+	#[test]
+	fn bindings_are_rolled_once() {
+		assert_eq!(total("/r $a = 2+3; $a*2"), 10.0);
+		for _ in 0..20 {
+			assert_eq!(total("/r $a = 1d6; $a - $a"), 0.0);
+		}
+	}
+
+	#[test]
+	fn conditionals_roll_the_chosen_branch() {
+		assert_eq!(total("/r $a = 5; $a >= 5 ? 10 : 20"), 10.0);
+		assert_eq!(total("/r $a = 4; $a >= 5 ? 10 : 20"), 20.0);
+		assert_eq!(
+			text("/r $a = 4; $a >= 5 ? 10 : 20"),
+			"$a=4; $a(4)>=5 : 20=20"
+		);
+	}
+
+	#[test]
+	fn attributes_and_macros() {
+		let mut context = RollContext::default();
+		context.attributes.insert("str".into(), "3".into());
+		context.macros.insert("atk".into(), "[[1d1+4]]".into());
+		assert_eq!(total_with("/r 1d1+@{str}", &context), 4.0);
+		assert_eq!(total_with("#atk", &context), 5.0);
+		let missing = Evaluator::new(&context, HashMap::new(), RollMode::default())
+			.evaluate("/r @{dex}")
+			.is_err();
+		assert!(missing);
+	}
+
+	#[test]
+	fn sides_are_checked_while_rolling() {
+		let error = |input: &str| {
+			Evaluator::new(&RollContext::default(), HashMap::new(), RollMode::default())
+				.evaluate(input)
+				.map(|_| ())
+				.unwrap_err()
+				.to_string()
+		};
+		assert_eq!(
+			error("/r 1d(2000000000)r1"),
+			"Dice with more than 10000 sides"
+		);
+		assert_eq!(error("/r 1d6r<3r>2"), "InfiniteRerollsDetected");
+		// Only the 3 is not rerolled
+		assert_eq!(total("/r 1d(2+2)r<3r>3"), 3.0);
+	}
+
+	#[test]
+	fn macro_cycles_through_queries_are_stopped() {
+		let mut context = RollContext::default();
+		context.macros.insert("a".into(), "/r 1+#a".into());
+		let error = |input: &str, answers: HashMap<String, Expression>| {
+			Evaluator::new(&context, answers, RollMode::default())
+				.evaluate(input)
+				.map(|_| ())
+				.unwrap_err()
+				.to_string()
+		};
+		let answer = parser::parse_expression("#a").expect("an expression");
+		let answers = vec![("Pick".to_owned(), answer)].into_iter().collect();
+		let cycle = "Macros use each other in a cycle #{a} -> #{a}";
+		assert_eq!(error("/r ?{Pick|1}", answers), cycle);
+		assert_eq!(error("/r ?{Pick|#a}", HashMap::new()), cycle);
+	}
+}
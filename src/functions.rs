@@ -0,0 +1,163 @@
+// File: src/functions.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::sync::RwLock;
+
+use roll_lang::ast::number::*;
+use roll_lang::interpreter::InterpretError;
+
+use super::evaluator::number_value;
+
+pub type FunctionCall = fn(&[Number]) -> Result<Number, InterpretError>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Arguments {
+	Exactly(usize),
+	AtLeast(usize),
+}
+impl Arguments {
+	pub fn accepts(&self, count: usize) -> bool {
+		match self {
+			Arguments::Exactly(n) => count == *n,
+			Arguments::AtLeast(n) => count >= *n,
+		}
+	}
+	// More arguments can follow the ones given
+	pub fn wants_more(&self, count: usize) -> bool {
+		match self {
+			Arguments::Exactly(n) => count < *n,
+			Arguments::AtLeast(_) => true,
+		}
+	}
+}
+
+#[derive(Copy, Clone)]
+pub struct FunctionDefinition {
+	pub name: &'static str,
+	pub arguments: Arguments,
+	pub call: FunctionCall,
+}
+
+static BUILTIN: [FunctionDefinition; 10] = [
+	FunctionDefinition {
+		name: "floor",
+		arguments: Arguments::Exactly(1),
+		call: |args| Ok(args[0].floor()),
+	},
+	FunctionDefinition {
+		name: "ceil",
+		arguments: Arguments::Exactly(1),
+		call: |args| Ok(args[0].ceil()),
+	},
+	FunctionDefinition {
+		name: "round",
+		arguments: Arguments::Exactly(1),
+		call: |args| Ok(args[0].round()),
+	},
+	FunctionDefinition {
+		name: "abs",
+		arguments: Arguments::Exactly(1),
+		call: |args| Ok(args[0].abs()),
+	},
+	FunctionDefinition {
+		name: "min",
+		arguments: Arguments::AtLeast(1),
+		call: |args| Ok(pick(args, |value, best| value < best)),
+	},
+	FunctionDefinition {
+		name: "max",
+		arguments: Arguments::AtLeast(1),
+		call: |args| Ok(pick(args, |value, best| value > best)),
+	},
+	FunctionDefinition {
+		name: "clamp",
+		arguments: Arguments::Exactly(3),
+		call: clamp,
+	},
+	FunctionDefinition {
+		name: "sqrt",
+		arguments: Arguments::Exactly(1),
+		call: square_root,
+	},
+	FunctionDefinition {
+		name: "mod",
+		arguments: Arguments::Exactly(2),
+		call: modulo,
+	},
+	FunctionDefinition {
+		name: "sign",
+		arguments: Arguments::Exactly(1),
+		call: |args| {
+			let sign = match number_value(args[0]) {
+				v if v > 0.0 => 1,
+				v if v < 0.0 => -1,
+				_ => 0,
+			};
+			Ok(Number::Integer(Integer::new(sign)))
+		},
+	},
+];
+static REGISTERED: RwLock<Vec<FunctionDefinition>> = RwLock::new(Vec::new());
+
+// Makes a function usable in rolls, replacing a built in one with the
+// same name.
+pub fn register(name: &'static str, arguments: Arguments, call: FunctionCall) {
+	let mut registered = REGISTERED.write().unwrap();
+	registered.retain(|function| function.name != name);
+	registered.push(FunctionDefinition {
+		name,
+		arguments,
+		call,
+	});
+}
+pub fn lookup(name: &str) -> Option<FunctionDefinition> {
+	REGISTERED
+		.read()
+		.unwrap()
+		.iter()
+		.chain(BUILTIN.iter())
+		.find(|function| function.name == name)
+		.copied()
+}
+
+fn pick(args: &[Number], better: fn(f32, f32) -> bool) -> Number {
+	let mut best = args[0];
+	for arg in args[1..].iter() {
+		if better(number_value(*arg), number_value(best)) {
+			best = *arg;
+		}
+	}
+	best
+}
+fn clamp(args: &[Number]) -> Result<Number, InterpretError> {
+	let (x, low, high) = (args[0], args[1], args[2]);
+	Ok(if number_value(x) < number_value(low) {
+		low
+	} else if number_value(x) > number_value(high) {
+		high
+	} else {
+		x
+	})
+}
+// roll_lang has no error for this, like it has none for unknown functions.
+fn square_root(args: &[Number]) -> Result<Number, InterpretError> {
+	match number_value(args[0]) {
+		value if value < 0.0 => Err(InterpretError::Unkown),
+		value => Ok(Number::Float(Float::new(value.sqrt()))),
+	}
+}
+// The remainder is never negative, `mod(-1, 6)` is 5.
+fn modulo(args: &[Number]) -> Result<Number, InterpretError> {
+	match (args[0], args[1]) {
+		(_, divisor) if number_value(divisor) == 0.0 => {
+			Err(InterpretError::OperatorError(OperatorError::DivideByZero))
+		}
+		(Number::Integer(lhs), Number::Integer(rhs)) => Ok(Number::Integer(Integer::new(
+			lhs.value().rem_euclid(rhs.value()),
+		))),
+		(lhs, rhs) => Ok(Number::Float(Float::new(
+			number_value(lhs).rem_euclid(number_value(rhs)),
+		))),
+	}
+}
@@ -0,0 +1,317 @@
+// File: src/history_export.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::fs;
+use std::path::Path;
+
+use super::output::{Mark, MarkStyle, MarkedText};
+use super::profile::{HistoryEntry, Profile, ProfileBook};
+use super::templates::escape_html;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExportFormat {
+	Csv,
+	Markdown,
+	Html,
+}
+impl ExportFormat {
+	// From the extension of a file or a name given on the command line.
+	pub fn from_name(name: &str) -> Option<ExportFormat> {
+		match name.to_lowercase().as_str() {
+			"csv" => Some(ExportFormat::Csv),
+			"md" | "markdown" => Some(ExportFormat::Markdown),
+			"html" | "htm" => Some(ExportFormat::Html),
+			_ => None,
+		}
+	}
+}
+
+// The days of history that are exported, the first and last included. No
+// bound is open ended.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DateRange {
+	// Seconds since the Unix epoch, the end is the start of the day after
+	pub start: Option<i64>,
+	pub end: Option<i64>,
+}
+impl DateRange {
+	// Reads the first and last day, `2020-01-31`, empty is no bound.
+	pub fn parse(first: &str, last: &str) -> Result<DateRange, String> {
+		let bound = |text: &str, days_after: i32| match text.trim() {
+			"" => Ok(None),
+			day => parse_day(day, days_after).map(Some),
+		};
+		Ok(DateRange {
+			start: bound(first, 0)?,
+			end: bound(last, 1)?,
+		})
+	}
+	// Entries from before times were kept are only in a range without
+	// bounds.
+	pub fn contains(&self, time: Option<i64>) -> bool {
+		match time {
+			Some(time) => {
+				self.start.is_none_or(|start| time >= start)
+					&& self.end.is_none_or(|end| time < end)
+			}
+			None => self.start.is_none() && self.end.is_none(),
+		}
+	}
+}
+// The local time the day starts at, or of a day after it.
+fn parse_day(text: &str, days_after: i32) -> Result<i64, String> {
+	let invalid = || format!("{} is not a day like 2020-01-31", text);
+	let parts: Vec<i32> = text
+		.split('-')
+		.map(|part| part.trim().parse().map_err(|_| invalid()))
+		.collect::<Result<_, _>>()?;
+	let (year, month, day) = match parts.as_slice() {
+		&[year, month, day] => (year, month, day),
+		_ => return Err(invalid()),
+	};
+	let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+	let days_in_month = match month {
+		2 if leap => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+		_ => return Err(invalid()),
+	};
+	if !(1..=9999).contains(&year) || day < 1 || day > days_in_month {
+		return Err(invalid());
+	}
+	let start = glib::DateTime::new_local(year, month, day, 0, 0, 0.0);
+	Ok(start.add_days(days_after).unwrap_or(start).to_unix())
+}
+
+// A history entry and the character that rolled it.
+pub struct ExportRow<'a> {
+	pub character: &'a str,
+	pub entry: &'a HistoryEntry,
+}
+
+// The entries of the profiles in the range, oldest first.
+pub fn rows<'a>(profiles: &[&'a Profile], range: DateRange) -> Vec<ExportRow<'a>> {
+	let mut rows: Vec<ExportRow> = profiles
+		.iter()
+		.flat_map(|profile| {
+			profile.history().iter().map(move |entry| ExportRow {
+				character: profile.name(),
+				entry,
+			})
+		})
+		.filter(|row| range.contains(row.entry.time))
+		.collect();
+	rows.sort_by_key(|row| row.entry.time);
+	rows
+}
+
+pub fn export(rows: &[ExportRow], format: ExportFormat) -> String {
+	match format {
+		ExportFormat::Csv => csv(rows),
+		ExportFormat::Markdown => markdown(rows),
+		ExportFormat::Html => html(rows),
+	}
+}
+
+// `2020-01-31 20:15:04` in local time, nothing when it is not known.
+fn time_text(time: Option<i64>) -> String {
+	time.map(|time| local_time(time, "%Y-%m-%d %H:%M:%S"))
+		.unwrap_or_default()
+}
+// Seconds since the Unix epoch written in local time with glib's format.
+pub fn local_time(time: i64, format: &str) -> String {
+	glib::DateTime::from_unix_local(time)
+		.format(format)
+		.map(|text| text.to_string())
+		.unwrap_or_default()
+}
+fn result_text(entry: &HistoryEntry) -> String {
+	match &entry.result {
+		Ok(output) => output.clone(),
+		Err(error) => format!("Error: {}", error),
+	}
+}
+fn joined<T: ToString>(values: &[T]) -> String {
+	values
+		.iter()
+		.map(T::to_string)
+		.collect::<Vec<String>>()
+		.join(" ")
+}
+
+// One line per entry, the totals and dice separated by spaces. Dice are
+// written with their sides, `d20:14`.
+fn csv(rows: &[ExportRow]) -> String {
+	let field = |text: &str| match text.contains([',', '"', '\n']) {
+		true => format!("\"{}\"", text.replace('"', "\"\"")),
+		false => text.to_owned(),
+	};
+	let mut csv = String::from("timestamp,character,source,total,dice,result\n");
+	for row in rows.iter() {
+		let fields = [
+			time_text(row.entry.time),
+			row.character.to_owned(),
+			row.entry.input.clone(),
+			joined(&row.entry.totals),
+			joined(
+				&row.entry
+					.dice
+					.iter()
+					.map(|die| format!("d{}:{}", die.sides, die.face))
+					.collect::<Vec<String>>(),
+			),
+			result_text(row.entry),
+		];
+		let fields: Vec<String> = fields.iter().map(|text| field(text)).collect();
+		csv.push_str(&fields.join(","));
+		csv.push('\n');
+	}
+	csv
+}
+
+// A table to paste into a wiki.
+fn markdown(rows: &[ExportRow]) -> String {
+	let cell = |text: &str| text.replace('|', "\\|").replace('\n', "<br>");
+	let mut markdown =
+		String::from("| Time | Character | Roll | Result |\n| --- | --- | --- | --- |\n");
+	for row in rows.iter() {
+		markdown.push_str(&format!(
+			"| {} | {} | `{}` | {} |\n",
+			time_text(row.entry.time),
+			cell(row.character),
+			row.entry.input.replace('`', "'").replace('|', "\\|"),
+			cell(&result_text(row.entry)),
+		));
+	}
+	markdown
+}
+
+// A page that needs nothing else to be shown, with the results styled like
+// the history pane.
+fn html(rows: &[ExportRow]) -> String {
+	let mut html = String::from(
+		"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Roll history</title>\n<style>\n\
+		body { font-family: sans-serif; max-width: 50em; margin: 1em auto; }\n\
+		.entry { border-bottom: 1px solid #e0e0e0; padding: 0.5em 0; }\n\
+		.meta { color: #757575; font-size: 0.9em; }\n\
+		.input { font-family: monospace; }\n\
+		.result { white-space: pre-wrap; }\n\
+		.error { color: #c62828; }\n\
+		s { color: #9e9e9e; }\n\
+		</style>\n</head>\n<body>\n<h1>Roll history</h1>\n",
+	);
+	for row in rows.iter() {
+		html.push_str("<div class=\"entry\">\n");
+		html.push_str(&format!(
+			"<div class=\"meta\">{} · {}</div>\n<div class=\"input\">{}</div>\n",
+			escape_html(&time_text(row.entry.time)),
+			escape_html(row.character),
+			escape_html(&row.entry.input)
+		));
+		match &row.entry.result {
+			Ok(output) => html.push_str(&format!(
+				"<div class=\"result\">{}</div>\n",
+				result_html(output, &row.entry.marks)
+			)),
+			Err(error) => html.push_str(&format!(
+				"<div class=\"result error\">{}</div>\n",
+				escape_html(error)
+			)),
+		}
+		html.push_str("</div>\n");
+	}
+	html.push_str("</body>\n</html>\n");
+	html
+}
+// Struck through dice, outcomes, band labels with a colour and the titles
+// and labels of cards are styled. Entries kept before their marks were are
+// only escaped.
+fn result_html(text: &str, marks: &[Mark]) -> String {
+	let marked = MarkedText {
+		text: text.to_owned(),
+		marks: marks.to_vec(),
+	};
+	marked.render(escape_html, |style| {
+		let (open, close) = match style {
+			MarkStyle::Colour(colour) => {
+				let open = format!("<b style=\"color: {}\">", escape_html(colour));
+				return Some((open, String::from("</b>")));
+			}
+			MarkStyle::Struck => ("<s>", "</s>"),
+			MarkStyle::Title | MarkStyle::Label => ("<b>", "</b>"),
+			MarkStyle::Footer => ("<i>", "</i>"),
+		};
+		Some((open.to_owned(), close.to_owned()))
+	})
+}
+// Writes the export to the file, or to standard output without one.
+pub fn write(export: &str, path: Option<&Path>) -> Result<(), String> {
+	match path {
+		Some(path) => fs::write(path, export)
+			.map_err(|e| format!("Could not write {}: {}", path.display(), e)),
+		None => {
+			print!("{}", export);
+			Ok(())
+		}
+	}
+}
+
+pub static USAGE: &str = "Usage: g_roll_lang export [--format csv|md|html] [--from YYYY-MM-DD] \
+	[--to YYYY-MM-DD] [--character NAME] [--output FILE]";
+
+// The headless export, `g_roll_lang export --from 2020-01-01 --output
+// session.md`. Every character's history is exported unless one is named
+// and the format is taken from the output file when it is not given.
+pub fn run(args: &[String]) -> Result<(), String> {
+	let mut format = None;
+	let mut first = String::new();
+	let mut last = String::new();
+	let mut character = None;
+	let mut output = None;
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		let mut value = || {
+			args.next()
+				.cloned()
+				.ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))
+		};
+		match arg.as_str() {
+			"--format" => {
+				let name = value()?;
+				format = Some(
+					ExportFormat::from_name(&name)
+						.ok_or_else(|| format!("{} is not csv, md or html", name))?,
+				);
+			}
+			"--from" => first = value()?,
+			"--to" => last = value()?,
+			"--character" => character = Some(value()?),
+			"--output" => output = Some(value()?),
+			_ => return Err(format!("Unknown argument {}\n{}", arg, USAGE)),
+		}
+	}
+	let range = DateRange::parse(&first, &last)?;
+	let format = format
+		.or_else(|| {
+			let path = Path::new(output.as_ref()?);
+			ExportFormat::from_name(&path.extension()?.to_string_lossy())
+		})
+		.unwrap_or(ExportFormat::Csv);
+
+	let book = ProfileBook::load();
+	let profiles: Vec<&Profile> = book
+		.profiles()
+		.iter()
+		.filter(|profile| character.as_ref().is_none_or(|name| profile.name() == name))
+		.collect();
+	if profiles.is_empty() {
+		return Err(format!(
+			"No character named {}",
+			character.unwrap_or_default()
+		));
+	}
+	let export = export(&rows(&profiles, range), format);
+	write(&export, output.as_ref().map(Path::new))
+}
@@ -0,0 +1,172 @@
+// File: src/limits.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::fmt;
+
+use super::budget::EvaluationBudget;
+use super::roll::{
+	attribute_sources, for_each_repeated_atom, macro_names, table_names, RollContext,
+};
+use super::syntax::*;
+
+// Limits that are checked before anything is rolled. The evaluator checks
+// the ones only known while rolling, like the sides of computed dice and
+// macros given as query answers. Exploding dice are left to the evaluation
+// budget.
+#[derive(Debug, Clone)]
+pub enum LimitError {
+	TooManyDice(u32),
+	TooManySides(u32),
+	TooManyRepeats(u32),
+	MacroNestingTooDeep(String, u32),
+	MacroCycle(Vec<String>),
+	TableCycle(Vec<String>),
+	AttributeCycle(Vec<String>),
+}
+impl fmt::Display for LimitError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LimitError::TooManyDice(max) => write!(f, "Rolls more than {} dice", max),
+			LimitError::TooManySides(max) => write!(f, "Dice with more than {} sides", max),
+			LimitError::TooManyRepeats(max) => write!(f, "Repeats a roll more than {} times", max),
+			LimitError::MacroNestingTooDeep(name, max) => {
+				write!(f, "Macro #{{{}}} is nested more than {} deep", name, max)
+			}
+			LimitError::MacroCycle(names) => {
+				let names: Vec<String> =
+					names.iter().map(|name| format!("#{{{}}}", name)).collect();
+				write!(f, "Macros use each other in a cycle {}", names.join(" -> "))
+			}
+			LimitError::TableCycle(names) => {
+				let names: Vec<String> = names.iter().map(|name| format!("[{}]", name)).collect();
+				write!(
+					f,
+					"Tables roll on each other in a cycle {}",
+					names.join(" -> ")
+				)
+			}
+			LimitError::AttributeCycle(names) => {
+				let names: Vec<String> =
+					names.iter().map(|name| format!("@{{{}}}", name)).collect();
+				write!(
+					f,
+					"Attributes use each other in a cycle {}",
+					names.join(" -> ")
+				)
+			}
+		}
+	}
+}
+
+pub fn check_limits(
+	input: &str,
+	context: &RollContext,
+	budget: &EvaluationBudget,
+) -> Result<(), LimitError> {
+	let mut checker = LimitChecker {
+		context,
+		budget,
+		total_dice: 0,
+		stack: Vec::new(),
+		table_stack: Vec::new(),
+		attribute_stack: Vec::new(),
+	};
+	checker.check_source(input)
+}
+
+struct LimitChecker<'m, 'b> {
+	context: &'m RollContext,
+	budget: &'b EvaluationBudget,
+	total_dice: u32,
+	stack: Vec<String>,
+	table_stack: Vec<String>,
+	attribute_stack: Vec<String>,
+}
+impl<'m, 'b> LimitChecker<'m, 'b> {
+	fn check_source(&mut self, source: &str) -> Result<(), LimitError> {
+		let mut error = None;
+		for_each_repeated_atom(source, &mut |atom, repeat| {
+			if error.is_some() {
+				return;
+			}
+			if repeat > self.budget.max_repeat {
+				error = Some(LimitError::TooManyRepeats(self.budget.max_repeat));
+				return;
+			}
+			if let Atom::Dice(Dice::Normal(normal, modifiers, _)) = atom {
+				let wild_sides = modifiers.wild.map_or(0, |sides| sides.value());
+				if normal.sides.value().max(wild_sides) > self.budget.max_sides as i32 {
+					error = Some(LimitError::TooManySides(self.budget.max_sides));
+				}
+				let dice =
+					(normal.count.value().max(0) as u32).saturating_add(modifiers.extra_dice());
+				self.total_dice = self.total_dice.saturating_add(dice.saturating_mul(repeat));
+			}
+			// Custom dice counted by a query or expression are only known
+			// once they are rolled
+			if let Atom::Dice(Dice::Custom(custom, _, _)) = atom {
+				if let Some(Atom::Number(Number::Integer(dice))) = custom.count.single_atom() {
+					let dice = (dice.value().max(0) as u32).saturating_mul(repeat);
+					self.total_dice = self.total_dice.saturating_add(dice);
+				}
+			}
+			if self.total_dice > self.budget.max_dice {
+				error = Some(LimitError::TooManyDice(self.budget.max_dice));
+			}
+		});
+		if let Some(error) = error {
+			return Err(error);
+		}
+
+		for name in macro_names(source) {
+			if self.stack.contains(&name) {
+				let mut cycle = self.stack.clone();
+				cycle.push(name);
+				return Err(LimitError::MacroCycle(cycle));
+			}
+			if self.stack.len() as u32 >= self.budget.max_macro_nesting {
+				return Err(LimitError::MacroNestingTooDeep(
+					name,
+					self.budget.max_macro_nesting,
+				));
+			}
+			// Missing macros are reported by the interpreter
+			if let Some(macro_source) = self.context.macros.get(&name) {
+				self.stack.push(name);
+				self.check_source(macro_source)?;
+				self.stack.pop();
+			}
+		}
+
+		// Only one row of a table is picked but any of them could be
+		for name in table_names(source) {
+			if self.table_stack.contains(&name) {
+				let mut cycle = self.table_stack.clone();
+				cycle.push(name);
+				return Err(LimitError::TableCycle(cycle));
+			}
+			// Missing tables are reported by the evaluator
+			if let Some(table) = self.context.tables.get(&name) {
+				self.table_stack.push(name);
+				for row in table.rows.iter() {
+					self.check_source(&row.text)?;
+				}
+				self.table_stack.pop();
+			}
+		}
+
+		// Missing attributes are reported by the evaluator
+		for (name, attribute_source) in attribute_sources(source, &self.context.attributes) {
+			if self.attribute_stack.contains(&name) {
+				let mut cycle = self.attribute_stack.clone();
+				cycle.push(name);
+				return Err(LimitError::AttributeCycle(cycle));
+			}
+			self.attribute_stack.push(name);
+			self.check_source(&attribute_source)?;
+			self.attribute_stack.pop();
+		}
+		Ok(())
+	}
+}
@@ -6,9 +6,12 @@ extern crate gtk;
 extern crate roll_lang;
 
 use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use gtk::{ButtonExt, EntryExt};
+use gtk::{ButtonExt, EntryExt, GtkMenuItemExt};
+use roll_lang::macros::Macros;
 
 pub mod main_window;
 use main_window::MainWindow;
@@ -16,32 +19,93 @@ use main_window::MainWindow;
 pub mod state;
 use state::*;
 
+pub mod output;
+use output::Output;
+
+pub mod bands;
+
+pub mod dice_library;
+
 pub mod roll;
-use roll::roll;
+use roll::{
+	face_histogram, output_marked, roll_queries, totals, QueryAnswers, RollContext, RollLibrary,
+};
+
+pub mod query_form;
+
+pub mod functions;
+
+pub mod syntax;
+
+pub mod parser;
+
+pub mod evaluator;
+
+pub mod budget;
+
+pub mod roll_worker;
+use roll_worker::RollWorker;
+
+pub mod limits;
+
+pub mod storage;
+
+pub mod character;
+
+pub mod profile;
+use profile::{HistoryEntry, Profile, ProfileBook};
+
+pub mod sheet_editor;
+
+pub mod tables;
+
+pub mod table_editor;
+
+pub mod band_editor;
+
+pub mod presets;
+
+pub mod templates;
+
+pub mod history_export;
+
+pub mod stats;
+
+pub mod stats_window;
+
+pub mod share;
+
+pub mod system_panel;
+
+pub mod dice_panel;
+
+pub mod encounter;
+use encounter::{
+	ApplyDamageCommand, Combatant, Encounter, NextTurnCommand, PreviousTurnCommand,
+	SetEncounterCommand,
+};
+
+pub mod encounter_panel;
+
+pub mod api;
 
 static APP_NAME: &str = "gRollLang";
-static OTHER_BUTTON_NAMES: [&str; 7] = [
+static OTHER_BUTTON_NAMES: [&str; 13] = [
+	"profile_new",
+	"profile_delete",
 	"roll_button",
+	"roll_cancel",
+	"export_card",
+	"copy_result",
 	"undo",
 	"redo",
 	"clear",
 	"insert_modifier",
 	"insert_ndx",
 	"insert_query",
+	"insert_inline_roll",
 ];
-static INSERT_OP_FN_BUTTONS: [(&str, &str); 11] = [
-	("insert_operator_add", "[] + []"),
-	("insert_operator_minus", "[] - []"),
-	("insert_operator_multiply", "[] * []"),
-	("insert_operator_divide", "[] / []"),
-	("insert_operator_power", "[] ^ ([])"),
-	("insert_operator_parentheses", "([])"),
-	("insert_function_floor", "floor([])"),
-	("insert_function_ceil", "ceil([])"),
-	("insert_function_round", "round([])"),
-	("insert_function_abs", "abs([])"),
-	("insert_inline_roll", "[[ [] ]]"),
-];
+static MENU_ITEM_NAMES: [&str; 2] = ["export_history", "statistics"];
 
 static OTHER_ENTRY_NAMES: [&str; 5] = [
 	"modifier_entry",
@@ -50,9 +114,20 @@ static OTHER_ENTRY_NAMES: [&str; 5] = [
 	"query_prompt_entry",
 	"query_default_entry",
 ];
-static DICE_NAMES: [&str; 7] = ["d4", "d6", "d8", "d10", "d12", "d20", "d100"];
+// Saved with the profile along with the dice counts
+static DICE_PRESET_ENTRY_NAMES: [&str; 3] = ["modifier_entry", "ndx_n_count", "ndx_x_count"];
 
 fn main() {
+	// `g_roll_lang export` exports the roll history without the window
+	let args: Vec<String> = std::env::args().collect();
+	if args.get(1).map(String::as_str) == Some("export") {
+		if let Err(e) = history_export::run(&args[2..]) {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+		return;
+	}
+
 	if gtk::init().is_err() {
 		eprintln!("Failed to initialize GTK application");
 		std::process::exit(1);
@@ -60,26 +135,69 @@ fn main() {
 
 	let gui = Arc::new(MainWindow::default());
 	let state_handler = Arc::new(RefCell::new(StateHandler::new(&State::default())));
+	let profiles = Arc::new(RefCell::new(ProfileBook::load()));
+	// What the game systems add to the rolls of every profile
+	let mut library = RollLibrary::default();
+	{
+		let presets = presets::load();
+		for preset in presets.iter() {
+			preset.add_to(&mut library);
+		}
+		// The user's dice library replaces dice of the presets with its names
+		for dice in dice_library::load().iter() {
+			library.dice.add(dice);
+		}
+		gui.system_panel().set_presets(presets);
+		for template in templates::load().into_iter() {
+			library.templates.insert(template.name.clone(), template);
+		}
+	}
+	let library = Arc::new(library);
 	{
-		gui.update_from(state_handler.borrow().current());
+		show_profile(&gui, &state_handler, &profiles.borrow());
 	}
 
+	// Rolls are evaluated on a worker thread, the result is applied here
+	let roll_worker = {
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let profiles = Arc::clone(&profiles);
+		Arc::new(RollWorker::new(move |input, output| {
+			apply_roll(&gui, &state_handler, &profiles, input, output);
+			gui.set_busy(false);
+		}))
+	};
+	// and so is initiative, which replaces the encounter
+	let initiative_worker: Rc<RollWorker<Encounter>> = {
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		Rc::new(RollWorker::new(move |_, rolled| match rolled {
+			Ok(rolled) => {
+				state_handler
+					.borrow_mut()
+					.execute(&mut Box::new(SetEncounterCommand::new(rolled)));
+				gui.update_from(state_handler.borrow().current());
+			}
+			Err(e) => show_error(&gui, &state_handler, &e),
+		}))
+	};
+
 	// Run the entry
 	{
 		let button = gui.button("roll_button");
 		let gui = Arc::clone(&gui);
 		let state_handler = Arc::clone(&state_handler);
+		let roll_worker = Arc::clone(&roll_worker);
+		let profiles = Arc::clone(&profiles);
+		let library = Arc::clone(&library);
 		button.connect_clicked(move |_| {
-			let input = gui.user_roll_entry().get_text().to_owned();
-			state_handler
-				.borrow_mut()
-				.current_mut()
-				.update_to_match_entry(gui.user_roll_entry());
-			state_handler
-				.borrow_mut()
-				.current_mut()
-				.update_from_roll_result(&roll(&input));
-			gui.update_from(state_handler.borrow().current());
+			request_roll(
+				&gui,
+				&state_handler,
+				&roll_worker,
+				profiles.borrow().active(),
+				&library,
+			);
 		});
 	}
 
@@ -87,17 +205,80 @@ fn main() {
 		let user_roll_entry = gui.user_roll_entry();
 		let gui = Arc::clone(&gui);
 		let state_handler = Arc::clone(&state_handler);
-		user_roll_entry.connect_activate(move |entry| {
-			let input = entry.get_text().to_owned();
-			state_handler
-				.borrow_mut()
-				.current_mut()
-				.update_to_match_entry(gui.user_roll_entry());
-			state_handler
-				.borrow_mut()
-				.current_mut()
-				.update_from_roll_result(&roll(&input));
-			gui.update_from(state_handler.borrow().current());
+		let roll_worker = Arc::clone(&roll_worker);
+		let profiles = Arc::clone(&profiles);
+		let library = Arc::clone(&library);
+		user_roll_entry.connect_activate(move |_| {
+			request_roll(
+				&gui,
+				&state_handler,
+				&roll_worker,
+				profiles.borrow().active(),
+				&library,
+			);
+		});
+	}
+
+	{
+		let item = gui.menu_item("export_history");
+		let gui = Arc::clone(&gui);
+		let profiles = Arc::clone(&profiles);
+		item.connect_activate(move |_| {
+			gui.export_history(&profiles.borrow());
+		});
+	}
+
+	{
+		let item = gui.menu_item("statistics");
+		let gui = Arc::clone(&gui);
+		let profiles = Arc::clone(&profiles);
+		item.connect_activate(move |_| {
+			gui.stats_window().show(&profiles.borrow());
+		});
+	}
+
+	{
+		let button = gui.button("export_card");
+		let gui = Arc::clone(&gui);
+		button.connect_clicked(move |_| {
+			gui.export_cards();
+		});
+	}
+
+	{
+		let button = gui.button("copy_result");
+		let gui = Arc::clone(&gui);
+		button.connect_clicked(move |_| {
+			gui.copy_result();
+		});
+	}
+
+	{
+		let button = gui.button("roll_cancel");
+		let roll_worker = Arc::clone(&roll_worker);
+		button.connect_clicked(move |_| {
+			roll_worker.cancel();
+		});
+	}
+
+	// Answer the roll queries and run the roll that was waiting on them
+	{
+		let button = gui.query_form().submit_button();
+		let gui = Arc::clone(&gui);
+		let roll_worker = Arc::clone(&roll_worker);
+		button.connect_clicked(move |_| {
+			if let Some((input, answers, context)) = gui.query_form().take() {
+				run_roll(&gui, &roll_worker, &input, &answers, &context);
+			}
+		});
+	}
+
+	{
+		let button = gui.query_form().cancel_button();
+		let gui = Arc::clone(&gui);
+		button.connect_clicked(move |_| {
+			gui.query_form().dismiss();
+			gui.user_roll_entry().grab_focus_without_selecting();
 		});
 	}
 
@@ -134,52 +315,48 @@ fn main() {
 		});
 	}
 
-	// Insert operators, functions, and inline rolls
-	for &(name, what_to_insert) in INSERT_OP_FN_BUTTONS.iter() {
-		let button = gui.button(name);
-		let gui = Arc::clone(&gui);
+	// Insert dice, operators and functions
+	{
+		let gui_clone = Arc::clone(&gui);
 		let state_handler = Arc::clone(&state_handler);
-		button.connect_clicked(move |_| {
+		gui.dice_panel().connect_insert(move |text| {
+			let gui = &gui_clone;
 			state_handler
 				.borrow_mut()
 				.current_mut()
 				.update_to_match_entry(gui.user_roll_entry());
 			state_handler
 				.borrow_mut()
-				.execute(&mut Box::new(InsertCommand::new(what_to_insert)));
+				.execute(&mut Box::new(InsertCommand::new(text)));
 			gui.update_from(state_handler.borrow().current());
 		});
 	}
 
-	// Insert a modifier
+	// Insert an inline roll
 	{
-		let button = gui.button("insert_modifier");
+		let button = gui.button("insert_inline_roll");
 		let gui = Arc::clone(&gui);
 		let state_handler = Arc::clone(&state_handler);
 		button.connect_clicked(move |_| {
-			let modifier = gui.entry("modifier_entry").get_text().to_owned();
-			if modifier.parse::<i32>().is_err() && modifier.parse::<f32>().is_err() {
-				return;
-			};
 			state_handler
 				.borrow_mut()
 				.current_mut()
 				.update_to_match_entry(gui.user_roll_entry());
 			state_handler
 				.borrow_mut()
-				.execute(&mut Box::new(InsertCommand::new(&modifier)));
+				.execute(&mut Box::new(InsertCommand::new("[[ [] ]]")));
 			gui.update_from(state_handler.borrow().current());
 		});
 	}
 
-	// Insert a normal dice
-	for &name in DICE_NAMES.iter() {
-		let button = gui.button(&format!("insert_{}", name));
+	// Insert a modifier
+	{
+		let button = gui.button("insert_modifier");
 		let gui = Arc::clone(&gui);
 		let state_handler = Arc::clone(&state_handler);
 		button.connect_clicked(move |_| {
-			let count = gui.entry(&format!("{}_count", name)).get_text().to_owned();
-			if count.parse::<u32>().is_err() && count.parse::<f32>().is_err() {
+			let modifier = gui.entry("modifier_entry").get_text().to_owned();
+			if modifier.parse::<i32>().is_err() && modifier.parse::<f32>().is_err() {
 				return;
 			};
 			state_handler
@@ -188,10 +365,7 @@ fn main() {
 				.update_to_match_entry(gui.user_roll_entry());
 			state_handler
 				.borrow_mut()
-				.execute(&mut Box::new(InsertCommand::new(&format!(
-					"{}{}",
-					count, name,
-				))));
+				.execute(&mut Box::new(InsertCommand::new(&modifier)));
 			gui.update_from(state_handler.borrow().current());
 		});
 	}
@@ -246,6 +420,594 @@ fn main() {
 		});
 	}
 
+	// Profiles
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let roll_worker = Arc::clone(&roll_worker);
+		let initiative_worker = Rc::clone(&initiative_worker);
+		let profiles = Arc::clone(&profiles);
+		gui.connect_profile_selected(move |index| {
+			let gui = &gui_clone;
+			let mut profiles = profiles.borrow_mut();
+			initiative_worker.abandon();
+			leave_profile(gui, &state_handler, &roll_worker, &mut profiles);
+			profiles.select(index, &mut state_handler.borrow_mut());
+			show_profile(gui, &state_handler, &profiles);
+		});
+	}
+
+	{
+		let button = gui.button("profile_new");
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let roll_worker = Arc::clone(&roll_worker);
+		let initiative_worker = Rc::clone(&initiative_worker);
+		let profiles = Arc::clone(&profiles);
+		button.connect_clicked(move |_| {
+			let mut profiles = profiles.borrow_mut();
+			initiative_worker.abandon();
+			leave_profile(&gui, &state_handler, &roll_worker, &mut profiles);
+			profiles.add(Profile::default(), &mut state_handler.borrow_mut());
+			show_profile(&gui, &state_handler, &profiles);
+		});
+	}
+
+	{
+		let button = gui.button("profile_delete");
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let roll_worker = Arc::clone(&roll_worker);
+		let initiative_worker = Rc::clone(&initiative_worker);
+		let profiles = Arc::clone(&profiles);
+		button.connect_clicked(move |_| {
+			let mut profiles = profiles.borrow_mut();
+			roll_worker.abandon();
+			initiative_worker.abandon();
+			gui.set_busy(false);
+			gui.query_form().dismiss();
+			let removed = profiles.remove_active(&mut state_handler.borrow_mut());
+			show_profile(&gui, &state_handler, &profiles);
+			if let Err(e) = removed {
+				show_error(
+					&gui,
+					&state_handler,
+					&format!("Could not delete profile: {}", e),
+				);
+			}
+		});
+	}
+
+	// The character sheet
+	{
+		let button = gui.sheet_editor().save_button();
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let profiles = Arc::clone(&profiles);
+		button.connect_clicked(move |_| {
+			let mut profiles = profiles.borrow_mut();
+			profiles.active_mut().dice_preset = gui.dice_preset();
+			let saved = profiles.save_sheet(
+				gui.sheet_editor().character(),
+				gui.sheet_editor().macros(),
+				state_handler.borrow().current(),
+			);
+			gui.set_profiles(&profiles.names(), profiles.active_index());
+			if let Err(e) = saved {
+				show_error(
+					&gui,
+					&state_handler,
+					&format!("Could not save profile: {}", e),
+				);
+			}
+		});
+	}
+
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		gui.sheet_editor().connect_insert(move |name| {
+			let gui = &gui_clone;
+			state_handler
+				.borrow_mut()
+				.current_mut()
+				.update_to_match_entry(gui.user_roll_entry());
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(InsertCommand::new(name)));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	// Roll tables
+	{
+		let button = gui.table_editor().save_button();
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let profiles = Arc::clone(&profiles);
+		button.connect_clicked(move |_| {
+			let saved = profiles.borrow_mut().save_tables(
+				gui.table_editor().tables(),
+				state_handler.borrow().current(),
+			);
+			if let Err(e) = saved {
+				show_error(
+					&gui,
+					&state_handler,
+					&format!("Could not save profile: {}", e),
+				);
+			}
+		});
+	}
+
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		gui.table_editor().connect_insert(move |roll| {
+			let gui = &gui_clone;
+			state_handler
+				.borrow_mut()
+				.current_mut()
+				.update_to_match_entry(gui.user_roll_entry());
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(InsertCommand::new(roll)));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	// Result bands
+	{
+		let button = gui.band_editor().save_button();
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let profiles = Arc::clone(&profiles);
+		button.connect_clicked(move |_| {
+			let mut profiles = profiles.borrow_mut();
+			let saved =
+				profiles.save_bands(gui.band_editor().tables(), state_handler.borrow().current());
+			if let Err(e) = saved {
+				show_error(
+					&gui,
+					&state_handler,
+					&format!("Could not save profile: {}", e),
+				);
+			}
+		});
+	}
+
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		gui.band_editor().connect_insert(move |text| {
+			let gui = &gui_clone;
+			state_handler
+				.borrow_mut()
+				.current_mut()
+				.update_to_match_entry(gui.user_roll_entry());
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(InsertCommand::new(text)));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	// Game systems
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let profiles = Arc::clone(&profiles);
+		gui.system_panel().connect_selected(move |preset| {
+			let gui = &gui_clone;
+			let dice = preset.map(|preset| preset.dice.clone()).unwrap_or_default();
+			gui.dice_panel().show_dice(&dice);
+			let mut profiles = profiles.borrow_mut();
+			profiles.active_mut().system =
+				preset.map(|preset| preset.name.clone()).unwrap_or_default();
+			if let Err(e) = profiles.save_active(state_handler.borrow().current()) {
+				show_error(
+					gui,
+					&state_handler,
+					&format!("Could not save profile: {}", e),
+				);
+			}
+		});
+	}
+
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		gui.system_panel().connect_insert(move |text| {
+			let gui = &gui_clone;
+			state_handler
+				.borrow_mut()
+				.current_mut()
+				.update_to_match_entry(gui.user_roll_entry());
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(InsertCommand::new(text)));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	// Macros already on the sheet are kept
+	{
+		let button = gui.system_panel().add_macros_button();
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		let profiles = Arc::clone(&profiles);
+		button.connect_clicked(move |_| {
+			let preset = match gui.system_panel().selected() {
+				Some(preset) => preset,
+				None => return,
+			};
+			let character = gui.sheet_editor().character();
+			let mut macros = gui.sheet_editor().macros();
+			for (name, source) in preset.macros.into_iter() {
+				macros.entry(name).or_insert(source);
+			}
+			gui.sheet_editor().show(&character, &macros);
+			let mut profiles = profiles.borrow_mut();
+			let saved = profiles.save_sheet(character, macros, state_handler.borrow().current());
+			gui.set_profiles(&profiles.names(), profiles.active_index());
+			if let Err(e) = saved {
+				show_error(
+					&gui,
+					&state_handler,
+					&format!("Could not save profile: {}", e),
+				);
+			}
+		});
+	}
+
+	// The initiative tracker
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		gui.encounter_panel().connect_changed(move || {
+			state_handler.borrow_mut().current_mut().encounter =
+				gui_clone.encounter_panel().encounter();
+		});
+	}
+
+	{
+		let gui_clone = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		gui.encounter_panel().connect_remove(move |index| {
+			let gui = &gui_clone;
+			let mut encounter = gui.encounter_panel().encounter();
+			if index >= encounter.combatants.len() {
+				return;
+			}
+			encounter.combatants.remove(index);
+			if index < encounter.turn {
+				encounter.turn -= 1;
+			}
+			encounter.turn = encounter
+				.turn
+				.min(encounter.combatants.len().saturating_sub(1));
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(SetEncounterCommand::new(encounter)));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	{
+		let button = gui.encounter_panel().add_button();
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		button.connect_clicked(move |_| {
+			let mut encounter = gui.encounter_panel().encounter();
+			encounter.combatants.push(Combatant::default());
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(SetEncounterCommand::new(encounter)));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	{
+		let button = gui.encounter_panel().roll_button();
+		let gui = Arc::clone(&gui);
+		let initiative_worker = Rc::clone(&initiative_worker);
+		let profiles = Arc::clone(&profiles);
+		let library = Arc::clone(&library);
+		button.connect_clicked(move |_| {
+			let context = profiles.borrow().active().roll_context(&library);
+			let encounter = gui.encounter_panel().encounter();
+			let budget = gui.evaluation_budget();
+			initiative_worker.run("initiative", move |cancelled| {
+				encounter.rolled(&context, budget, cancelled)
+			});
+		});
+	}
+
+	{
+		let button = gui.encounter_panel().next_button();
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		button.connect_clicked(move |_| {
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(NextTurnCommand {}));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	{
+		let button = gui.encounter_panel().previous_button();
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		button.connect_clicked(move |_| {
+			state_handler
+				.borrow_mut()
+				.execute(&mut Box::new(PreviousTurnCommand {}));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	// Hit points
+	{
+		let gui_clone = Arc::clone(&gui);
+		let profiles = Arc::clone(&profiles);
+		gui.connect_history_activated(move |index| {
+			let profiles = profiles.borrow();
+			let total = profiles
+				.active()
+				.history()
+				.get(index)
+				.and_then(|entry| entry.totals.last());
+			if let Some(&total) = total {
+				gui_clone.encounter_panel().set_amount(total);
+			}
+		});
+	}
+
+	for &healing in [false, true].iter() {
+		let panel = gui.encounter_panel();
+		let button = if healing {
+			panel.heal_button()
+		} else {
+			panel.damage_button()
+		};
+		let gui = Arc::clone(&gui);
+		let state_handler = Arc::clone(&state_handler);
+		button.connect_clicked(move |_| {
+			let panel = gui.encounter_panel();
+			let selected = panel.selected();
+			let command = if healing {
+				ApplyDamageCommand::healing(panel.amount(), selected)
+			} else {
+				ApplyDamageCommand::damage(panel.amount(), panel.damage_modifier(), selected)
+			};
+			state_handler.borrow_mut().execute(&mut Box::new(command));
+			gui.update_from(state_handler.borrow().current());
+		});
+	}
+
+	// `g_roll_lang --api` answers the HTTP API on this computer,
+	// `--api=address` on another address
+	let api_address = args.iter().find_map(|arg| match arg.as_str() {
+		"--api" => Some(api::DEFAULT_ADDRESS.to_owned()),
+		arg => arg.strip_prefix("--api=").map(str::to_owned),
+	});
+	if let Some(address) = api_address {
+		let window = ApiWindow {
+			gui: Arc::clone(&gui),
+			state_handler: Arc::clone(&state_handler),
+			profiles: Arc::clone(&profiles),
+			library: Arc::clone(&library),
+		};
+		let started = api::start(&address, window);
+		match started {
+			Ok(address) => println!("The API is at http://{}", address),
+			Err(e) => eprintln!("Could not start the API on {}: {}", address, e),
+		}
+	}
+
 	gui.start();
 	gtk::main();
+
+	let mut profiles = profiles.borrow_mut();
+	profiles.active_mut().dice_preset = gui.dice_preset();
+	let saved = profiles.save_active(state_handler.borrow().current());
+	if let Err(e) = saved {
+		eprintln!("Could not save profile: {}", e);
+	}
+}
+
+// Rolls the entry right away, or asks for its roll queries first.
+fn request_roll(
+	gui: &MainWindow,
+	state_handler: &RefCell<StateHandler>,
+	roll_worker: &RollWorker,
+	profile: &Profile,
+	library: &RollLibrary,
+) {
+	state_handler
+		.borrow_mut()
+		.current_mut()
+		.update_to_match_entry(gui.user_roll_entry());
+
+	let input = gui.user_roll_entry().get_text().to_string();
+	let context = profile.roll_context(library);
+	let queries = roll_queries(&input, &context);
+	if queries.is_empty() {
+		run_roll(gui, roll_worker, &input, &QueryAnswers::new(), &context);
+	} else {
+		gui.query_form().ask(&input, &queries, &context);
+	}
+}
+// Shows the result of a roll and keeps it in the history of the active
+// profile. The entry that was kept is returned.
+fn apply_roll(
+	gui: &MainWindow,
+	state_handler: &RefCell<StateHandler>,
+	profiles: &RefCell<ProfileBook>,
+	input: String,
+	output: Result<Output, String>,
+) -> HistoryEntry {
+	let (result, marks, totals, dice, faces, cards) = match &output {
+		Ok(output) => {
+			let marked = output_marked(output, gui.text_options());
+			(
+				Ok(marked.text),
+				marked.marks,
+				totals(output),
+				output.dice.clone(),
+				face_histogram(output),
+				templates::cards(&output.fragments)
+					.into_iter()
+					.cloned()
+					.collect(),
+			)
+		}
+		Err(e) => (
+			Err(e.clone()),
+			Vec::new(),
+			Vec::new(),
+			Vec::new(),
+			String::new(),
+			Vec::new(),
+		),
+	};
+	state_handler
+		.borrow_mut()
+		.current_mut()
+		.update_from_roll_result(&result, &marks);
+	gui.update_from(state_handler.borrow().current());
+	gui.set_faces(&faces);
+	gui.set_cards(cards);
+	gui.set_last_output(output.ok());
+
+	if let Some(&total) = totals.last() {
+		gui.encounter_panel().set_amount(total);
+	}
+	let entry = HistoryEntry {
+		input,
+		result,
+		marks,
+		totals,
+		dice,
+		time: SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.ok()
+			.map(|since| since.as_secs() as i64),
+	};
+	gui.push_history(&entry);
+	let mut profiles = profiles.borrow_mut();
+	profiles.active_mut().push_history(entry.clone());
+	if let Err(e) = profiles.save_active(state_handler.borrow().current()) {
+		eprintln!("Could not save profile: {}", e);
+	}
+	entry
+}
+fn run_roll(
+	gui: &MainWindow,
+	roll_worker: &RollWorker,
+	input: &str,
+	answers: &QueryAnswers,
+	context: &RollContext,
+) {
+	gui.set_busy(true);
+	roll_worker.start(
+		input,
+		answers,
+		context,
+		gui.evaluation_budget(),
+		gui.roll_mode(),
+	);
+}
+// The window as the HTTP API sees it. Its rolls are run with the macros,
+// sheet and roll mode of the active profile like rolls of the entry, and
+// shown and kept the same way.
+struct ApiWindow {
+	gui: Arc<MainWindow>,
+	state_handler: Arc<RefCell<StateHandler>>,
+	profiles: Arc<RefCell<ProfileBook>>,
+	library: Arc<RollLibrary>,
+}
+impl api::Window for ApiWindow {
+	fn roll_setup(&self) -> api::RollSetup {
+		api::RollSetup {
+			context: self.profiles.borrow().active().roll_context(&self.library),
+			budget: self.gui.evaluation_budget(),
+			mode: self.gui.roll_mode(),
+		}
+	}
+	fn add_roll(&mut self, input: String, output: Result<Output, String>) -> HistoryEntry {
+		// What is being typed in the entry is kept
+		self.state_handler
+			.borrow_mut()
+			.current_mut()
+			.update_to_match_entry(self.gui.user_roll_entry());
+		apply_roll(
+			&self.gui,
+			&self.state_handler,
+			&self.profiles,
+			input,
+			output,
+		)
+	}
+	fn history(&self, character: Option<&str>) -> Option<(String, Vec<HistoryEntry>)> {
+		let profiles = self.profiles.borrow();
+		let profile = match character {
+			Some(name) => profiles.profiles().iter().find(|p| p.name() == name)?,
+			None => profiles.active(),
+		};
+		Some((profile.name().to_owned(), profile.history().to_vec()))
+	}
+	fn macros(&self) -> Macros {
+		self.profiles.borrow().active().macros.clone()
+	}
+	fn set_macros(&mut self, macros: Macros) -> Result<Macros, String> {
+		let mut profiles = self.profiles.borrow_mut();
+		let character = profiles.active().character.clone();
+		self.gui.sheet_editor().show(&character, &macros);
+		profiles
+			.save_sheet(character, macros, self.state_handler.borrow().current())
+			.map_err(|e| e.to_string())?;
+		Ok(profiles.active().macros.clone())
+	}
+}
+// Stops anything still running for the active profile and keeps its dice
+// panel before another profile is shown.
+fn leave_profile(
+	gui: &MainWindow,
+	state_handler: &RefCell<StateHandler>,
+	roll_worker: &RollWorker,
+	profiles: &mut ProfileBook,
+) {
+	roll_worker.abandon();
+	gui.set_busy(false);
+	gui.query_form().dismiss();
+	profiles.active_mut().dice_preset = gui.dice_preset();
+	if let Err(e) = profiles.save_active(state_handler.borrow().current()) {
+		eprintln!("Could not save profile: {}", e);
+	}
+}
+fn show_profile(gui: &MainWindow, state_handler: &RefCell<StateHandler>, profiles: &ProfileBook) {
+	let profile = profiles.active();
+	gui.set_profiles(&profiles.names(), profiles.active_index());
+	gui.sheet_editor().show(&profile.character, &profile.macros);
+	gui.table_editor().show(&profile.tables);
+	gui.band_editor().show(&profile.bands);
+	gui.system_panel().select(&profile.system);
+	gui.dice_panel().show_dice(
+		&gui.system_panel()
+			.selected()
+			.map(|preset| preset.dice)
+			.unwrap_or_default(),
+	);
+	gui.set_dice_preset(&profile.dice_preset);
+	gui.show_history(profile.history());
+	gui.update_from(state_handler.borrow().current());
+}
+fn show_error(gui: &MainWindow, state_handler: &RefCell<StateHandler>, error: &str) {
+	state_handler
+		.borrow_mut()
+		.current_mut()
+		.update_from_roll_result(&Err(error.to_owned()), &[]);
+	gui.update_from(state_handler.borrow().current());
 }
@@ -5,17 +5,71 @@
 use crate::gtk::prelude::*;
 use gtk::*;
 
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
 
+use super::band_editor::BandEditor;
+use super::budget::EvaluationBudget;
+use super::dice_panel::DicePanel;
+use super::encounter_panel::EncounterPanel;
+use super::evaluator::{Advantage, RollMode};
+use super::history_export::{self, DateRange, ExportFormat};
+use super::output::{Card, Mark, MarkStyle, MarkedText, Output, OutputFragment};
+use super::profile::{DicePreset, HistoryEntry, Profile, ProfileBook};
+use super::query_form::QueryForm;
+use super::roll::{fragments_marked, fragments_string, TextOptions};
+use super::share::{self, ShareFormat};
+use super::sheet_editor::SheetEditor;
+use super::stats_window::StatsWindow;
+use super::system_panel::SystemPanel;
+use super::table_editor::TableEditor;
+use super::templates;
 use super::State;
-use super::{APP_NAME, DICE_NAMES, INSERT_OP_FN_BUTTONS, OTHER_BUTTON_NAMES, OTHER_ENTRY_NAMES};
+use super::{
+	APP_NAME, DICE_PRESET_ENTRY_NAMES, MENU_ITEM_NAMES, OTHER_BUTTON_NAMES, OTHER_ENTRY_NAMES,
+};
 
 pub struct MainWindow {
 	window: gtk::Window,
 	result: gtk::Label,
+	faces: gtk::Label,
+	// The cards of the last roll, for exporting
+	cards: Rc<RefCell<Vec<Card>>>,
+	// The last roll, for copying
+	last_output: Rc<RefCell<Option<Output>>>,
+	copy_format: gtk::ComboBoxText,
 	user_roll_entry: gtk::Entry,
+	roll_spinner: gtk::Spinner,
+	profile_selector: gtk::ComboBoxText,
+	// Set while the selector is filled so it does not report a selection
+	updating_profiles: Rc<Cell<bool>>,
+	history_list: gtk::ListBox,
+	roll_mode_advantage: gtk::RadioButton,
+	roll_mode_disadvantage: gtk::RadioButton,
+	roll_mode_twice: gtk::CheckButton,
+	group_faces: gtk::CheckButton,
+	repeat_summary: gtk::CheckButton,
+	budget_max_dice: gtk::SpinButton,
+	budget_max_sides: gtk::SpinButton,
+	budget_max_explosions: gtk::SpinButton,
+	budget_max_macro_nesting: gtk::SpinButton,
+	budget_max_repeat: gtk::SpinButton,
+	budget_max_milliseconds: gtk::SpinButton,
 	entries: HashMap<String, gtk::Entry>,
 	buttons: HashMap<String, gtk::Button>,
+	menu_items: HashMap<String, gtk::MenuItem>,
+	dice_panel: DicePanel,
+	query_form: QueryForm,
+	sheet_editor: SheetEditor,
+	table_editor: TableEditor,
+	band_editor: BandEditor,
+	system_panel: SystemPanel,
+	encounter_panel: EncounterPanel,
+	stats_window: StatsWindow,
 }
 impl Default for MainWindow {
 	fn default() -> Self {
@@ -30,48 +84,138 @@ impl Default for MainWindow {
 		let result = builder
 			.get_object("roll_result")
 			.expect("Could not get label roll_result");
+		let faces = builder
+			.get_object("roll_faces")
+			.expect("Could not get label roll_faces");
+		let copy_format = builder
+			.get_object("copy_format")
+			.expect("Could not get combo box copy_format.");
 
 		let user_roll_entry = builder
 			.get_object("user_roll_entry")
 			.expect("Could not get entry user_roll_entry.");
 
-		let buttons = INSERT_OP_FN_BUTTONS
+		let roll_spinner = builder
+			.get_object("roll_spinner")
+			.expect("Could not get spinner roll_spinner.");
+
+		let profile_selector = builder
+			.get_object("profile_selector")
+			.expect("Could not get combo box profile_selector.");
+		let history_list = builder
+			.get_object("history_list")
+			.expect("Could not get list box history_list.");
+
+		let roll_mode_advantage = builder
+			.get_object("roll_mode_advantage")
+			.expect("Could not get radio button roll_mode_advantage.");
+		let roll_mode_disadvantage = builder
+			.get_object("roll_mode_disadvantage")
+			.expect("Could not get radio button roll_mode_disadvantage.");
+		let roll_mode_twice = builder
+			.get_object("roll_mode_twice")
+			.expect("Could not get check button roll_mode_twice.");
+		let group_faces = builder
+			.get_object("group_faces")
+			.expect("Could not get check button group_faces.");
+		let repeat_summary = builder
+			.get_object("repeat_summary")
+			.expect("Could not get check button repeat_summary.");
+
+		let budget_max_dice = builder
+			.get_object("budget_max_dice")
+			.expect("Could not get spin button budget_max_dice.");
+		let budget_max_sides = builder
+			.get_object("budget_max_sides")
+			.expect("Could not get spin button budget_max_sides.");
+		let budget_max_explosions = builder
+			.get_object("budget_max_explosions")
+			.expect("Could not get spin button budget_max_explosions.");
+		let budget_max_macro_nesting = builder
+			.get_object("budget_max_macro_nesting")
+			.expect("Could not get spin button budget_max_macro_nesting.");
+		let budget_max_repeat = builder
+			.get_object("budget_max_repeat")
+			.expect("Could not get spin button budget_max_repeat.");
+		let budget_max_milliseconds = builder
+			.get_object("budget_max_milliseconds")
+			.expect("Could not get spin button budget_max_milliseconds.");
+
+		let buttons = OTHER_BUTTON_NAMES
 			.iter()
-			.map(|&(name, _)| name.to_owned())
-			.chain(OTHER_BUTTON_NAMES.iter().map(|&name| name.to_owned()))
-			.chain(DICE_NAMES.iter().map(|d| format!("insert_{}", d)))
-			.map(|name: String| {
+			.map(|&name| {
 				(
-					name.clone(),
+					name.to_owned(),
 					builder
-						.get_object(&name)
+						.get_object(name)
 						.unwrap_or_else(|| panic!("Could not get button: {}", name)),
 				)
 			})
 			.collect();
 
-		let entries = DICE_NAMES
+		let menu_items = MENU_ITEM_NAMES
+			.iter()
+			.map(|&name| {
+				let item = builder
+					.get_object(name)
+					.unwrap_or_else(|| panic!("Could not get menu item {}", name));
+				(name.to_owned(), item)
+			})
+			.collect();
+
+		let entries = OTHER_ENTRY_NAMES
 			.iter()
-			.map(|&dice_name| format!("{}_count", dice_name))
-			.chain(
-				OTHER_ENTRY_NAMES
-					.iter()
-					.map(|&entry_name| entry_name.to_owned()),
-			)
-			.map(|name| {
+			.map(|&name| {
 				let entry = builder
-					.get_object(&name)
-					.unwrap_or_else(|| panic!("Could not get entry {}", &name));
-				(name, entry)
+					.get_object(name)
+					.unwrap_or_else(|| panic!("Could not get entry {}", name));
+				(name.to_owned(), entry)
 			})
 			.collect();
 
+		let dice_panel = DicePanel::new(&builder);
+		let query_form = QueryForm::new(&builder);
+		let sheet_editor = SheetEditor::new(&builder);
+		let table_editor = TableEditor::new(&builder);
+		let band_editor = BandEditor::new(&builder);
+		let system_panel = SystemPanel::new(&builder);
+		let encounter_panel = EncounterPanel::new(&builder);
+		let stats_window = StatsWindow::new(&window);
+
 		MainWindow {
 			window,
 			result,
+			faces,
+			cards: Rc::new(RefCell::new(Vec::new())),
+			last_output: Rc::new(RefCell::new(None)),
+			copy_format,
 			user_roll_entry,
+			roll_spinner,
+			profile_selector,
+			updating_profiles: Rc::new(Cell::new(false)),
+			history_list,
+			roll_mode_advantage,
+			roll_mode_disadvantage,
+			roll_mode_twice,
+			group_faces,
+			repeat_summary,
+			budget_max_dice,
+			budget_max_sides,
+			budget_max_explosions,
+			budget_max_macro_nesting,
+			budget_max_repeat,
+			budget_max_milliseconds,
 			entries,
 			buttons,
+			menu_items,
+			dice_panel,
+			query_form,
+			sheet_editor,
+			table_editor,
+			band_editor,
+			system_panel,
+			encounter_panel,
+			stats_window,
 		}
 	}
 }
@@ -89,11 +233,357 @@ impl MainWindow {
 			Ok(ref val) => val,
 			Err(ref err) => err,
 		};
-		self.result.set_text(val);
+		self.result
+			.set_markup(&result_markup(val, state.roll_marks()));
 		self.user_roll_entry.set_text(&state.roll_entry);
 		self.user_roll_entry
 			.set_position(state.roll_entry_cursor as i32);
 		self.user_roll_entry.grab_focus_without_selecting();
+		self.encounter_panel.show(&state.encounter);
+	}
+
+	pub fn set_busy(&self, busy: bool) {
+		let cancel = self.button("roll_cancel");
+		if busy {
+			self.roll_spinner.show();
+			self.roll_spinner.start();
+			cancel.show();
+		} else {
+			self.roll_spinner.stop();
+			self.roll_spinner.hide();
+			cancel.hide();
+		}
+		self.button("roll_button").set_sensitive(!busy);
+	}
+	pub fn evaluation_budget(&self) -> EvaluationBudget {
+		EvaluationBudget {
+			max_dice: self.budget_max_dice.get_value_as_int() as u32,
+			max_sides: self.budget_max_sides.get_value_as_int() as u32,
+			max_explosions: self.budget_max_explosions.get_value_as_int() as u32,
+			max_macro_nesting: self.budget_max_macro_nesting.get_value_as_int() as u32,
+			max_repeat: self.budget_max_repeat.get_value_as_int() as u32,
+			max_time: Duration::from_millis(self.budget_max_milliseconds.get_value_as_int() as u64),
+		}
+	}
+	pub fn roll_mode(&self) -> RollMode {
+		let advantage = if self.roll_mode_advantage.get_active() {
+			Advantage::Advantage
+		} else if self.roll_mode_disadvantage.get_active() {
+			Advantage::Disadvantage
+		} else {
+			Advantage::Normal
+		};
+		RollMode {
+			advantage,
+			roll_twice: self.roll_mode_twice.get_active(),
+		}
+	}
+	pub fn text_options(&self) -> TextOptions {
+		TextOptions {
+			group_faces: self.group_faces.get_active(),
+			repeat_summary: self.repeat_summary.get_active(),
+		}
+	}
+	// The export button is shown when the last roll has cards.
+	pub fn set_cards(&self, cards: Vec<Card>) {
+		let export = self.button("export_card");
+		match cards.is_empty() {
+			true => export.hide(),
+			false => export.show(),
+		}
+		*self.cards.borrow_mut() = cards;
+	}
+	// Saves the cards of the last roll as text, HTML or a PNG image,
+	// depending on the extension of the chosen file.
+	pub fn export_cards(&self) {
+		let dialog = gtk::FileChooserDialog::with_buttons(
+			Some("Export Card"),
+			Some(&self.window),
+			FileChooserAction::Save,
+			&[
+				("_Cancel", ResponseType::Cancel),
+				("_Save", ResponseType::Accept),
+			],
+		);
+		dialog.set_do_overwrite_confirmation(true);
+		dialog.set_current_name("card.html");
+		for &(name, pattern) in [
+			("HTML pages", "*.html"),
+			("PNG images", "*.png"),
+			("Text files", "*.txt"),
+		]
+		.iter()
+		{
+			let filter = gtk::FileFilter::new();
+			filter.set_name(Some(name));
+			filter.add_pattern(pattern);
+			dialog.add_filter(&filter);
+		}
+		let path = match dialog.run() {
+			ResponseType::Accept => dialog.get_filename(),
+			_ => None,
+		};
+		unsafe {
+			dialog.destroy();
+		}
+		let path = match path {
+			Some(path) => path,
+			None => return,
+		};
+		if let Err(e) = self.write_cards(&path) {
+			self.show_error(&format!("Could not export {}: {}", path.display(), e));
+		}
+	}
+	fn write_cards(&self, path: &Path) -> Result<(), String> {
+		let options = self.text_options();
+		let cards = self.cards.borrow();
+		let extension = path
+			.extension()
+			.map(|extension| extension.to_string_lossy().to_lowercase())
+			.unwrap_or_default();
+		let contents = match extension.as_str() {
+			"html" | "htm" => {
+				let cards: Vec<&Card> = cards.iter().collect();
+				templates::cards_html(&cards, options).into_bytes()
+			}
+			"png" => cards_png(&cards, options)?,
+			_ => {
+				let fragments: Vec<OutputFragment> =
+					cards.iter().cloned().map(OutputFragment::Card).collect();
+				fragments_string(&fragments, options).into_bytes()
+			}
+		};
+		fs::write(path, contents).map_err(|e| e.to_string())
+	}
+
+	// The copy button is shown when the last roll succeeded.
+	pub fn set_last_output(&self, output: Option<Output>) {
+		let copy = self.button("copy_result");
+		match output.is_some() {
+			true => {
+				copy.show();
+				self.copy_format.show();
+			}
+			false => {
+				copy.hide();
+				self.copy_format.hide();
+			}
+		}
+		*self.last_output.borrow_mut() = output;
+	}
+	// Puts the last roll on the clipboard in the chosen format, as rich text
+	// for the programs that take it and as text for the others.
+	pub fn copy_result(&self) {
+		let last_output = self.last_output.borrow();
+		let output = match last_output.as_ref() {
+			Some(output) => output,
+			None => return,
+		};
+		let format = self
+			.copy_format
+			.get_active_id()
+			.and_then(|id| ShareFormat::from_name(&id))
+			.unwrap_or(ShareFormat::Plain);
+		let options = self.text_options();
+		let text = share::share_text(output, format, options);
+		let html = share::share_html(output, format, options);
+
+		let clipboard = match gtk::Clipboard::get_default(&self.window.get_display()) {
+			Some(clipboard) => clipboard,
+			None => return,
+		};
+		// The info of the HTML target is 0, of the text ones 1
+		let targets = [
+			gtk::TargetEntry::new("text/html", TargetFlags::empty(), 0),
+			gtk::TargetEntry::new("UTF8_STRING", TargetFlags::empty(), 1),
+			gtk::TargetEntry::new("text/plain;charset=utf-8", TargetFlags::empty(), 1),
+			gtk::TargetEntry::new("text/plain", TargetFlags::empty(), 1),
+			gtk::TargetEntry::new("STRING", TargetFlags::empty(), 1),
+			gtk::TargetEntry::new("TEXT", TargetFlags::empty(), 1),
+		];
+		clipboard.set_with_data(&targets, move |_, data, info| match info {
+			0 => data.set(&data.get_target(), 8, html.as_bytes()),
+			_ => {
+				data.set_text(&text);
+			}
+		});
+	}
+
+	// Asks for a file and the days to export. The format is taken from the
+	// extension of the file.
+	pub fn export_history(&self, profiles: &ProfileBook) {
+		let dialog = gtk::FileChooserDialog::with_buttons(
+			Some("Export History"),
+			Some(&self.window),
+			FileChooserAction::Save,
+			&[
+				("_Cancel", ResponseType::Cancel),
+				("_Export", ResponseType::Accept),
+			],
+		);
+		dialog.set_do_overwrite_confirmation(true);
+		dialog.set_current_name("history.md");
+		for &(name, pattern) in [
+			("Markdown files", "*.md"),
+			("CSV files", "*.csv"),
+			("HTML pages", "*.html"),
+		]
+		.iter()
+		{
+			let filter = gtk::FileFilter::new();
+			filter.set_name(Some(name));
+			filter.add_pattern(pattern);
+			dialog.add_filter(&filter);
+		}
+		let first = gtk::Entry::new();
+		first.set_placeholder_text(Some("2020-01-01"));
+		first.set_tooltip_text(Some("The first day to export, empty for the first entry"));
+		let last = gtk::Entry::new();
+		last.set_placeholder_text(Some("2020-01-31"));
+		last.set_tooltip_text(Some("The last day to export, empty for the last entry"));
+		let every_character = gtk::CheckButton::with_label("Every character");
+		let range = gtk::Box::new(Orientation::Horizontal, 5);
+		range.pack_start(&gtk::Label::new(Some("From")), false, true, 0);
+		range.pack_start(&first, false, true, 0);
+		range.pack_start(&gtk::Label::new(Some("to")), false, true, 0);
+		range.pack_start(&last, false, true, 0);
+		range.pack_start(&every_character, false, true, 0);
+		range.show_all();
+		dialog.set_extra_widget(&range);
+
+		let path = match dialog.run() {
+			ResponseType::Accept => dialog.get_filename(),
+			_ => None,
+		};
+		let (first, last) = (first.get_text(), last.get_text());
+		let every_character = every_character.get_active();
+		unsafe {
+			dialog.destroy();
+		}
+		let path = match path {
+			Some(path) => path,
+			None => return,
+		};
+
+		let format = match path
+			.extension()
+			.and_then(|extension| ExportFormat::from_name(&extension.to_string_lossy()))
+		{
+			Some(format) => format,
+			None => {
+				self.show_error("Export to a file ending in .md, .csv or .html");
+				return;
+			}
+		};
+		let range = match DateRange::parse(&first, &last) {
+			Ok(range) => range,
+			Err(e) => {
+				self.show_error(&e);
+				return;
+			}
+		};
+		let exported: Vec<&Profile> = match every_character {
+			true => profiles.profiles().iter().collect(),
+			false => vec![profiles.active()],
+		};
+		let export = history_export::export(&history_export::rows(&exported, range), format);
+		if let Err(e) = history_export::write(&export, Some(&path)) {
+			self.show_error(&e);
+		}
+	}
+	fn show_error(&self, text: &str) {
+		let message = gtk::MessageDialog::new(
+			Some(&self.window),
+			DialogFlags::MODAL,
+			MessageType::Error,
+			ButtonsType::Close,
+			text,
+		);
+		message.run();
+		unsafe {
+			message.destroy();
+		}
+	}
+
+	// Shown under the result of the last roll.
+	pub fn set_faces(&self, histogram: &str) {
+		self.faces.set_text(histogram);
+	}
+
+	pub fn set_profiles(&self, names: &[String], active: usize) {
+		self.updating_profiles.set(true);
+		self.profile_selector.remove_all();
+		for name in names.iter() {
+			self.profile_selector.append_text(name);
+		}
+		self.profile_selector.set_active(Some(active as u32));
+		self.updating_profiles.set(false);
+	}
+	// Called with the index of the profile picked in the header bar.
+	pub fn connect_profile_selected<F: Fn(usize) + 'static>(&self, f: F) {
+		let updating = Rc::clone(&self.updating_profiles);
+		self.profile_selector.connect_changed(move |combo| {
+			if updating.get() {
+				return;
+			}
+			if let Some(index) = combo.get_active() {
+				f(index as usize);
+			}
+		});
+	}
+
+	pub fn dice_preset(&self) -> DicePreset {
+		let mut preset = self.dice_panel.counts();
+		for &name in DICE_PRESET_ENTRY_NAMES.iter() {
+			preset.insert(name.to_owned(), self.entry(name).get_text().to_string());
+		}
+		preset
+	}
+	// Entries missing from the preset are left as they are.
+	pub fn set_dice_preset(&self, preset: &DicePreset) {
+		for (name, value) in preset.iter() {
+			if let Some(entry) = self.entries.get(name) {
+				entry.set_text(value);
+			}
+		}
+		self.dice_panel.set_counts(preset);
+	}
+
+	// Newest rolls are shown first.
+	pub fn show_history(&self, history: &[HistoryEntry]) {
+		for row in self.history_list.get_children() {
+			self.history_list.remove(&row);
+		}
+		for entry in history.iter() {
+			self.push_history(entry);
+		}
+	}
+	// Called with the index into the history of the roll that was clicked.
+	pub fn connect_history_activated<F: Fn(usize) + 'static>(&self, f: F) {
+		self.history_list.connect_row_activated(move |list, row| {
+			let newest_first = row.get_index().max(0) as usize;
+			let count = list.get_children().len();
+			if newest_first < count {
+				f(count - 1 - newest_first);
+			}
+		});
+	}
+	pub fn push_history(&self, entry: &HistoryEntry) {
+		let result = match entry.result {
+			Ok(ref val) => val,
+			Err(ref err) => err,
+		};
+		let label = gtk::Label::new(None);
+		label.set_markup(&format!(
+			"{}\n{}",
+			glib::markup_escape_text(&entry.input),
+			result_markup(result, &entry.marks)
+		));
+		label.set_xalign(0.0);
+		label.set_line_wrap(true);
+		label.set_selectable(true);
+		self.history_list.insert(&label, 0);
+		label.show();
 	}
 
 	pub fn button(&self, name: &str) -> &gtk::Button {
@@ -101,6 +591,11 @@ impl MainWindow {
 			.get(name)
 			.unwrap_or_else(|| panic!("Could not get button: {}.", name))
 	}
+	pub fn menu_item(&self, name: &str) -> &gtk::MenuItem {
+		self.menu_items
+			.get(name)
+			.unwrap_or_else(|| panic!("Could not get menu item {}", name))
+	}
 	pub fn entry(&self, name: &str) -> &gtk::Entry {
 		self.entries
 			.get(name)
@@ -109,4 +604,97 @@ impl MainWindow {
 	pub fn user_roll_entry(&self) -> &gtk::Entry {
 		&self.user_roll_entry
 	}
+	pub fn query_form(&self) -> &QueryForm {
+		&self.query_form
+	}
+	pub fn sheet_editor(&self) -> &SheetEditor {
+		&self.sheet_editor
+	}
+	pub fn table_editor(&self) -> &TableEditor {
+		&self.table_editor
+	}
+	pub fn band_editor(&self) -> &BandEditor {
+		&self.band_editor
+	}
+	pub fn dice_panel(&self) -> &DicePanel {
+		&self.dice_panel
+	}
+	pub fn system_panel(&self) -> &SystemPanel {
+		&self.system_panel
+	}
+	pub fn encounter_panel(&self) -> &EncounterPanel {
+		&self.encounter_panel
+	}
+	pub fn stats_window(&self) -> &StatsWindow {
+		&self.stats_window
+	}
+}
+
+// Draws the cards like the result shows them, with the titles in the
+// colours of their templates, as a PNG image.
+fn cards_png(cards: &[Card], options: TextOptions) -> Result<Vec<u8>, String> {
+	let window = gtk::OffscreenWindow::new();
+	let column = gtk::Box::new(Orientation::Vertical, 10);
+	column.set_border_width(10);
+	for card in cards.iter() {
+		let title = gtk::Label::new(None);
+		title.set_xalign(0.0);
+		title.set_markup(&format!(
+			"<span size=\"large\" foreground=\"{}\"><b>{}</b></span>",
+			glib::markup_escape_text(card.colour.as_deref().unwrap_or("#000000")),
+			glib::markup_escape_text(&fragments_string(&card.title, options))
+		));
+		// The lines of the card after its title
+		let marked = fragments_marked(&[OutputFragment::Card(card.clone())], options);
+		let markup = result_markup(&marked.text, &marked.marks);
+		let body = gtk::Label::new(None);
+		body.set_xalign(0.0);
+		body.set_markup(markup.split_once('\n').map_or("", |(_, rest)| rest));
+		column.pack_start(&title, false, true, 0);
+		column.pack_start(&body, false, true, 0);
+	}
+	window.add(&column);
+	window.show_all();
+	while gtk::events_pending() {
+		gtk::main_iteration();
+	}
+	let png = match window.get_pixbuf() {
+		Some(pixbuf) => pixbuf
+			.save_to_bufferv("png", &[])
+			.map_err(|e| e.to_string()),
+		None => Err(String::from("the cards could not be drawn")),
+	};
+	unsafe {
+		window.destroy();
+	}
+	png
+}
+
+// Escapes a result for a label with its marks: outcomes, bands and symbols
+// in their colour, the title of a card larger, the labels of its rows in
+// bold and its footer in italics.
+fn result_markup(text: &str, marks: &[Mark]) -> String {
+	let marked = MarkedText {
+		text: text.to_owned(),
+		marks: marks.to_vec(),
+	};
+	marked.render(
+		|part| glib::markup_escape_text(part).to_string(),
+		|style| {
+			let (open, close) = match style {
+				MarkStyle::Colour(colour) => {
+					let open = format!(
+						"<span foreground=\"{}\"><b>",
+						glib::markup_escape_text(colour)
+					);
+					return Some((open, String::from("</b></span>")));
+				}
+				MarkStyle::Struck => return None,
+				MarkStyle::Title => ("<span size=\"large\"><b>", "</b></span>"),
+				MarkStyle::Label => ("<b>", "</b>"),
+				MarkStyle::Footer => ("<i>", "</i>"),
+			};
+			Some((open.to_owned(), close.to_owned()))
+		},
+	)
 }
@@ -0,0 +1,242 @@
+// File: src/output.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+// The output of a roll. It follows roll_lang's output and reuses its
+// rolls, with fragments roll_lang does not have.
+
+use std::fmt;
+
+use serde::Serialize;
+use toml::Value;
+
+pub use roll_lang::interpreter::output::{
+	ExpressionOutput, FormulaFragment, FormulaFragments, NumberRoll, RollType,
+};
+
+use super::bands::Band;
+use super::dice_library::SymbolCount;
+use super::syntax::{Comparison, Number};
+
+#[derive(Debug, Clone)]
+pub struct Output {
+	pub source: String,
+	pub fragments: Vec<OutputFragment>,
+	// Every die with sides that was rolled, including rerolled and dropped
+	// ones, in the order rolled
+	pub dice: Vec<RolledDie>,
+}
+
+// A face a die with sides came up on, the 14 of a d20.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct RolledDie {
+	pub sides: i32,
+	pub face: i32,
+}
+
+#[derive(Debug, Clone)]
+pub enum OutputFragment {
+	StringLit(String),
+	Roll(RollType),
+	// The symbols the custom dice of the roll before it came up with
+	Symbols(SymbolCount),
+	// Follows the roll it is the outcome of
+	Outcome(Outcome),
+	// What the total of the roll before it means, `|bands:pbta`
+	Band(Band),
+	// The fragments of each roll of `6x(4d6kh3)`
+	Repeated(Vec<Vec<OutputFragment>>),
+	// A roll template filled in, `&{template:attack} {{name=Longsword}}`
+	Card(Card),
+}
+
+// The fields of a roll template laid out by the template.
+#[derive(Debug, Clone)]
+pub struct Card {
+	pub title: Vec<OutputFragment>,
+	// The title is shown in it, `#8e0000`
+	pub colour: Option<String>,
+	pub rows: Vec<(String, Vec<OutputFragment>)>,
+	pub footer: Vec<OutputFragment>,
+}
+
+// A roll checked against a target, `1d20+5 vs 15`.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+	pub comparison: Comparison,
+	pub target: ExpressionOutput,
+	pub success: bool,
+	// How far the roll was from the target
+	pub margin: Number,
+	// The raises of a success with `raises`
+	pub raises: Option<i32>,
+}
+impl fmt::Display for Outcome {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.success {
+			true => write!(f, "Success by {}", self.margin)?,
+			false => write!(f, "Fail by {}", self.margin)?,
+		}
+		match self.raises {
+			Some(1) => write!(f, ", 1 raise"),
+			Some(raises) if raises > 1 => write!(f, ", {} raises", raises),
+			_ => Ok(()),
+		}
+	}
+}
+
+// A part of the text of a result that is shown in its own style, from the
+// fragment it was written from. The bounds are byte indexes into the text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mark {
+	pub start: usize,
+	pub end: usize,
+	pub style: MarkStyle,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkStyle {
+	// The outcome of a check, `#2e7d32` for a success
+	Colour(String),
+	// A dropped or rerolled die, written `~4~`
+	Struck,
+	// The parts of a card, the label of a row without its colon
+	Title,
+	Label,
+	Footer,
+}
+impl Mark {
+	// `[12, 20, "colour", "#2e7d32"]` or `[3, 6, "struck"]`.
+	pub fn to_value(&self) -> Value {
+		let mut value = vec![
+			Value::Integer(self.start as i64),
+			Value::Integer(self.end as i64),
+		];
+		match &self.style {
+			MarkStyle::Colour(colour) => {
+				value.push(Value::String(String::from("colour")));
+				value.push(Value::String(colour.clone()));
+			}
+			MarkStyle::Struck => value.push(Value::String(String::from("struck"))),
+			MarkStyle::Title => value.push(Value::String(String::from("title"))),
+			MarkStyle::Label => value.push(Value::String(String::from("label"))),
+			MarkStyle::Footer => value.push(Value::String(String::from("footer"))),
+		}
+		Value::Array(value)
+	}
+	pub fn from_value(value: &Value) -> Option<Mark> {
+		let value = value.as_array()?;
+		let bound = |i: usize| Some(value.get(i)?.as_integer()?.max(0) as usize);
+		let style = match (value.get(2)?.as_str()?, value.get(3)) {
+			("colour", Some(colour)) => MarkStyle::Colour(colour.as_str()?.to_owned()),
+			("struck", _) => MarkStyle::Struck,
+			("title", _) => MarkStyle::Title,
+			("label", _) => MarkStyle::Label,
+			("footer", _) => MarkStyle::Footer,
+			_ => return None,
+		};
+		Some(Mark {
+			start: bound(0)?,
+			end: bound(1)?,
+			style,
+		})
+	}
+}
+
+// The text of a result with its marks. Marks hold each other or are apart,
+// they never overlap.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkedText {
+	pub text: String,
+	pub marks: Vec<Mark>,
+}
+impl MarkedText {
+	pub fn push_str(&mut self, text: &str) {
+		self.text.push_str(text);
+	}
+	pub fn push_marked(&mut self, text: &str, style: MarkStyle) {
+		let start = self.text.len();
+		self.text.push_str(text);
+		self.mark_from(start, style);
+	}
+	// Marks what was written since `start`.
+	pub fn mark_from(&mut self, start: usize, style: MarkStyle) {
+		self.marks.push(Mark {
+			start,
+			end: self.text.len(),
+			style,
+		});
+	}
+
+	// Writes the text with the tags of every mark around its part and the
+	// rest escaped. Marks without tags are left out. Struck dice that are
+	// marked up lose their tildes. Marks that do not fit the text, like
+	// ones of a history file that was edited, are left out.
+	pub fn render(
+		&self,
+		escape: impl Fn(&str) -> String,
+		tags: impl Fn(&MarkStyle) -> Option<(String, String)>,
+	) -> String {
+		let text = &self.text;
+		let mut marks: Vec<&Mark> = self
+			.marks
+			.iter()
+			.filter(|mark| {
+				mark.start <= mark.end
+					&& mark.end <= text.len()
+					&& text.is_char_boundary(mark.start)
+					&& text.is_char_boundary(mark.end)
+			})
+			.collect();
+		// Outer marks before the marks they hold
+		marks.sort_by_key(|mark| (mark.start, std::cmp::Reverse(mark.end)));
+
+		let mut rendered = String::new();
+		let mut at = 0;
+		// The end and closing tag of each mark that is open, innermost last
+		let mut open: Vec<(usize, String)> = Vec::new();
+		for mark in marks {
+			while let Some((end, _)) = open.last() {
+				if *end > mark.start {
+					break;
+				}
+				let (end, close) = open.pop().unwrap_or_default();
+				rendered.push_str(&escape(&text[at..end]));
+				rendered.push_str(&close);
+				at = end;
+			}
+			let inside = open.last().map_or(text.len(), |(end, _)| *end);
+			if mark.start < at || mark.end > inside {
+				continue;
+			}
+			let (opening, closing) = match tags(&mark.style) {
+				Some(tags) => tags,
+				None => continue,
+			};
+			rendered.push_str(&escape(&text[at..mark.start]));
+			rendered.push_str(&opening);
+			let part = &text[mark.start..mark.end];
+			match (
+				&mark.style,
+				part.strip_prefix('~')
+					.and_then(|part| part.strip_suffix('~')),
+			) {
+				(MarkStyle::Struck, Some(die)) => {
+					rendered.push_str(&escape(die));
+					rendered.push_str(&closing);
+					at = mark.end;
+				}
+				_ => {
+					open.push((mark.end, closing));
+					at = mark.start;
+				}
+			}
+		}
+		while let Some((end, close)) = open.pop() {
+			rendered.push_str(&escape(&text[at..end]));
+			rendered.push_str(&close);
+			at = end;
+		}
+		rendered.push_str(&escape(&text[at..]));
+		rendered
+	}
+}
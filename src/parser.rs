@@ -0,0 +1,1228 @@
+// File: src/parser.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use roll_lang::lexer::lexeme::Lexeme;
+use roll_lang::lexer::token::{Token, TokenT};
+use roll_lang::lexer::{Lexer, LexerT};
+
+use super::functions;
+use super::syntax::*;
+
+// Parses text with rolls in it. It reads the same grammar as roll_lang's
+// parser, from roll_lang's lexemes, along with the additions in syntax.rs.
+// Parsing stops at the first roll that can not be parsed.
+pub fn parse(source: &str) -> Vec<Node> {
+	Parser::new(source).parse()
+}
+// Parses a lone expression, such as the answer to a roll query. Anything
+// after the expression is ignored like roll_lang does.
+pub fn parse_expression(source: &str) -> Result<Expression, ParseError> {
+	Parser::new(source).expression()
+}
+
+// Reads a roll template, `&{template:attack} {{name=Longsword}}`. The text
+// around the fields is not part of the card and is left out.
+pub fn template_call(source: &str) -> Option<TemplateCall> {
+	let start = source.find("&{template:")?;
+	let rest = &source[(start + "&{template:".len())..];
+	let end = rest.find('}')?;
+	let name = rest[..end].trim().to_owned();
+	let mut rest = &rest[(end + 1)..];
+	let mut fields = Vec::new();
+	let mut unclosed = String::new();
+	while let Some(open) = rest.find("{{") {
+		let field_start = &rest[open..];
+		rest = &rest[(open + 2)..];
+		// Braces of groups and roll queries in the value are skipped over.
+		// The field ends at the first brace of the pair that closes it.
+		let mut depth = 2;
+		let mut first_brace = 0;
+		let mut close = None;
+		for (i, c) in rest.char_indices() {
+			match c {
+				'{' => depth += 1,
+				'}' => depth -= 1,
+				_ => (),
+			}
+			if c == '}' && depth == 1 {
+				first_brace = i;
+			}
+			if depth == 0 {
+				close = Some(i);
+				break;
+			}
+		}
+		let close = match close {
+			Some(close) => close,
+			None => {
+				unclosed = field_start.to_owned();
+				break;
+			}
+		};
+		let field = &rest[..first_brace];
+		rest = &rest[(close + 1)..];
+		match field.find('=') {
+			Some(equals) => fields.push((
+				field[..equals].trim().to_owned(),
+				field[(equals + 1)..].trim().to_owned(),
+			)),
+			None => fields.push((field.trim().to_owned(), String::new())),
+		}
+	}
+	Some(TemplateCall {
+		name,
+		fields,
+		unclosed,
+	})
+}
+
+struct Parser {
+	lexemes: Vec<Lexeme>,
+	index: usize,
+}
+impl Parser {
+	fn new(source: &str) -> Parser {
+		// roll_lang's lexer keeps `,`, `;` and `:` in literals, groups, let
+		// bindings and conditionals need them on their own
+		let separator = |c: char| c == ',' || c == ';' || c == ':';
+		let mut lexemes = Vec::new();
+		for lexeme in Lexer::new(source) {
+			match &lexeme {
+				Lexeme::Literal(token) if token.source().contains(separator) => {
+					let mut part = Token::new(token.start());
+					for (i, c) in token.source().char_indices() {
+						if separator(c) {
+							if part.length() > 0 {
+								lexemes.push(Lexeme::Literal(part));
+							}
+							let mut separator = Token::new(token.start() + i);
+							separator.push_str(&c.to_string());
+							lexemes.push(Lexeme::Literal(separator));
+							part = Token::new(token.start() + i + 1);
+						} else {
+							part.push_str(&c.to_string());
+						}
+					}
+					if part.length() > 0 {
+						lexemes.push(Lexeme::Literal(part));
+					}
+				}
+				_ => lexemes.push(lexeme),
+			}
+		}
+		Parser { lexemes, index: 0 }
+	}
+
+	fn parse(&mut self) -> Vec<Node> {
+		let mut nodes = Vec::new();
+		let mut literal = String::new();
+		while let Some(lexeme) = self.current() {
+			let text = lexeme.source().to_owned();
+			if self.at_roll() || self.at_inline_roll() {
+				if !literal.is_empty() {
+					nodes.push(Node::StringLiteral(std::mem::take(&mut literal)));
+				}
+				match self.roll() {
+					Ok(roll) => nodes.push(Node::Roll(roll)),
+					Err(e) => {
+						nodes.push(Node::ParseError(e));
+						return nodes;
+					}
+				}
+				continue;
+			}
+			if let Ok(my_macro) = self.attempt(Parser::macro_call) {
+				if !literal.is_empty() {
+					nodes.push(Node::StringLiteral(std::mem::take(&mut literal)));
+				}
+				let bands = self.attempt(Parser::bands).ok();
+				nodes.push(Node::Macro(my_macro, bands));
+			} else if let Ok(name) = self.attempt(Parser::attribute) {
+				if !literal.is_empty() {
+					nodes.push(Node::StringLiteral(std::mem::take(&mut literal)));
+				}
+				nodes.push(Node::Attribute(name));
+			} else {
+				literal.push_str(&text);
+				self.index += 1;
+			}
+		}
+		if !literal.is_empty() {
+			nodes.push(Node::StringLiteral(literal));
+		}
+		nodes
+	}
+
+	// <explicit_roll> ::= "/roll" (<repeat> | <check>) [<bands>] ["\"]
+	// <inline_roll> ::= "[[" (<repeat> | <check>) "]]" [<bands>]
+	fn roll(&mut self) -> Result<Roll, ParseError> {
+		if self.at_inline_roll() {
+			self.index += 2;
+			let check = self.repeat_or_check()?;
+			self.skip_whitespace();
+			self.expect_punctuation("]")?;
+			self.expect_punctuation("]")?;
+			let bands = self.attempt(Parser::bands).ok();
+			Ok(Roll::InlineRoll(Check { bands, ..check }))
+		} else {
+			self.index += 2;
+			let check = self.repeat_or_check()?;
+			let check = match self.attempt(Parser::bands) {
+				Ok(bands) => Check {
+					bands: Some(bands),
+					..check
+				},
+				Err(_) => check,
+			};
+			// Without a `\` the roll ends with its expression
+			let end = self.index;
+			self.skip_whitespace();
+			if !self.eat_punctuation("\\") {
+				self.index = end;
+			}
+			Ok(Roll::ExplicitRoll(check))
+		}
+	}
+
+	// <bands> ::= "|bands:" <name>
+	fn bands(&mut self) -> Result<String, ParseError> {
+		self.skip_whitespace();
+		self.expect_punctuation("|")?;
+		self.expect_literal("bands")?;
+		self.expect_literal(":")?;
+		self.name()
+	}
+
+	fn repeat_or_check(&mut self) -> Result<Check, ParseError> {
+		match self.attempt(Parser::repeat) {
+			Ok(check) => Ok(check),
+			Err(_) => self.check(),
+		}
+	}
+	// <repeat> ::= <integer> ("x(" <check> ")" | "#" <check>)
+	fn repeat(&mut self) -> Result<Check, ParseError> {
+		self.skip_whitespace();
+		let count = self.integer()?;
+		let check = if self.eat_literal("x") {
+			self.expect_punctuation("(")?;
+			let check = self.check()?;
+			self.skip_whitespace();
+			self.expect_punctuation(")")?;
+			check
+		} else {
+			self.expect_punctuation("#")?;
+			self.check()?
+		};
+		Ok(Check {
+			repeat: Some(count),
+			..check
+		})
+	}
+	// <check> ::= {<binding>} <value> [("vs" | <comparison>) <expression>]
+	fn check(&mut self) -> Result<Check, ParseError> {
+		let mut bindings = Vec::new();
+		while let Ok(binding) = self.attempt(Parser::binding) {
+			bindings.push(binding);
+		}
+		let expression = Box::new(self.value()?);
+		let target = self.attempt(Parser::target).ok().map(Box::new);
+		Ok(Check {
+			bindings,
+			expression,
+			target,
+			repeat: None,
+			bands: None,
+		})
+	}
+	// Comparisons right after dice count successes, `5d10>7`, so a target
+	// after dice is written with a space, `1d20 >= 15`.
+	fn target(&mut self) -> Result<Target, ParseError> {
+		self.skip_whitespace();
+		let comparison = match self.eat_literal("vs") {
+			true => Comparison::GreaterThanEqual,
+			false => self.comparison()?,
+		};
+		let expression = self.expression()?;
+		Ok(Target {
+			comparison,
+			expression,
+			raises: self.attempt(Parser::raises).ok(),
+		})
+	}
+
+	// <raises> ::= "raises" [<integer>]
+	fn raises(&mut self) -> Result<Integer, ParseError> {
+		self.skip_whitespace();
+		self.expect_literal("raises")?;
+		let start = self.index;
+		self.skip_whitespace();
+		match self.integer() {
+			Ok(step) => Ok(step),
+			Err(_) => {
+				self.index = start;
+				Ok(Integer::new(4))
+			}
+		}
+	}
+
+	// <binding> ::= <variable> "=" <value> ";"
+	fn binding(&mut self) -> Result<Binding, ParseError> {
+		self.skip_whitespace();
+		let name = self.variable()?;
+		self.skip_whitespace();
+		if !matches!(self.comparison()?, Comparison::Equal) {
+			return Err(ParseError::DoesNotMatch);
+		}
+		let expression = self.value()?;
+		self.skip_whitespace();
+		self.expect_literal(";")?;
+		Ok(Binding { name, expression })
+	}
+	// An expression or a conditional choosing between two.
+	// <value> ::= <expression> [<comparison> <expression> "?" <value> ":" <value>]
+	fn value(&mut self) -> Result<Expression, ParseError> {
+		let lhs = self.expression()?;
+		let start = self.index;
+		match self.conditional(lhs.clone()) {
+			Ok(conditional) => Ok(Expression::from(Atom::Conditional(Box::new(conditional)))),
+			Err(_) => {
+				self.index = start;
+				Ok(lhs)
+			}
+		}
+	}
+	fn conditional(&mut self, lhs: Expression) -> Result<Conditional, ParseError> {
+		self.skip_whitespace();
+		let comparison = self.comparison()?;
+		let rhs = self.expression()?;
+		self.skip_whitespace();
+		self.expect_punctuation("?")?;
+		let then = self.value()?;
+		self.skip_whitespace();
+		self.expect_literal(":")?;
+		let otherwise = self.value()?;
+		Ok(Conditional {
+			lhs,
+			comparison,
+			rhs,
+			then,
+			otherwise,
+		})
+	}
+	// <variable> ::= "$" <name>
+	fn variable(&mut self) -> Result<String, ParseError> {
+		let is_name = |text: &str| text.chars().all(|c| c.is_alphanumeric() || c == '_');
+		let mut name = match self.current() {
+			Some(Lexeme::Literal(token))
+				if token.source().len() > 1
+					&& token.source().starts_with('$')
+					&& is_name(&token.source()[1..]) =>
+			{
+				token.source()[1..].to_owned()
+			}
+			_ => return Err(ParseError::DoesNotMatch),
+		};
+		self.index += 1;
+		if let Ok(rest) = self.name() {
+			name.push_str(&rest);
+		}
+		Ok(name)
+	}
+	// roll_lang's lexer splits digits out of literals, `x_1` is two lexemes
+	fn name(&mut self) -> Result<String, ParseError> {
+		let is_name = |text: &str| text.chars().all(|c| c.is_alphanumeric() || c == '_');
+		let mut name = String::new();
+		while let Some(Lexeme::Literal(token)) | Some(Lexeme::Number(token)) = self.current() {
+			if !is_name(token.source()) {
+				break;
+			}
+			name.push_str(token.source());
+			self.index += 1;
+		}
+		match name.is_empty() {
+			true => Err(ParseError::DoesNotMatch),
+			false => Ok(name),
+		}
+	}
+
+	fn expression(&mut self) -> Result<Expression, ParseError> {
+		self.skip_whitespace();
+		let mut expression = Expression::MulDiv(self.mul_div()?);
+		loop {
+			let start = self.index;
+			self.skip_whitespace();
+			let is_add = if self.eat_operator("+") {
+				true
+			} else if self.eat_operator("-") {
+				false
+			} else {
+				self.index = start;
+				break;
+			};
+			self.skip_whitespace();
+			let rhs = match self.mul_div() {
+				Ok(rhs) => rhs,
+				Err(_) => {
+					self.index = start;
+					break;
+				}
+			};
+			expression = match is_add {
+				true => Expression::Add(Box::new(expression), rhs),
+				false => Expression::Subtract(Box::new(expression), rhs),
+			};
+		}
+		Ok(expression)
+	}
+	fn mul_div(&mut self) -> Result<MulDiv, ParseError> {
+		let mut mul_div = MulDiv::Power(self.power()?);
+		loop {
+			let start = self.index;
+			self.skip_whitespace();
+			let is_multiply = if self.eat_operator("*") {
+				true
+			} else if self.eat_operator("/") {
+				false
+			} else {
+				self.index = start;
+				break;
+			};
+			self.skip_whitespace();
+			let rhs = match self.power() {
+				Ok(rhs) => rhs,
+				Err(_) => {
+					self.index = start;
+					break;
+				}
+			};
+			mul_div = match is_multiply {
+				true => MulDiv::Multiply(Box::new(mul_div), rhs),
+				false => MulDiv::Divide(Box::new(mul_div), rhs),
+			};
+		}
+		Ok(mul_div)
+	}
+	fn power(&mut self) -> Result<Power, ParseError> {
+		let base = self.unary()?;
+		let start = self.index;
+		self.skip_whitespace();
+		if self.eat_operator("^") || self.eat_operator("**") {
+			self.skip_whitespace();
+			if let Ok(exponent) = self.power() {
+				return Ok(Power::Pow(base, Box::new(exponent)));
+			}
+		}
+		self.index = start;
+		Ok(Power::Unary(base))
+	}
+	fn unary(&mut self) -> Result<Unary, ParseError> {
+		let start = self.index;
+		let comment = self.comment();
+		if self.eat_operator("-") {
+			self.skip_whitespace();
+			if let Ok(unary) = self.unary() {
+				return Ok(Unary::Minus(comment, Box::new(unary)));
+			}
+		}
+		self.index = start;
+		let comment_before = self.comment();
+		let atom = self.atom()?;
+		let comment_after = self.comment();
+		Ok(Unary::Atom(comment_before, atom, comment_after))
+	}
+	fn atom(&mut self) -> Result<Atom, ParseError> {
+		self.skip_whitespace();
+		if let Ok(table) = self.attempt(Parser::table) {
+			return Ok(Atom::Table(table));
+		}
+		match self.attempt(Parser::dice) {
+			Ok(dice) => return Ok(Atom::Dice(dice)),
+			// Dice that can not be read one way are not read as a number
+			Err(e @ ParseError::UnexpectedToken(_)) => return Err(e),
+			Err(_) => (),
+		}
+		if let Ok(number) = self.attempt(Parser::number) {
+			return Ok(Atom::Number(number));
+		}
+		if let Ok(query) = self.attempt(Parser::roll_query) {
+			return Ok(Atom::RollQuery(query));
+		}
+		if let Ok(function) = self.attempt(Parser::function) {
+			return Ok(Atom::Function(function));
+		}
+		if let Ok(my_macro) = self.attempt(Parser::macro_call) {
+			return Ok(Atom::Macro(my_macro));
+		}
+		if let Ok(name) = self.attempt(Parser::variable) {
+			return Ok(Atom::Variable(name));
+		}
+		if let Ok(name) = self.attempt(Parser::attribute) {
+			return Ok(Atom::Attribute(name));
+		}
+		if let Ok(group) = self.attempt(Parser::group) {
+			return Ok(Atom::Group(group));
+		}
+		if let Ok(expression) = self.attempt(Parser::inline_roll) {
+			return Ok(Atom::InlineRoll(Box::new(expression)));
+		}
+		self.attempt(Parser::parentheses)
+			.map(|expression| Atom::ParenthesesExpression(Box::new(expression)))
+	}
+	// <table> ::= <integer> "t[" <name> "]"
+	fn table(&mut self) -> Result<TableRoll, ParseError> {
+		let count = self.integer()?;
+		if !self.eat_literal("t") {
+			return Err(ParseError::DoesNotMatch);
+		}
+		self.expect_punctuation("[")?;
+		let mut name = String::new();
+		while !self.eat_punctuation("]") {
+			match self.current() {
+				Some(lexeme) => name.push_str(lexeme.source()),
+				None => return Err(ParseError::ExpectedPunctuation(String::from("]"))),
+			}
+			self.index += 1;
+		}
+		Ok(TableRoll {
+			count,
+			name: name.trim().to_owned(),
+		})
+	}
+	fn inline_roll(&mut self) -> Result<Expression, ParseError> {
+		if !self.at_inline_roll() {
+			return Err(ParseError::DoesNotMatch);
+		}
+		self.index += 2;
+		let expression = self.expression()?;
+		self.skip_whitespace();
+		self.expect_punctuation("]")?;
+		self.expect_punctuation("]")?;
+		Ok(expression)
+	}
+	fn parentheses(&mut self) -> Result<Expression, ParseError> {
+		self.expect_punctuation("(")?;
+		let expression = self.value()?;
+		self.skip_whitespace();
+		self.expect_punctuation(")")?;
+		Ok(expression)
+	}
+	// A comment in square brackets, `[[` starts an inline roll instead.
+	fn comment(&mut self) -> Option<String> {
+		let start = self.index;
+		if self.at_inline_roll() || !self.eat_punctuation("[") {
+			self.index = start;
+			return None;
+		}
+		let mut comment = String::new();
+		while !self.eat_punctuation("]") {
+			match self.current() {
+				Some(lexeme) => comment.push_str(lexeme.source()),
+				None => {
+					self.index = start;
+					return None;
+				}
+			}
+			self.index += 1;
+		}
+		Some(comment)
+	}
+
+	// <group> ::= "{" <expression> {"," <expression>} "}" {<keep_drop> | <success>}
+	fn group(&mut self) -> Result<Group, ParseError> {
+		self.expect_punctuation("{")?;
+		let mut members = vec![self.expression()?];
+		loop {
+			self.skip_whitespace();
+			if !self.eat_literal(",") {
+				break;
+			}
+			members.push(self.expression()?);
+		}
+		self.expect_punctuation("}")?;
+		let mut modifiers = Vec::new();
+		loop {
+			if let Ok(modifier) = self.attempt(Parser::keep_drop) {
+				modifiers.push(modifier);
+			} else if let Ok(modifier) = self.attempt(Parser::success) {
+				modifiers.push(modifier);
+			} else {
+				break;
+			}
+		}
+		Ok(Group { members, modifiers })
+	}
+
+	// Any function in the registry, with as many arguments as it takes.
+	fn function(&mut self) -> Result<Function, ParseError> {
+		let name = match self.current() {
+			Some(Lexeme::Literal(token)) => token.source().to_owned(),
+			_ => return Err(ParseError::DoesNotMatch),
+		};
+		let definition = functions::lookup(&name).ok_or(ParseError::DoesNotMatch)?;
+		self.index += 1;
+		self.skip_whitespace();
+		self.expect_punctuation("(")?;
+		let mut arguments = vec![self.expression()?];
+		loop {
+			self.skip_whitespace();
+			if !definition.arguments.wants_more(arguments.len()) || !self.eat_literal(",") {
+				break;
+			}
+			arguments.push(self.expression()?);
+		}
+		if !definition.arguments.accepts(arguments.len()) {
+			return Err(ParseError::ExpectedPunctuation(String::from(",")));
+		}
+		self.expect_punctuation(")")?;
+		Ok(Function { name, arguments })
+	}
+
+	fn number(&mut self) -> Result<Number, ParseError> {
+		let integer = self.integer()?;
+		let start = self.index;
+		if self.eat_punctuation(".") {
+			if let Some(Lexeme::Number(fraction)) = self.current() {
+				let float = format!("{}.{}", integer.value(), fraction.source())
+					.parse()
+					.map_err(|_| ParseError::Unknown)?;
+				self.index += 1;
+				return Ok(Number::Float(Float::new(float)));
+			}
+		}
+		self.index = start;
+		Ok(Number::Integer(integer))
+	}
+	fn integer(&mut self) -> Result<Integer, ParseError> {
+		let integer = match self.current() {
+			Some(Lexeme::Number(token)) => token
+				.source()
+				.parse()
+				.map_err(|_| ParseError::ExpectedInteger)?,
+			_ => return Err(ParseError::ExpectedInteger),
+		};
+		self.index += 1;
+		Ok(Integer::new(integer))
+	}
+
+	// <roll_query> ::= "?{" <prompt> ["|" <default>] "}"
+	fn roll_query(&mut self) -> Result<RollQuery, ParseError> {
+		self.expect_punctuation("?")?;
+		self.expect_punctuation("{")?;
+		let mut query = RollQuery::new();
+		let mut prompt_complete = false;
+		while !self.eat_punctuation("}") {
+			if !prompt_complete && self.eat_punctuation("|") {
+				prompt_complete = true;
+				self.skip_whitespace();
+				continue;
+			}
+			let text = match self.current() {
+				Some(lexeme) => lexeme.source().to_owned(),
+				None => return Err(ParseError::ExpectedPunctuation(String::from("}"))),
+			};
+			match prompt_complete {
+				true => query.default.push_str(&text),
+				false => query.prompt.push_str(&text),
+			}
+			self.index += 1;
+		}
+		Ok(query)
+	}
+
+	// <macro> ::= "#" <name> | "#{" <name> "}"
+	fn macro_call(&mut self) -> Result<Macro, ParseError> {
+		self.expect_punctuation("#")?;
+		if self.eat_punctuation("{") {
+			let mut name = String::new();
+			while !self.eat_punctuation("}") {
+				match self.current() {
+					Some(lexeme) => name.push_str(lexeme.source()),
+					None => return Err(ParseError::ExpectedPunctuation(String::from("}"))),
+				}
+				self.index += 1;
+			}
+			return Ok(Macro { name });
+		}
+		match self.current() {
+			Some(Lexeme::Literal(token)) if !matches!(token.source(), "," | ";" | ":") => {
+				let name = token.source().to_owned();
+				self.index += 1;
+				Ok(Macro { name })
+			}
+			_ => Err(ParseError::DoesNotMatch),
+		}
+	}
+
+	// <attribute> ::= "@{" <name> "}"
+	// A `@{` that is not closed is left as text.
+	fn attribute(&mut self) -> Result<String, ParseError> {
+		self.expect_literal("@")?;
+		self.expect_punctuation("{")?;
+		let mut name = String::new();
+		while !self.eat_punctuation("}") {
+			match self.current() {
+				Some(lexeme) => name.push_str(lexeme.source()),
+				None => return Err(ParseError::ExpectedPunctuation(String::from("}"))),
+			}
+			self.index += 1;
+		}
+		match name.trim() {
+			"" => Err(ParseError::DoesNotMatch),
+			name => Ok(name.to_owned()),
+		}
+	}
+
+	fn dice(&mut self) -> Result<Dice, ParseError> {
+		if let Ok(normal) = self.attempt(Parser::normal) {
+			return Ok(Dice::Normal(normal, self.modifiers(), self.comment()));
+		}
+		if let Ok(fate) = self.attempt(Parser::fate) {
+			return Ok(Dice::Fate(fate, self.modifiers(), self.comment()));
+		}
+		if let Ok(custom) = self.attempt(Parser::custom) {
+			return Ok(Dice::Custom(custom, self.modifiers(), self.comment()));
+		}
+		let computed = self.computed()?;
+		let start = self.index;
+		let modifiers = self.modifiers();
+		// `1d(2)d3` reads like dice with a die for its sides, after computed
+		// dice only `dl3` drops the lowest
+		let bare_drop = self.lexemes[start..self.index]
+			.iter()
+			.find_map(|lexeme| match lexeme {
+				Lexeme::Literal(token) if token.source() == "d" => Some(token.clone()),
+				_ => None,
+			});
+		if let Some(token) = bare_drop {
+			return Err(ParseError::UnexpectedToken(token));
+		}
+		Ok(Dice::Computed(computed, modifiers, self.comment()))
+	}
+	fn normal(&mut self) -> Result<Normal, ParseError> {
+		let count = self
+			.attempt(Parser::integer)
+			.unwrap_or_else(|_| Integer::new(1));
+		self.expect_literal("d")?;
+		let sides = self.integer()?;
+		Ok(Normal { count, sides })
+	}
+	fn fate(&mut self) -> Result<Fate, ParseError> {
+		let count = self.integer()?;
+		self.expect_literal("dF")?;
+		Ok(Fate { count })
+	}
+	// <computed> ::= [<computed_part>] "d" <computed_part>
+	fn computed(&mut self) -> Result<Computed, ParseError> {
+		// Parentheses are only parsed here when a `d` follows them, otherwise
+		// nested ones would be parsed again for every level
+		if let Some(close) = self.closing_parenthesis() {
+			match self.lexemes.get(close + 1) {
+				Some(Lexeme::Literal(token)) if token.source() == "d" => (),
+				_ => return Err(ParseError::DoesNotMatch),
+			}
+		}
+		let count = self
+			.attempt(Parser::computed_part)
+			.unwrap_or_else(|_| Expression::from(Atom::Number(Number::Integer(Integer::new(1)))));
+		self.expect_literal("d")?;
+		let sides = self.computed_part()?;
+		Ok(Computed {
+			count: Box::new(count),
+			sides: Box::new(sides),
+		})
+	}
+	// <custom> ::= [<computed_part>] "d" ("{" <name> "}" | <faces>)
+	fn custom(&mut self) -> Result<CustomDice, ParseError> {
+		if let Some(close) = self.closing_parenthesis() {
+			match self.lexemes.get(close + 1) {
+				Some(Lexeme::Literal(token)) if token.source() == "d" => (),
+				_ => return Err(ParseError::DoesNotMatch),
+			}
+		}
+		let count = self
+			.attempt(Parser::computed_part)
+			.unwrap_or_else(|_| Expression::from(Atom::Number(Number::Integer(Integer::new(1)))));
+		self.expect_literal("d")?;
+		let faces = match self.attempt(Parser::faces) {
+			Ok(faces) => CustomFaces::Listed(faces),
+			Err(_) => {
+				self.expect_punctuation("{")?;
+				self.skip_whitespace();
+				let name = self.name()?;
+				self.skip_whitespace();
+				self.expect_punctuation("}")?;
+				CustomFaces::Named(name)
+			}
+		};
+		Ok(CustomDice {
+			count: Box::new(count),
+			faces,
+		})
+	}
+	// <faces> ::= "[" <face> {"," <face>} "]"
+	fn faces(&mut self) -> Result<Vec<i32>, ParseError> {
+		self.expect_punctuation("[")?;
+		let mut faces = Vec::new();
+		loop {
+			self.skip_whitespace();
+			let sign = if self.eat_operator("-") { -1 } else { 1 };
+			faces.push(sign * self.integer()?.value());
+			self.skip_whitespace();
+			if !self.eat_literal(",") {
+				break;
+			}
+		}
+		self.expect_punctuation("]")?;
+		Ok(faces)
+	}
+	// <computed_part> ::= <integer> | <roll_query> | "(" <expression> ")"
+	fn computed_part(&mut self) -> Result<Expression, ParseError> {
+		if let Ok(integer) = self.attempt(Parser::integer) {
+			return Ok(Expression::from(Atom::Number(Number::Integer(integer))));
+		}
+		if let Ok(query) = self.attempt(Parser::roll_query) {
+			return Ok(Expression::from(Atom::RollQuery(query)));
+		}
+		self.parentheses()
+	}
+
+	fn modifiers(&mut self) -> Modifiers {
+		let mut modifiers = Modifiers::new();
+		modifiers.expanding = self.attempt(Parser::expanding).ok();
+		loop {
+			if let Ok(reroll) = self.attempt(Parser::reroll) {
+				modifiers.reroll_modifiers.push(reroll);
+			} else if let Ok(modifier) = self.attempt(Parser::success) {
+				modifiers.post_modifiers.push(modifier);
+			} else if let Ok(modifier) = self.attempt(Parser::critical) {
+				modifiers.post_modifiers.push(modifier);
+			} else if let Ok(modifier) = self.attempt(Parser::keep_drop) {
+				modifiers.post_modifiers.push(modifier);
+			} else if let Ok(sort) = self.attempt(Parser::sort) {
+				modifiers.sort = Some(sort);
+			} else if let Ok(sides) = self.attempt(Parser::wild) {
+				modifiers.wild = Some(sides);
+			} else {
+				break;
+			}
+		}
+		modifiers
+	}
+	fn sort(&mut self) -> Result<Sort, ParseError> {
+		if self.eat_literal("s") || self.eat_literal("sa") {
+			Ok(Sort::Ascending)
+		} else if self.eat_literal("sd") {
+			Ok(Sort::Descending)
+		} else {
+			Err(ParseError::DoesNotMatch)
+		}
+	}
+	// A wild die is a d6 unless its sides are given.
+	fn wild(&mut self) -> Result<Integer, ParseError> {
+		self.expect_literal("w")?;
+		Ok(self
+			.attempt(Parser::integer)
+			.unwrap_or_else(|_| Integer::new(6)))
+	}
+	fn expanding(&mut self) -> Result<Expanding, ParseError> {
+		let operator = match self.current() {
+			Some(Lexeme::Operator(token)) => token.source().to_owned(),
+			_ => return Err(ParseError::DoesNotMatch),
+		};
+		self.index += 1;
+		// Without a point the dice explode on their highest side
+		let (comparison, point) = match self.attempt(Parser::comparison_and_integer) {
+			Ok((comparison, point)) => (comparison, Some(point)),
+			Err(_) => (Comparison::Equal, None),
+		};
+		let exploding = Exploding::new(comparison, point);
+		match operator.as_str() {
+			"!" => Ok(Expanding::Exploding(exploding)),
+			"!!" => Ok(Expanding::Compounding(exploding)),
+			"!p" => Ok(Expanding::Penetrating(exploding)),
+			_ => Err(ParseError::DoesNotMatch),
+		}
+	}
+	fn reroll(&mut self) -> Result<Reroll, ParseError> {
+		self.expect_literal("r")?;
+		let (comparison, point) = self.comparison_and_integer()?;
+		Ok(Reroll::new(comparison, Some(point)))
+	}
+	fn keep_drop(&mut self) -> Result<PostModifier, ParseError> {
+		let kind = match self.current() {
+			Some(Lexeme::Literal(token)) => token.source().to_owned(),
+			_ => return Err(ParseError::DoesNotMatch),
+		};
+		self.index += 1;
+		self.skip_whitespace();
+		let count = self.integer()?;
+		match kind.as_str() {
+			"k" | "kh" => Ok(PostModifier::KeepHighest(count)),
+			"kl" => Ok(PostModifier::KeepLowest(count)),
+			"d" | "dl" => Ok(PostModifier::DropLowest(count)),
+			"dh" => Ok(PostModifier::DropHighest(count)),
+			_ => Err(ParseError::DoesNotMatch),
+		}
+	}
+	fn success(&mut self) -> Result<PostModifier, ParseError> {
+		let comparison = self.comparison()?;
+		let target = self.integer()?;
+		Ok(PostModifier::Success(comparison, target))
+	}
+	fn critical(&mut self) -> Result<PostModifier, ParseError> {
+		let kind = match self.current() {
+			Some(Lexeme::Literal(token)) => token.source().to_owned(),
+			_ => return Err(ParseError::DoesNotMatch),
+		};
+		self.index += 1;
+		self.skip_whitespace();
+		let (comparison, point) = self.comparison_and_integer()?;
+		match kind.as_str() {
+			"cs" => Ok(PostModifier::CriticalSuccess(comparison, point)),
+			"cf" => Ok(PostModifier::CriticalFailure(comparison, point)),
+			_ => Err(ParseError::DoesNotMatch),
+		}
+	}
+	// A point with an optional comparison, equal when there is none.
+	fn comparison_and_integer(&mut self) -> Result<(Comparison, Integer), ParseError> {
+		let comparison = self
+			.attempt(Parser::comparison)
+			.unwrap_or(Comparison::Equal);
+		Ok((comparison, self.integer()?))
+	}
+	fn comparison(&mut self) -> Result<Comparison, ParseError> {
+		let comparison = match self.current() {
+			Some(Lexeme::Comparison(token)) => match token.source() {
+				"<" => Comparison::LessThan,
+				">" => Comparison::GreaterThan,
+				"<=" => Comparison::LessThanEqual,
+				">=" => Comparison::GreaterThanEqual,
+				"=" => Comparison::Equal,
+				_ => return Err(ParseError::DoesNotMatch),
+			},
+			_ => return Err(ParseError::DoesNotMatch),
+		};
+		self.index += 1;
+		Ok(comparison)
+	}
+
+	// Runs `parse` and goes back to where it started when it fails.
+	fn attempt<T>(
+		&mut self,
+		parse: fn(&mut Parser) -> Result<T, ParseError>,
+	) -> Result<T, ParseError> {
+		let start = self.index;
+		let result = parse(self);
+		if result.is_err() {
+			self.index = start;
+		}
+		result
+	}
+
+	// The index of the `)` closing the `(` at the current lexeme.
+	fn closing_parenthesis(&self) -> Option<usize> {
+		if !matches!(self.current(), Some(Lexeme::Punctuation(token)) if token.source() == "(") {
+			return None;
+		}
+		let mut depth = 0;
+		for (i, lexeme) in self.lexemes.iter().enumerate().skip(self.index) {
+			if let Lexeme::Punctuation(token) = lexeme {
+				match token.source() {
+					"(" => depth += 1,
+					")" => depth -= 1,
+					_ => (),
+				}
+				if depth == 0 {
+					return Some(i);
+				}
+			}
+		}
+		None
+	}
+
+	fn current(&self) -> Option<&Lexeme> {
+		self.lexemes.get(self.index)
+	}
+	fn skip_whitespace(&mut self) {
+		while let Some(Lexeme::Whitespace(_)) = self.current() {
+			self.index += 1;
+		}
+	}
+	fn eat_punctuation(&mut self, punctuation: &str) -> bool {
+		match self.current() {
+			Some(Lexeme::Punctuation(token)) if token.source() == punctuation => {
+				self.index += 1;
+				true
+			}
+			_ => false,
+		}
+	}
+	fn eat_literal(&mut self, literal: &str) -> bool {
+		match self.current() {
+			Some(Lexeme::Literal(token)) if token.source() == literal => {
+				self.index += 1;
+				true
+			}
+			_ => false,
+		}
+	}
+	fn eat_operator(&mut self, operator: &str) -> bool {
+		match self.current() {
+			Some(Lexeme::Operator(token)) if token.source() == operator => {
+				self.index += 1;
+				true
+			}
+			_ => false,
+		}
+	}
+	fn expect_punctuation(&mut self, punctuation: &str) -> Result<(), ParseError> {
+		match self.eat_punctuation(punctuation) {
+			true => Ok(()),
+			false => Err(ParseError::ExpectedPunctuation(punctuation.to_owned())),
+		}
+	}
+	fn expect_literal(&mut self, literal: &str) -> Result<(), ParseError> {
+		match self.eat_literal(literal) {
+			true => Ok(()),
+			false => Err(ParseError::DoesNotMatch),
+		}
+	}
+
+	fn at_roll(&self) -> bool {
+		let slash =
+			matches!(self.current(), Some(Lexeme::Operator(token)) if token.source() == "/");
+		let roll = matches!(
+			self.lexemes.get(self.index + 1),
+			Some(Lexeme::Literal(token)) if token.source() == "roll" || token.source() == "r"
+		);
+		slash && roll
+	}
+	fn at_inline_roll(&self) -> bool {
+		let is_open_bracket = |lexeme: Option<&Lexeme>| matches!(lexeme, Some(Lexeme::Punctuation(token)) if token.source() == "[");
+		is_open_bracket(self.current()) && is_open_bracket(self.lexemes.get(self.index + 1))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn check(source: &str) -> Check {
+		match parse(source).as_slice() {
+			[Node::Roll(Roll::ExplicitRoll(check))] => check.clone(),
+			nodes => panic!("{:?} is not one roll: {:?}", source, nodes),
+		}
+	}
+	fn atom(expression: &Expression) -> &Atom {
+		match expression {
+			Expression::MulDiv(MulDiv::Power(Power::Unary(Unary::Atom(_, atom, _)))) => atom,
+			_ => panic!("{:?} is not an atom", expression),
+		}
+	}
+	// The atom on the right of `a+b`.
+	fn added(expression: &Expression) -> &Atom {
+		match expression {
+			Expression::Add(_, MulDiv::Power(Power::Unary(Unary::Atom(_, atom, _)))) => atom,
+			_ => panic!("{:?} is not an addition", expression),
+		}
+	}
+
+	#[test]
+	fn text_around_rolls() {
+		let nodes = parse("Attack [[1d20]] for [[1d8]]");
+		assert!(matches!(
+			nodes.as_slice(),
+			[
+				Node::StringLiteral(_),
+				Node::Roll(Roll::InlineRoll(_)),
+				Node::StringLiteral(_),
+				Node::Roll(Roll::InlineRoll(_)),
+			]
+		));
+	}
+	#[test]
+	fn parsing_stops_at_an_error() {
+		let nodes = parse("a [[{1,]] b");
+		assert!(matches!(nodes.last(), Some(Node::ParseError(_))));
+	}
+
+	#[test]
+	fn multiplication_before_addition() {
+		let roll = check("/r 2+3*4");
+		assert!(matches!(
+			*roll.expression,
+			Expression::Add(_, MulDiv::Multiply(_, _))
+		));
+	}
+	#[test]
+	fn subtraction_from_the_left() {
+		let roll = check("/r 10-2-3");
+		match *roll.expression {
+			Expression::Subtract(lhs, _) => {
+				assert!(matches!(*lhs, Expression::Subtract(_, _)))
+			}
+			expression => panic!("{:?}", expression),
+		}
+	}
+	#[test]
+	fn parentheses_first() {
+		let roll = check("/r (2+3)*4");
+		assert!(matches!(
+			*roll.expression,
+			Expression::MulDiv(MulDiv::Multiply(_, _))
+		));
+	}
+
+	#[test]
+	fn comments_on_dice_and_numbers() {
+		let roll = check("/r 1d20[to hit]+5[strength]");
+		match *roll.expression {
+			Expression::Add(lhs, MulDiv::Power(Power::Unary(Unary::Atom(_, _, after)))) => {
+				assert!(matches!(
+					atom(&lhs),
+					Atom::Dice(Dice::Normal(_, _, Some(comment))) if comment == "to hit"
+				));
+				assert_eq!(after.as_deref(), Some("strength"));
+			}
+			expression => panic!("{:?}", expression),
+		}
+	}
+
+	#[test]
+	fn groups_with_keep_and_success() {
+		let roll = check("/r {4d6+3, 4d6+2}kh1");
+		assert!(matches!(
+			atom(&roll.expression),
+			Atom::Group(group)
+				if group.members.len() == 2
+					&& matches!(group.modifiers.as_slice(), [PostModifier::KeepHighest(n)] if n.value() == 1)
+		));
+		let roll = check("/r {3, 5, 1}kl2");
+		assert!(matches!(
+			atom(&roll.expression),
+			Atom::Group(group)
+				if group.members.len() == 3
+					&& matches!(group.modifiers.as_slice(), [PostModifier::KeepLowest(n)] if n.value() == 2)
+		));
+		let roll = check("/r {1d20, 10}>15");
+		assert!(matches!(
+			atom(&roll.expression),
+			Atom::Group(group)
+				if matches!(
+					group.modifiers.as_slice(),
+					[PostModifier::Success(Comparison::GreaterThan, n)] if n.value() == 15
+				)
+		));
+	}
+
+	#[test]
+	fn drops_after_computed_dice_are_written_dl() {
+		let nodes = parse("/r 1d(2)d3");
+		assert!(matches!(nodes.last(), Some(Node::ParseError(_))));
+		let roll = check("/r 2d(2+4)dl1");
+		assert!(matches!(
+			atom(&roll.expression),
+			Atom::Dice(Dice::Computed(_, modifiers, _))
+				if matches!(modifiers.post_modifiers.as_slice(), [PostModifier::DropLowest(n)] if n.value() == 1)
+		));
+		let roll = check("/r 4d6d1");
+		assert!(matches!(
+			atom(&roll.expression),
+			Atom::Dice(Dice::Normal(_, modifiers, _))
+				if matches!(modifiers.post_modifiers.as_slice(), [PostModifier::DropLowest(_)])
+		));
+	}
+
+	#[test]
+	fn roll_queries() {
+		let roll = check("/r 1d20+?{Bonus|2}");
+		assert!(matches!(
+			added(&roll.expression),
+			Atom::RollQuery(query) if query.prompt == "Bonus" && query.default == "2"
+		));
+	}
+
+	#[test]
+	fn repeats() {
+		assert_eq!(check("/r 3x(1d6)").repeat.map(|n| n.value()), Some(3));
+		assert_eq!(check("/r 6# 4d6kh3").repeat.map(|n| n.value()), Some(6));
+		assert!(check("/r 1d6").repeat.is_none());
+	}
+
+	#[test]
+	fn targets() {
+		let target = check("/r 1d20+5 vs 15").target.expect("a target");
+		assert!(matches!(target.comparison, Comparison::GreaterThanEqual));
+		let target = check("/r 1d20 < 10").target.expect("a target");
+		assert!(matches!(target.comparison, Comparison::LessThan));
+		let target = check("/r 1d8w vs 4 raises").target.expect("a target");
+		assert_eq!(target.raises.map(|n| n.value()), Some(4));
+	}
+
+	#[test]
+	fn bindings() {
+		let roll = check("/r $atk = 1d20+5; $dmg = 2d6; $atk + $dmg");
+		let names: Vec<&str> = roll
+			.bindings
+			.iter()
+			.map(|binding| binding.name.as_str())
+			.collect();
+		assert_eq!(names, ["atk", "dmg"]);
+		assert!(matches!(added(&roll.expression), Atom::Variable(name) if name == "dmg"));
+	}
+
+	#[test]
+	fn conditionals() {
+		let roll = check("/r $atk = 1d20; $atk >= 20 ? 4d6 : 2d6");
+		assert!(matches!(
+			atom(&roll.expression),
+			Atom::Conditional(conditional)
+				if matches!(conditional.comparison, Comparison::GreaterThanEqual)
+		));
+	}
+
+	#[test]
+	fn template_fields() {
+		let call = template_call(
+			"&{template:attack} {{name=Épée}} {{attack=[[{1d20, 10}kh1]]}} {{note=über ✓} }",
+		)
+		.expect("a template");
+		assert_eq!(call.name, "attack");
+		let fields: Vec<(&str, &str)> = call
+			.fields
+			.iter()
+			.map(|(name, value)| (name.as_str(), value.as_str()))
+			.collect();
+		assert_eq!(
+			fields,
+			[
+				("name", "Épée"),
+				("attack", "[[{1d20, 10}kh1]]"),
+				("note", "über ✓"),
+			]
+		);
+	}
+
+	#[test]
+	fn unclosed_template_fields_are_text() {
+		let call =
+			template_call("&{template:attack} {{name=Axe}} {{attack=[[1d20]]").expect("a template");
+		assert_eq!(call.fields, [("name".to_owned(), "Axe".to_owned())]);
+		assert_eq!(call.unclosed, "{{attack=[[1d20]]");
+	}
+
+	#[test]
+	fn attributes() {
+		let roll = check("/r 1d20+@{str_mod}");
+		assert!(matches!(added(&roll.expression), Atom::Attribute(name) if name == "str_mod"));
+		assert!(matches!(
+			parse("I swing @{weapon}").as_slice(),
+			[Node::StringLiteral(_), Node::Attribute(name)] if name == "weapon"
+		));
+		assert!(matches!(
+			parse("an @{ that is not closed").as_slice(),
+			[Node::StringLiteral(text)] if text == "an @{ that is not closed"
+		));
+	}
+}
@@ -0,0 +1,150 @@
+// File: src/presets.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::fs;
+use std::path::PathBuf;
+
+use roll_lang::macros::Macros;
+use toml::value::Table;
+use toml::Value;
+
+use super::bands::{self, BandTables};
+use super::dice_library::DiceLibrary;
+use super::roll::RollLibrary;
+use super::storage;
+
+static BUILTIN: [&str; 7] = [
+	include_str!("../data/presets/dnd5e.toml"),
+	include_str!("../data/presets/pbta.toml"),
+	include_str!("../data/presets/blades.toml"),
+	include_str!("../data/presets/wod.toml"),
+	include_str!("../data/presets/year_zero.toml"),
+	include_str!("../data/presets/savage_worlds.toml"),
+	include_str!("../data/presets/genesys.toml"),
+];
+
+#[derive(Debug, Clone)]
+pub struct PresetButton {
+	pub label: String,
+	// Inserted into the roll entry like the operator buttons
+	pub insert: String,
+}
+
+// The dice panel, macros, bands and custom dice of a game system.
+#[derive(Debug, Clone, Default)]
+pub struct Preset {
+	pub name: String,
+	pub description: String,
+	// The dice of the dice panel, those of dice_panel.toml when empty
+	pub dice: Vec<String>,
+	pub buttons: Vec<PresetButton>,
+	pub macros: Macros,
+	pub bands: BandTables,
+	pub dice_library: DiceLibrary,
+}
+impl Preset {
+	pub fn from_table(table: &Table) -> Preset {
+		let strings = |value: &Value| -> Vec<String> {
+			value
+				.as_array()
+				.map(|values| {
+					values
+						.iter()
+						.filter_map(Value::as_str)
+						.map(str::to_owned)
+						.collect()
+				})
+				.unwrap_or_default()
+		};
+		let buttons = table
+			.get("buttons")
+			.and_then(Value::as_array)
+			.map(|buttons| {
+				buttons
+					.iter()
+					.filter_map(Value::as_table)
+					.map(|button| PresetButton {
+						label: storage::get_str(button, "label"),
+						insert: storage::get_str(button, "insert"),
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		let macros = table
+			.get("macros")
+			.and_then(Value::as_table)
+			.map(|macros| {
+				macros
+					.iter()
+					.filter_map(|(name, source)| Some((name.clone(), source.as_str()?.to_owned())))
+					.collect()
+			})
+			.unwrap_or_default();
+		let bands = table
+			.get("bands")
+			.map(bands::tables_from_value)
+			.unwrap_or_default();
+		Preset {
+			name: storage::get_str(table, "name"),
+			description: storage::get_str(table, "description"),
+			dice: table.get("dice").map(strings).unwrap_or_default(),
+			buttons,
+			macros,
+			bands,
+			dice_library: DiceLibrary::from_table(table),
+		}
+	}
+
+	// Makes the bands and custom dice usable in rolls, replacing those of
+	// presets added before with the same names.
+	pub fn add_to(&self, library: &mut RollLibrary) {
+		library.bands.extend(self.bands.clone());
+		library.dice.add(&self.dice_library);
+	}
+}
+
+fn dir() -> PathBuf {
+	storage::data_dir().join("presets")
+}
+
+// The built in presets followed by the ones in the presets directory. A
+// file with the name of a built in preset replaces it.
+pub fn load() -> Vec<Preset> {
+	let mut presets: Vec<Preset> = BUILTIN
+		.iter()
+		.filter_map(|source| match source.parse::<Value>() {
+			Ok(Value::Table(table)) => Some(Preset::from_table(&table)),
+			_ => None,
+		})
+		.collect();
+	let mut paths: Vec<PathBuf> = fs::read_dir(dir())
+		.map(|entries| {
+			entries
+				.filter_map(Result::ok)
+				.map(|entry| entry.path())
+				.filter(|path| {
+					path.extension()
+						.is_some_and(|extension| extension == "toml")
+				})
+				.collect()
+		})
+		.unwrap_or_default();
+	paths.sort();
+	for path in paths.iter() {
+		let preset = match storage::load_table(path) {
+			Ok(table) => Preset::from_table(&table),
+			Err(e) => {
+				eprintln!("Could not load preset {}: {}", path.display(), e);
+				continue;
+			}
+		};
+		if preset.name.is_empty() {
+			eprintln!("Could not load preset {}: it has no name", path.display());
+			continue;
+		}
+		presets.retain(|other| other.name != preset.name);
+		presets.push(preset);
+	}
+	presets
+}
@@ -0,0 +1,466 @@
+// File: src/profile.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use roll_lang::macros::Macros;
+use toml::value::Table;
+use toml::Value;
+
+use super::bands::{self, BandTables};
+use super::character::Character;
+use super::encounter::Encounter;
+use super::output::{Mark, RolledDie};
+use super::roll::{RollContext, RollLibrary};
+use super::state::{RollResult, State, StateHandler};
+use super::storage::{self, StorageError};
+use super::tables::{RollTable, Tables};
+
+// Enough for the statistics of a campaign
+const MAX_HISTORY: usize = 5000;
+
+// The values of the dice panel entries keyed by entry name.
+pub type DicePreset = HashMap<String, String>;
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+	pub input: String,
+	pub result: RollResult,
+	// The parts of the result shown in their own style
+	pub marks: Vec<Mark>,
+	// The total of each roll in the result
+	pub totals: Vec<f32>,
+	// Every die with sides that was rolled, in the order rolled
+	pub dice: Vec<RolledDie>,
+	// When it was rolled in seconds since the Unix epoch, unknown for
+	// entries saved before it was kept
+	pub time: Option<i64>,
+}
+
+// Everything that belongs to one character. Of the undo history only the
+// current encounter is saved.
+pub struct Profile {
+	pub character: Character,
+	pub macros: Macros,
+	pub tables: Tables,
+	// Used with `|bands:name` like the bands of the game system
+	pub bands: BandTables,
+	pub dice_preset: DicePreset,
+	// The name of the game system preset, empty for none
+	pub system: String,
+	history: Vec<HistoryEntry>,
+	state_handler: StateHandler,
+	// The name of its file in the profiles directory, names like "A B" and
+	// "A_B" get different files
+	file: String,
+}
+impl Default for Profile {
+	fn default() -> Self {
+		Profile::new(Character::default())
+	}
+}
+impl Profile {
+	pub fn new(character: Character) -> Profile {
+		Profile {
+			character,
+			macros: Macros::new(),
+			tables: Tables::new(),
+			bands: BandTables::new(),
+			dice_preset: DicePreset::new(),
+			system: String::new(),
+			history: Vec::new(),
+			state_handler: StateHandler::new(&State::default()),
+			file: String::new(),
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		&self.character.name
+	}
+	pub fn history(&self) -> &[HistoryEntry] {
+		&self.history
+	}
+	// The macros, tables and attributes rolls of this profile use.
+	// The profile's bands replace those of the game systems with their names.
+	pub fn roll_context(&self, library: &RollLibrary) -> RollContext {
+		let mut bands = library.bands.clone();
+		bands.extend(self.bands.clone());
+		RollContext {
+			macros: self.macros.clone(),
+			tables: self.tables.clone(),
+			attributes: self.character.attribute_values(),
+			bands,
+			dice: library.dice.clone(),
+			templates: library.templates.clone(),
+		}
+	}
+	pub fn push_history(&mut self, entry: HistoryEntry) {
+		self.history.push(entry);
+		if self.history.len() > MAX_HISTORY {
+			let excess = self.history.len() - MAX_HISTORY;
+			self.history.drain(..excess);
+		}
+	}
+
+	pub fn to_table(&self, encounter: &Encounter) -> Table {
+		let mut table = self.character.to_table();
+		table.insert(String::from("macros"), string_table(&self.macros));
+		table.insert(
+			String::from("tables"),
+			Value::Table(
+				self.tables
+					.iter()
+					.map(|(name, roll_table)| (name.clone(), roll_table.to_value()))
+					.collect(),
+			),
+		);
+		table.insert(String::from("bands"), bands::tables_to_value(&self.bands));
+		table.insert(String::from("dice"), string_table(&self.dice_preset));
+		if !self.system.is_empty() {
+			table.insert(String::from("system"), Value::String(self.system.clone()));
+		}
+		let history = self
+			.history
+			.iter()
+			.map(|entry| {
+				let mut table = Table::new();
+				table.insert(String::from("input"), Value::String(entry.input.clone()));
+				match &entry.result {
+					Ok(output) => {
+						table.insert(String::from("output"), Value::String(output.clone()))
+					}
+					Err(error) => table.insert(String::from("error"), Value::String(error.clone())),
+				};
+				let totals = entry
+					.totals
+					.iter()
+					.map(|total| Value::Float(*total as f64))
+					.collect();
+				table.insert(String::from("totals"), Value::Array(totals));
+				let marks = entry.marks.iter().map(Mark::to_value).collect();
+				table.insert(String::from("marks"), Value::Array(marks));
+				// `[20, 14]`, the sides and the face of each
+				let dice = entry
+					.dice
+					.iter()
+					.map(|die| {
+						Value::Array(vec![
+							Value::Integer(die.sides as i64),
+							Value::Integer(die.face as i64),
+						])
+					})
+					.collect();
+				table.insert(String::from("dice"), Value::Array(dice));
+				if let Some(time) = entry.time {
+					table.insert(String::from("time"), Value::Integer(time));
+				}
+				Value::Table(table)
+			})
+			.collect();
+		table.insert(String::from("history"), Value::Array(history));
+		table.insert(
+			String::from("encounter"),
+			Value::Table(encounter.to_table()),
+		);
+		table
+	}
+	pub fn from_table(table: &Table) -> Profile {
+		let history = table
+			.get("history")
+			.and_then(Value::as_array)
+			.map(|history| {
+				history
+					.iter()
+					.filter_map(Value::as_table)
+					.map(|entry| HistoryEntry {
+						input: storage::get_str(entry, "input"),
+						result: match entry.get("error").and_then(Value::as_str) {
+							Some(error) => Err(error.to_owned()),
+							None => Ok(storage::get_str(entry, "output")),
+						},
+						marks: entry
+							.get("marks")
+							.and_then(Value::as_array)
+							.map(|marks| marks.iter().filter_map(Mark::from_value).collect())
+							.unwrap_or_default(),
+						totals: entry
+							.get("totals")
+							.and_then(Value::as_array)
+							.map(|totals| {
+								totals
+									.iter()
+									.filter_map(Value::as_float)
+									.map(|total| total as f32)
+									.collect()
+							})
+							.unwrap_or_default(),
+						dice: entry
+							.get("dice")
+							.and_then(Value::as_array)
+							.map(|dice| {
+								dice.iter()
+									.filter_map(|die| match die.as_array()?.as_slice() {
+										[sides, face] => Some(RolledDie {
+											sides: sides.as_integer()? as i32,
+											face: face.as_integer()? as i32,
+										}),
+										_ => None,
+									})
+									.collect()
+							})
+							.unwrap_or_default(),
+						time: entry.get("time").and_then(Value::as_integer),
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		let mut state = State::default();
+		state.encounter = table
+			.get("encounter")
+			.and_then(Value::as_table)
+			.map(Encounter::from_table)
+			.unwrap_or_default();
+		Profile {
+			macros: read_string_table(table, "macros"),
+			tables: table
+				.get("tables")
+				.and_then(Value::as_table)
+				.map(|tables| {
+					tables
+						.iter()
+						.map(|(name, rows)| (name.clone(), RollTable::from_value(rows)))
+						.collect()
+				})
+				.unwrap_or_default(),
+			bands: table
+				.get("bands")
+				.map(bands::tables_from_value)
+				.unwrap_or_default(),
+			dice_preset: read_string_table(table, "dice"),
+			system: storage::get_str(table, "system"),
+			history,
+			state_handler: StateHandler::new(&state),
+			..Profile::new(Character::from_table(table))
+		}
+	}
+}
+
+fn string_table(map: &HashMap<String, String>) -> Value {
+	Value::Table(
+		map.iter()
+			.map(|(key, value)| (key.clone(), Value::String(value.clone())))
+			.collect(),
+	)
+}
+fn read_string_table(table: &Table, key: &str) -> HashMap<String, String> {
+	table
+		.get(key)
+		.and_then(Value::as_table)
+		.map(|map| {
+			map.iter()
+				.filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_owned())))
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+// Every profile is saved to its own file in the profiles directory.
+//
+// The state handler of the active profile is the one the window works with,
+// so it is swapped out of the profile while it is active and swapped back
+// when another profile is selected.
+pub struct ProfileBook {
+	profiles: Vec<Profile>,
+	active: usize,
+}
+impl ProfileBook {
+	fn dir() -> PathBuf {
+		storage::data_dir().join("profiles")
+	}
+	fn path_for(file: &str) -> PathBuf {
+		ProfileBook::dir().join(format!("{}.toml", file))
+	}
+	// Where the character sheets were saved before there were profiles.
+	fn characters_dir() -> PathBuf {
+		storage::data_dir().join("characters")
+	}
+
+	pub fn load() -> ProfileBook {
+		let first_run = !ProfileBook::dir().exists();
+		let mut profiles: Vec<Profile> = fs::read_dir(ProfileBook::dir())
+			.map(|entries| {
+				entries
+					.filter_map(Result::ok)
+					.filter_map(|entry| {
+						let path = entry.path();
+						let table = storage::load_table(&path).ok()?;
+						let file = path.file_stem()?.to_str()?.to_owned();
+						Some(Profile {
+							file,
+							..Profile::from_table(&table)
+						})
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		profiles.sort_by(|a, b| a.name().cmp(b.name()));
+		let mut book = ProfileBook {
+			profiles,
+			active: 0,
+		};
+		if first_run {
+			book.import_characters();
+		}
+		if book.profiles.is_empty() {
+			book.push(Profile::default());
+		}
+		book
+	}
+	// A character sheet file has the name and attributes a profile file
+	// starts with, so each one becomes a profile. The old files are kept.
+	fn import_characters(&mut self) {
+		let mut paths: Vec<PathBuf> = fs::read_dir(ProfileBook::characters_dir())
+			.map(|entries| {
+				entries
+					.filter_map(Result::ok)
+					.map(|entry| entry.path())
+					.collect()
+			})
+			.unwrap_or_default();
+		paths.sort();
+		for path in paths.iter() {
+			let table = match storage::load_table(path) {
+				Ok(table) => table,
+				Err(_) => continue,
+			};
+			let profile = Profile::new(Character::from_table(&table));
+			let file = self.free_file(profile.name(), self.profiles.len());
+			let table = profile.to_table(&Encounter::default());
+			match storage::save_table(&ProfileBook::path_for(&file), &table) {
+				Ok(()) => self.profiles.push(Profile { file, ..profile }),
+				Err(e) => eprintln!("Could not import {}: {}", path.display(), e),
+			}
+		}
+		self.profiles.sort_by(|a, b| a.name().cmp(b.name()));
+	}
+
+	// A file for a profile named `name` that no other profile uses, the
+	// file stem of the name with a number after it when that one is taken.
+	// `index` is the profile the file is for.
+	fn free_file(&self, name: &str, index: usize) -> String {
+		let stem = storage::file_stem(name);
+		(1..)
+			.map(|n| match n {
+				1 => stem.clone(),
+				n => format!("{}_{}", stem, n),
+			})
+			.find(|file| {
+				let own = self.profiles.get(index).map(|profile| &profile.file) == Some(file);
+				let taken = self
+					.profiles
+					.iter()
+					.enumerate()
+					.any(|(i, profile)| i != index && &profile.file == file);
+				own || (!taken && !ProfileBook::path_for(file).exists())
+			})
+			.unwrap_or(stem)
+	}
+	fn push(&mut self, profile: Profile) {
+		let file = self.free_file(profile.name(), self.profiles.len());
+		self.profiles.push(Profile { file, ..profile });
+	}
+
+	pub fn names(&self) -> Vec<String> {
+		self.profiles
+			.iter()
+			.map(|profile| profile.name().to_owned())
+			.collect()
+	}
+	pub fn profiles(&self) -> &[Profile] {
+		&self.profiles
+	}
+	pub fn active(&self) -> &Profile {
+		&self.profiles[self.active]
+	}
+	pub fn active_mut(&mut self) -> &mut Profile {
+		&mut self.profiles[self.active]
+	}
+	pub fn active_index(&self) -> usize {
+		self.active
+	}
+
+	// Makes another profile active, `state_handler` is the one the window
+	// is using and is exchanged for the selected profile's.
+	pub fn select(&mut self, index: usize, state_handler: &mut StateHandler) {
+		if index >= self.profiles.len() || index == self.active {
+			return;
+		}
+		std::mem::swap(state_handler, &mut self.profiles[self.active].state_handler);
+		self.active = index;
+		std::mem::swap(state_handler, &mut self.profiles[self.active].state_handler);
+	}
+	pub fn add(&mut self, profile: Profile, state_handler: &mut StateHandler) {
+		self.push(profile);
+		self.select(self.profiles.len() - 1, state_handler);
+	}
+
+	// `current` is the state the window is using for the active profile.
+	pub fn save_active(&self, current: &State) -> Result<(), StorageError> {
+		let profile = self.active();
+		storage::save_table(
+			&ProfileBook::path_for(&profile.file),
+			&profile.to_table(&current.encounter),
+		)
+	}
+	// Replaces the sheet of the active profile and saves it. A renamed
+	// profile moves to a new file, never to one another profile uses.
+	pub fn save_sheet(
+		&mut self,
+		character: Character,
+		macros: Macros,
+		current: &State,
+	) -> Result<(), StorageError> {
+		let old_path = ProfileBook::path_for(&self.active().file);
+		let renamed = character.name != self.active().name();
+		let file = match renamed {
+			true => self.free_file(&character.name, self.active),
+			false => self.active().file.clone(),
+		};
+		let profile = self.active_mut();
+		profile.character = character;
+		profile.macros = macros;
+		profile.file = file;
+		self.save_active(current)?;
+		if old_path != ProfileBook::path_for(&self.active().file) && old_path.exists() {
+			fs::remove_file(old_path)?;
+		}
+		Ok(())
+	}
+	// Replaces the tables of the active profile and saves it.
+	pub fn save_tables(&mut self, tables: Tables, current: &State) -> Result<(), StorageError> {
+		self.active_mut().tables = tables;
+		self.save_active(current)
+	}
+	// Replaces the bands of the active profile and saves it.
+	pub fn save_bands(&mut self, bands: BandTables, current: &State) -> Result<(), StorageError> {
+		self.active_mut().bands = bands;
+		self.save_active(current)
+	}
+	pub fn remove_active(&mut self, state_handler: &mut StateHandler) -> Result<(), StorageError> {
+		let path = ProfileBook::path_for(&self.active().file);
+		if path.exists() {
+			fs::remove_file(path)?;
+		}
+		let mut removed = self.profiles.remove(self.active);
+		if self.profiles.is_empty() {
+			self.push(Profile::default());
+		}
+		self.active = self.active.min(self.profiles.len() - 1);
+		std::mem::swap(state_handler, &mut removed.state_handler);
+		std::mem::swap(state_handler, &mut self.profiles[self.active].state_handler);
+		Ok(())
+	}
+}
@@ -0,0 +1,109 @@
+// File: src/query_form.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::RefCell;
+
+use roll_lang::ast::RollQuery;
+
+use super::roll::{QueryAnswers, RollContext};
+
+// Sidebar that asks for every roll query of a roll at once. The source and
+// the macros, tables and attributes waiting on the answers are held here
+// until the form is submitted.
+pub struct QueryForm {
+	container: gtk::Box,
+	fields: gtk::Grid,
+	submit: gtk::Button,
+	cancel: gtk::Button,
+	entries: RefCell<Vec<(String, gtk::Entry)>>,
+	pending: RefCell<Option<(String, RollContext)>>,
+}
+impl QueryForm {
+	pub fn new(builder: &gtk::Builder) -> QueryForm {
+		let container = builder
+			.get_object("query_form")
+			.expect("Could not get box query_form.");
+		let fields = builder
+			.get_object("query_form_fields")
+			.expect("Could not get grid query_form_fields.");
+		let submit = builder
+			.get_object("query_form_submit")
+			.expect("Could not get button query_form_submit.");
+		let cancel = builder
+			.get_object("query_form_cancel")
+			.expect("Could not get button query_form_cancel.");
+
+		QueryForm {
+			container,
+			fields,
+			submit,
+			cancel,
+			entries: RefCell::new(Vec::new()),
+			pending: RefCell::new(None),
+		}
+	}
+
+	pub fn ask(&self, source: &str, queries: &[RollQuery], context: &RollContext) {
+		self.clear();
+		let mut entries = self.entries.borrow_mut();
+		for (row, query) in queries.iter().enumerate() {
+			let label = gtk::Label::new(Some(&query.prompt));
+			label.set_halign(Align::Start);
+
+			let entry = gtk::Entry::new();
+			entry.set_text(&query.default);
+			{
+				let submit = self.submit.clone();
+				entry.connect_activate(move |_| submit.clicked());
+			}
+
+			self.fields.attach(&label, 0, row as i32, 1, 1);
+			self.fields.attach(&entry, 1, row as i32, 1, 1);
+			entries.push((query.prompt.clone(), entry));
+		}
+		*self.pending.borrow_mut() = Some((source.to_owned(), context.clone()));
+
+		self.fields.show_all();
+		self.container.show();
+		if let Some((_, first)) = entries.first() {
+			first.grab_focus();
+		}
+	}
+	// Returns the source that was waiting on the form along with the answers.
+	pub fn take(&self) -> Option<(String, QueryAnswers, RollContext)> {
+		let (source, context) = self.pending.borrow_mut().take()?;
+		let answers = self
+			.entries
+			.borrow()
+			.iter()
+			.map(|(prompt, entry)| (prompt.clone(), entry.get_text().to_string()))
+			.collect();
+		self.clear();
+		Some((source, answers, context))
+	}
+	pub fn dismiss(&self) {
+		self.pending.borrow_mut().take();
+		self.clear();
+	}
+
+	fn clear(&self) {
+		self.entries.borrow_mut().clear();
+		for child in self.fields.get_children() {
+			unsafe {
+				child.destroy();
+			}
+		}
+		self.container.hide();
+	}
+
+	pub fn submit_button(&self) -> &gtk::Button {
+		&self.submit
+	}
+	pub fn cancel_button(&self) -> &gtk::Button {
+		&self.cancel
+	}
+}
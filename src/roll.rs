@@ -2,69 +2,513 @@
 // Author: Jacob Guenther
 // Date: January 2020
 
-use std::sync::Arc;
+use std::collections::HashMap;
 
-use crate::gtk::prelude::*;
-use gtk::*;
+use roll_lang::macros::Macros;
 
-use roll_lang::interpreter::*;
+use super::bands::BandTables;
+use super::dice_library::{DiceLibrary, SymbolCount};
+use super::evaluator::{comparison_text, number_value, Evaluator, RollMode};
+use super::output::*;
+use super::parser::{parse, parse_expression};
+use super::syntax::*;
+use super::tables::Tables;
+use super::templates::Templates;
 
-pub fn roll(input: &str) -> Result<String, String> {
-	let out = roll_lang::InterpreterBuilder::default()
-		.with_source(input)
-		.with_query_prompter(RollQueryPopup::create_popup)
-		.build()
-		.interpret();
-	match out.error {
-		Some(e) => Err(format!("{:?}", e)),
-		None => Ok(out.to_string()),
+pub type QueryAnswers = HashMap<String, String>;
+// The values of a character's attributes by name.
+pub type Attributes = HashMap<String, String>;
+
+// What a roll can use by name, from the profile it is rolled for and the
+// game systems.
+#[derive(Debug, Clone, Default)]
+pub struct RollContext {
+	pub macros: Macros,
+	pub tables: Tables,
+	pub attributes: Attributes,
+	// The bands of the profile along with those of the game systems
+	pub bands: BandTables,
+	// The symbols and custom dice of the game systems and the dice library
+	pub dice: DiceLibrary,
+	pub templates: Templates,
+}
+// What rolls can use by name from the game system presets, loaded once and
+// added to the context of every profile.
+#[derive(Debug, Clone, Default)]
+pub struct RollLibrary {
+	pub bands: BandTables,
+	pub dice: DiceLibrary,
+	pub templates: Templates,
+}
+
+pub fn roll(
+	input: &str,
+	answers: &QueryAnswers,
+	context: &RollContext,
+	mode: RollMode,
+) -> Result<String, String> {
+	interpret(input, answers, context, mode).map(|out| output_text(&out, TextOptions::default()))
+}
+pub fn interpret(
+	input: &str,
+	answers: &QueryAnswers,
+	context: &RollContext,
+	mode: RollMode,
+) -> Result<Output, String> {
+	let mut roll_queries = HashMap::new();
+	for (prompt, answer) in answers.iter() {
+		match parse_expression(answer) {
+			Ok(expression) => roll_queries.insert(prompt.clone(), expression),
+			Err(e) => return Err(format!("{} :: {:?}", prompt, e)),
+		};
 	}
+
+	Evaluator::new(context, roll_queries, mode)
+		.evaluate(input)
+		.map_err(|e| e.to_string())
 }
 
-pub struct RollQueryPopup {
-	dialog: MessageDialog,
-	entry: Entry,
+// The colours outcomes are shown in.
+pub const SUCCESS_COLOUR: &str = "#2e7d32";
+pub const FAIL_COLOUR: &str = "#c62828";
+
+// How rolls are written out.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TextOptions {
+	// `6×3+5×2+1` instead of `6+6+6+5+5+1`
+	pub group_faces: bool,
+	// A line with the sum and sorted results after repeated rolls
+	pub repeat_summary: bool,
 }
-impl<'a, 'b> RollQueryPopup {
-	pub fn create_popup(prompt: &str, default: &str) -> Option<String> {
-		let popup = Arc::new(RollQueryPopup::new(Some(prompt), Some(default)));
-		{
-			let entry = &popup.entry;
-			let popup = Arc::clone(&popup);
-			entry.connect_activate(move |_entry| {
-				popup.dialog.response(ResponseType::Ok);
-			});
+
+// Like roll_lang's to_string but dropped and rerolled dice show their value
+// struck through as `~4~` instead of `NC`. Outcomes are written after their
+// roll, `18+5=23 >= 15: Success by 8`, bands after the total,
+// `2d6+1=[4, 5]+1=10 (Strong hit)`, symbols after the total or in place of
+// a total of nothing, `3d{ability}(✹, ✹⬢, –): ✹ 3 success, ⬢ 1 advantage`,
+// repeated rolls one per line and cards as a block of lines,
+// `┏━ Longsword`, `┃ Attack: 1d20+5=[14]+5=19` and `┗━ A trusty blade`.
+pub fn output_text(output: &Output, options: TextOptions) -> String {
+	fragments_string(&output.fragments, options)
+}
+pub fn fragments_string(fragments: &[OutputFragment], options: TextOptions) -> String {
+	fragments_marked(fragments, options).text
+}
+// The text along with the parts of it to show in their own style: outcomes
+// in their colour, struck dice and the parts of cards.
+pub fn output_marked(output: &Output, options: TextOptions) -> MarkedText {
+	fragments_marked(&output.fragments, options)
+}
+pub fn fragments_marked(fragments: &[OutputFragment], options: TextOptions) -> MarkedText {
+	let mut text = MarkedText::default();
+	fragments_text(fragments, options, &mut text);
+	text
+}
+fn fragments_text(fragments: &[OutputFragment], options: TextOptions, text: &mut MarkedText) {
+	let group_faces = options.group_faces;
+	for (i, fragment) in fragments.iter().enumerate() {
+		match fragment {
+			OutputFragment::StringLit(s) => text.push_str(s),
+			OutputFragment::Roll(RollType::ExplicitRoll(roll))
+			| OutputFragment::Roll(RollType::InlineRoll(roll)) => {
+				formula_text(roll, group_faces, text);
+				let only_symbols = matches!(fragments.get(i + 1), Some(OutputFragment::Symbols(_)))
+					&& number_value(roll.result) == 0.0;
+				if !only_symbols {
+					text.push_str(&format!("={}", roll.result));
+				}
+			}
+			OutputFragment::Symbols(symbols) => symbols_text(symbols, text),
+			OutputFragment::Outcome(outcome) => {
+				text.push_str(&format!(" {} ", comparison_text(outcome.comparison)));
+				// A target that is only a number is not written twice
+				let start = text.text.len();
+				formula_text(&outcome.target, group_faces, text);
+				if text.text[start..] != outcome.target.result.to_string() {
+					text.push_str(&format!("={}", outcome.target.result));
+				}
+				text.push_str(": ");
+				let colour = match outcome.success {
+					true => SUCCESS_COLOUR,
+					false => FAIL_COLOUR,
+				};
+				text.push_marked(&outcome.to_string(), MarkStyle::Colour(colour.to_owned()));
+			}
+			OutputFragment::Band(band) => {
+				text.push_str(" ");
+				let label = format!("({})", band.label);
+				match &band.colour {
+					Some(colour) => text.push_marked(&label, MarkStyle::Colour(colour.clone())),
+					None => text.push_str(&label),
+				}
+			}
+			OutputFragment::Repeated(repeated) => {
+				for (i, fragments) in repeated.iter().enumerate() {
+					if i > 0 {
+						text.push_str("\n");
+					}
+					fragments_text(fragments, options, text);
+				}
+				if options.repeat_summary && !repeated.is_empty() {
+					text.push_str("\n");
+					text.push_str(&repeat_summary(repeated));
+				}
+			}
+			OutputFragment::Card(card) => card_text(card, options, text),
+		}
+	}
+}
+// The icons of symbols with a colour are marked in it.
+fn symbols_text(symbols: &SymbolCount, text: &mut MarkedText) {
+	text.push_str(": ");
+	if symbols.counts.is_empty() {
+		text.push_str(&symbols.to_string());
+	}
+	for (i, (symbol, count)) in symbols.counts.iter().enumerate() {
+		if i > 0 {
+			text.push_str(", ");
+		}
+		match &symbol.colour {
+			Some(colour) => text.push_marked(&symbol.icon, MarkStyle::Colour(colour.clone())),
+			None => text.push_str(&symbol.icon),
+		}
+		text.push_str(&format!(" {} {}", count, symbol.name));
+	}
+}
+// Starts on a line of its own.
+fn card_text(card: &Card, options: TextOptions, text: &mut MarkedText) {
+	if !text.text.is_empty() && !text.text.ends_with('\n') {
+		text.push_str("\n");
+	}
+	text.push_str("┏━ ");
+	let start = text.text.len();
+	fragments_text(&card.title, options, text);
+	text.mark_from(start, MarkStyle::Title);
+	for (label, value) in card.rows.iter() {
+		text.push_str("\n┃ ");
+		text.push_marked(label, MarkStyle::Label);
+		text.push_str(": ");
+		fragments_text(value, options, text);
+	}
+	text.push_str("\n┗━");
+	if !card.footer.is_empty() {
+		text.push_str(" ");
+		let start = text.text.len();
+		fragments_text(&card.footer, options, text);
+		text.mark_from(start, MarkStyle::Footer);
+	}
+}
+// `Sum 73, sorted 15 14 13 12 10 9`
+pub fn repeat_summary(repeated: &[Vec<OutputFragment>]) -> String {
+	let mut results: Vec<f32> = repeated
+		.iter()
+		.filter_map(|fragments| {
+			rolls(fragments)
+				.first()
+				.map(|roll| number_value(roll.result))
+		})
+		.collect();
+	let sum: f32 = results.iter().sum();
+	results.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+	let sorted: Vec<String> = results.iter().map(f32::to_string).collect();
+	format!("Sum {}, sorted {}", sum, sorted.join(" "))
+}
+fn formula_text(roll: &ExpressionOutput, group_faces: bool, text: &mut MarkedText) {
+	for formula_fragment in roll.formula_fragments.iter() {
+		match formula_fragment {
+			FormulaFragment::NumberRolls(first, rest, tooltip) => {
+				let rolls = std::iter::once(first).chain(rest.iter());
+				let rolls: Vec<(NumberRoll, usize)> = match group_faces {
+					true => face_counts(rolls),
+					false => rolls.map(|roll| (*roll, 1)).collect(),
+				};
+				for (i, (roll, count)) in rolls.iter().enumerate() {
+					if i > 0 {
+						text.push_str("+");
+					}
+					roll_text(roll, text);
+					if *count > 1 {
+						text.push_str(&format!("×{}", count));
+					}
+				}
+				if let Some(tooltip) = tooltip {
+					text.push_str(&format!("[{}]", tooltip));
+				}
+			}
+			other => text.push_str(&other.to_string()),
+		}
+	}
+}
+
+fn roll_text(roll: &NumberRoll, text: &mut MarkedText) {
+	match roll {
+		NumberRoll::Counted(value) => text.push_str(&value.value().to_string()),
+		NumberRoll::NotCounted(value) => {
+			text.push_marked(&format!("~{}~", value.value()), MarkStyle::Struck)
+		}
+	}
+}
+// How many times each roll came up, in the order they first came up.
+pub fn face_counts<'a>(rolls: impl Iterator<Item = &'a NumberRoll>) -> Vec<(NumberRoll, usize)> {
+	let mut counts: Vec<(NumberRoll, usize)> = Vec::new();
+	for roll in rolls {
+		let same = |other: &NumberRoll| match (roll, other) {
+			(NumberRoll::Counted(a), NumberRoll::Counted(b))
+			| (NumberRoll::NotCounted(a), NumberRoll::NotCounted(b)) => a.value() == b.value(),
+			_ => false,
+		};
+		match counts.iter_mut().find(|(other, _)| same(other)) {
+			Some((_, count)) => *count += 1,
+			None => counts.push((*roll, 1)),
+		}
+	}
+	counts
+}
+
+// How many of the counted dice in the output came up on each face, highest
+// face first: `6×3  5×2  1×1`.
+pub fn face_histogram(output: &Output) -> String {
+	let mut counts: Vec<(i32, usize)> = Vec::new();
+	for roll in rolls(&output.fragments) {
+		for formula_fragment in roll.formula_fragments.iter() {
+			if let FormulaFragment::NumberRolls(first, rest, _) = formula_fragment {
+				for roll in std::iter::once(first).chain(rest.iter()) {
+					if let NumberRoll::Counted(value) = roll {
+						match counts.iter_mut().find(|(face, _)| *face == value.value()) {
+							Some((_, count)) => *count += 1,
+							None => counts.push((value.value(), 1)),
+						}
+					}
+				}
+			}
+		}
+	}
+	counts.sort_by_key(|(face, _)| std::cmp::Reverse(*face));
+	counts
+		.iter()
+		.map(|(face, count)| format!("{}×{}", face, count))
+		.collect::<Vec<String>>()
+		.join("  ")
+}
+
+// The result of every roll in the output, in order.
+pub fn totals(output: &Output) -> Vec<f32> {
+	rolls(&output.fragments)
+		.iter()
+		.map(|roll| number_value(roll.result))
+		.collect()
+}
+// Every roll in the fragments, including repeated ones, in order. The
+// targets of checks are not included.
+pub fn rolls(fragments: &[OutputFragment]) -> Vec<&ExpressionOutput> {
+	let mut found = Vec::new();
+	for fragment in fragments.iter() {
+		match fragment {
+			OutputFragment::Roll(RollType::ExplicitRoll(roll))
+			| OutputFragment::Roll(RollType::InlineRoll(roll)) => found.push(roll),
+			OutputFragment::Repeated(repeated) => {
+				for fragments in repeated.iter() {
+					found.append(&mut rolls(fragments));
+				}
+			}
+			OutputFragment::Card(card) => {
+				found.append(&mut rolls(&card.title));
+				for (_, value) in card.rows.iter() {
+					found.append(&mut rolls(value));
+				}
+				found.append(&mut rolls(&card.footer));
+			}
+			OutputFragment::StringLit(_)
+			| OutputFragment::Symbols(_)
+			| OutputFragment::Outcome(_)
+			| OutputFragment::Band(_) => (),
 		}
-		popup.run()
 	}
-	pub fn new(prompt: Option<&'a str>, default: Option<&'b str>) -> RollQueryPopup {
-		let glade_src = include_str!("../data/prompt.glade");
-		let builder = gtk::Builder::from_string(glade_src);
+	found
+}
 
-		let dialog: MessageDialog = builder
-			.get_object("prompt_dialog_popup")
-			.expect("Could not get window prompt_dialog_popup.");
+// Collects every roll query in the input and the macros and attributes it
+// uses, in order, without duplicates.
+pub fn roll_queries(input: &str, context: &RollContext) -> Vec<RollQuery> {
+	let mut queries: Vec<RollQuery> = Vec::new();
+	let mut sources = vec![input.to_owned()];
+	let mut expanded = Vec::<String>::new();
+	let mut expanded_attributes = Vec::<String>::new();
+	while let Some(source) = sources.pop() {
+		for_each_atom(&source, &mut |atom| {
+			if let Atom::RollQuery(query) = atom {
+				if !queries.iter().any(|q| q.prompt == query.prompt) {
+					queries.push(query.clone());
+				}
+			}
+		});
+		for (name, attribute_source) in attribute_sources(&source, &context.attributes)
+			.into_iter()
+			.rev()
+		{
+			if !expanded_attributes.contains(&name) {
+				sources.push(attribute_source);
+				expanded_attributes.push(name);
+			}
+		}
+		for name in macro_names(&source).into_iter().rev() {
+			if expanded.contains(&name) {
+				continue;
+			}
+			if let Some(macro_source) = context.macros.get(&name) {
+				sources.push(macro_source.clone());
+			}
+			expanded.push(name);
+		}
+	}
+	queries
+}
 
-		dialog.set_property_text(prompt);
+// Names of the macros the input uses directly, in order.
+pub fn macro_names(input: &str) -> Vec<String> {
+	let mut names = Vec::new();
+	for node in parse(input).iter() {
+		match node {
+			Node::Macro(my_macro, _) => names.push(my_macro.name.clone()),
+			Node::Roll(Roll::ExplicitRoll(check)) | Node::Roll(Roll::InlineRoll(check)) => {
+				visit_check(check, &mut |atom| {
+					if let Atom::Macro(my_macro) = atom {
+						names.push(my_macro.name.clone());
+					}
+				})
+			}
+			_ => (),
+		}
+	}
+	names
+}
 
-		let entry: Entry = builder
-			.get_object("prompt_entry")
-			.expect("Could not get entry prompt_entry");
-		entry.set_text(&default.unwrap());
+// The attributes the input uses directly, in order, with their values as a
+// source of their own. A value used in a roll is an expression and is
+// written as `[[value]]`. Missing attributes are left out.
+pub fn attribute_sources(input: &str, attributes: &Attributes) -> Vec<(String, String)> {
+	let mut sources = Vec::new();
+	for node in parse(input).iter() {
+		match node {
+			Node::Attribute(name) => {
+				if let Some(value) = attributes.get(name) {
+					sources.push((name.clone(), value.clone()));
+				}
+			}
+			Node::Roll(Roll::ExplicitRoll(check)) | Node::Roll(Roll::InlineRoll(check)) => {
+				visit_check(check, &mut |atom| {
+					if let Atom::Attribute(name) = atom {
+						if let Some(value) = attributes.get(name) {
+							sources.push((name.clone(), format!("[[{}]]", value)));
+						}
+					}
+				})
+			}
+			_ => (),
+		}
+	}
+	sources
+}
 
-		dialog.add_button("Enter", ResponseType::Ok);
+// Names of the tables the input rolls on directly.
+pub fn table_names(input: &str) -> Vec<String> {
+	let mut names = Vec::new();
+	for_each_atom(input, &mut |atom| {
+		if let Atom::Table(table) = atom {
+			names.push(table.name.clone());
+		}
+	});
+	names
+}
 
-		RollQueryPopup { dialog, entry }
+// Visits every atom of every roll in the input, including nested ones.
+pub fn for_each_atom(input: &str, f: &mut dyn FnMut(&Atom)) {
+	for_each_repeated_atom(input, &mut |atom, _| f(atom));
+}
+// Also gives how many times the roll the atom is in is repeated.
+pub fn for_each_repeated_atom(input: &str, f: &mut dyn FnMut(&Atom, u32)) {
+	for node in parse(input).iter() {
+		if let Node::Roll(Roll::ExplicitRoll(check)) | Node::Roll(Roll::InlineRoll(check)) = node {
+			let repeat = check.repeat.map_or(1, |count| count.value().max(0) as u32);
+			visit_check(check, &mut |atom| f(atom, repeat));
+		}
 	}
-	pub fn run(&self) -> Option<String> {
-		let text = if self.dialog.run() == ResponseType::Ok {
-			Some(self.entry.get_text().to_string())
-		} else {
-			None
-		};
-		unsafe {
-			self.dialog.destroy();
+}
+fn visit_check(check: &Check, f: &mut dyn FnMut(&Atom)) {
+	for binding in check.bindings.iter() {
+		visit_expression(&binding.expression, f);
+	}
+	visit_expression(&check.expression, f);
+	if let Some(target) = &check.target {
+		visit_expression(&target.expression, f);
+	}
+}
+fn visit_expression(expression: &Expression, f: &mut dyn FnMut(&Atom)) {
+	match expression {
+		Expression::Add(lhs, rhs) | Expression::Subtract(lhs, rhs) => {
+			visit_expression(lhs, f);
+			visit_mul_div(rhs, f);
+		}
+		Expression::MulDiv(mul_div) => visit_mul_div(mul_div, f),
+	}
+}
+fn visit_mul_div(mul_div: &MulDiv, f: &mut dyn FnMut(&Atom)) {
+	match mul_div {
+		MulDiv::Multiply(lhs, rhs) | MulDiv::Divide(lhs, rhs) => {
+			visit_mul_div(lhs, f);
+			visit_power(rhs, f);
+		}
+		MulDiv::Power(power) => visit_power(power, f),
+	}
+}
+fn visit_power(power: &Power, f: &mut dyn FnMut(&Atom)) {
+	match power {
+		Power::Pow(base, exponent) => {
+			visit_unary(base, f);
+			visit_power(exponent, f);
+		}
+		Power::Unary(unary) => visit_unary(unary, f),
+	}
+}
+fn visit_unary(unary: &Unary, f: &mut dyn FnMut(&Atom)) {
+	match unary {
+		Unary::Minus(_, unary) => visit_unary(unary, f),
+		Unary::Atom(_, atom, _) => visit_atom(atom, f),
+	}
+}
+fn visit_atom(atom: &Atom, f: &mut dyn FnMut(&Atom)) {
+	f(atom);
+	match atom {
+		Atom::ParenthesesExpression(expression) | Atom::InlineRoll(expression) => {
+			visit_expression(expression, f)
+		}
+		Atom::Function(function) => {
+			for argument in function.arguments.iter() {
+				visit_expression(argument, f);
+			}
+		}
+		Atom::Dice(Dice::Computed(computed, _, _)) => {
+			visit_expression(&computed.count, f);
+			visit_expression(&computed.sides, f);
+		}
+		Atom::Dice(Dice::Custom(custom, _, _)) => visit_expression(&custom.count, f),
+		Atom::Group(group) => {
+			for member in group.members.iter() {
+				visit_expression(member, f);
+			}
+		}
+		Atom::Conditional(conditional) => {
+			visit_expression(&conditional.lhs, f);
+			visit_expression(&conditional.rhs, f);
+			visit_expression(&conditional.then, f);
+			visit_expression(&conditional.otherwise, f);
 		}
-		text
+		Atom::Number(_)
+		| Atom::Dice(_)
+		| Atom::RollQuery(_)
+		| Atom::Macro(_)
+		| Atom::Table(_)
+		| Atom::Variable(_)
+		| Atom::Attribute(_) => {}
 	}
 }
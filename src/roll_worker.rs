@@ -0,0 +1,130 @@
+// File: src/roll_worker.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use super::budget::{run_within, EvaluationBudget};
+use super::evaluator::RollMode;
+use super::limits::check_limits;
+use super::output::Output;
+use super::roll::{interpret, QueryAnswers, RollContext};
+
+// Deep explosion chains recurse once per die, give them room.
+pub const WORKER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// Evaluates rolls off of the GTK main thread. Results come back through a
+// glib channel so the callback runs on the main loop. Most workers give the
+// output of a roll, others like the initiative tracker's give what they
+// rolled.
+pub struct RollWorker<T = Output> {
+	sender: glib::Sender<(u64, String, Result<T, String>)>,
+	latest: Rc<Cell<u64>>,
+	cancelled: RefCell<Option<Arc<AtomicBool>>>,
+}
+impl<T: Send + 'static> RollWorker<T> {
+	// `on_done` is given the input that was rolled and its output.
+	pub fn new<F>(mut on_done: F) -> RollWorker<T>
+	where
+		F: FnMut(String, Result<T, String>) + 'static,
+	{
+		let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+		let latest = Rc::new(Cell::new(0));
+		{
+			let latest = Rc::clone(&latest);
+			receiver.attach(None, move |(id, input, output)| {
+				// Results of rolls that were replaced by a newer one are dropped
+				if id == latest.get() {
+					on_done(input, output);
+				}
+				glib::Continue(true)
+			});
+		}
+		RollWorker {
+			sender,
+			latest,
+			cancelled: RefCell::new(None),
+		}
+	}
+
+	// Runs `job` on a new thread, `input` is given back with its result.
+	// The job is passed the flag that is set when it is cancelled.
+	pub fn run<F>(&self, input: &str, job: F)
+	where
+		F: FnOnce(Arc<AtomicBool>) -> Result<T, String> + Send + 'static,
+	{
+		self.cancel();
+
+		let id = self.latest.get() + 1;
+		self.latest.set(id);
+		let cancelled = Arc::new(AtomicBool::new(false));
+		*self.cancelled.borrow_mut() = Some(Arc::clone(&cancelled));
+
+		let sender = self.sender.clone();
+		let input = input.to_owned();
+		thread::Builder::new()
+			.name(String::from("roll"))
+			.stack_size(WORKER_STACK_SIZE)
+			.spawn(move || {
+				let output = job(cancelled);
+				let _ = sender.send((id, input, output));
+			})
+			.expect("Could not start the roll worker thread.");
+	}
+	// The worker notices the cancellation on its next step or dice roll.
+	pub fn cancel(&self) {
+		if let Some(cancelled) = self.cancelled.borrow_mut().take() {
+			cancelled.store(true, Ordering::Relaxed);
+		}
+	}
+	// Cancels the running roll without reporting its result.
+	pub fn abandon(&self) {
+		self.cancel();
+		self.latest.set(self.latest.get() + 1);
+	}
+}
+impl RollWorker<Output> {
+	pub fn start(
+		&self,
+		input: &str,
+		answers: &QueryAnswers,
+		context: &RollContext,
+		budget: EvaluationBudget,
+		mode: RollMode,
+	) {
+		let answers = answers.clone();
+		let context = context.clone();
+		let source = input.to_owned();
+		self.run(input, move |cancelled| {
+			evaluate(&source, &answers, &context, budget, mode, cancelled)
+		});
+	}
+}
+
+// Checks the limits and interprets the input on the calling thread, for
+// jobs of a worker that roll more than one thing.
+pub fn evaluate(
+	input: &str,
+	answers: &QueryAnswers,
+	context: &RollContext,
+	budget: EvaluationBudget,
+	mode: RollMode,
+	cancelled: Arc<AtomicBool>,
+) -> Result<Output, String> {
+	let result = panic::catch_unwind(AssertUnwindSafe(|| {
+		check_limits(input, context, &budget).map_err(|e| e.to_string())?;
+		run_within(budget, cancelled, || {
+			interpret(input, answers, context, mode)
+		})
+		.map_err(|e| e.to_string())?
+	}));
+	match result {
+		Ok(result) => result,
+		Err(_) => Err(String::from("The roll could not be evaluated")),
+	}
+}
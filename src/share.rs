@@ -0,0 +1,269 @@
+// File: src/share.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+// The last roll written out for pasting into chat, from the output instead
+// of its text so dropped dice and totals can be marked up for where it is
+// pasted.
+
+use super::evaluator::{comparison_text, number_value};
+use super::output::*;
+use super::roll::{face_counts, repeat_summary, TextOptions};
+use super::templates::escape_html;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShareFormat {
+	// The totals in place of the rolls, `Attack 19`
+	Plain,
+	// Markdown with the totals in bold and dropped dice struck through
+	Discord,
+	// The roll and every die, like the result shows it
+	Breakdown,
+}
+impl ShareFormat {
+	pub fn from_name(name: &str) -> Option<ShareFormat> {
+		match name.to_lowercase().as_str() {
+			"plain" => Some(ShareFormat::Plain),
+			"discord" | "markdown" => Some(ShareFormat::Discord),
+			"breakdown" => Some(ShareFormat::Breakdown),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Markup {
+	Text,
+	Markdown,
+	Html,
+}
+impl Markup {
+	fn escape(self, text: &str) -> String {
+		match self {
+			Markup::Text => text.to_owned(),
+			Markup::Markdown => {
+				let mut escaped = String::new();
+				for c in text.chars() {
+					if ['\\', '*', '_', '~', '`', '|'].contains(&c) {
+						escaped.push('\\');
+					}
+					escaped.push(c);
+				}
+				escaped
+			}
+			Markup::Html => escape_html(text),
+		}
+	}
+	// The text is escaped already.
+	fn bold(self, text: &str) -> String {
+		match self {
+			Markup::Text => text.to_owned(),
+			Markup::Markdown => format!("**{}**", text),
+			Markup::Html => format!("<b>{}</b>", text),
+		}
+	}
+	fn italic(self, text: &str) -> String {
+		match self {
+			Markup::Text => text.to_owned(),
+			Markup::Markdown => format!("*{}*", text),
+			Markup::Html => format!("<i>{}</i>", text),
+		}
+	}
+	fn struck(self, text: &str) -> String {
+		match self {
+			Markup::Text => format!("~{}~", text),
+			Markup::Markdown => format!("~~{}~~", text),
+			Markup::Html => format!("<s>{}</s>", text),
+		}
+	}
+	fn line_break(self) -> &'static str {
+		match self {
+			Markup::Html => "<br>\n",
+			_ => "\n",
+		}
+	}
+}
+
+struct Writer {
+	markup: Markup,
+	// Every die instead of only the totals
+	breakdown: bool,
+	options: TextOptions,
+	text: String,
+}
+
+// The text that is pasted where rich text is not taken.
+pub fn share_text(output: &Output, format: ShareFormat, options: TextOptions) -> String {
+	let markup = match format {
+		ShareFormat::Discord => Markup::Markdown,
+		_ => Markup::Text,
+	};
+	write(output, format, markup, options)
+}
+// The same roll as rich text, the totals in bold.
+pub fn share_html(output: &Output, format: ShareFormat, options: TextOptions) -> String {
+	// Without the charset some programs read the dice symbols wrong
+	format!(
+		"<meta charset=\"utf-8\">{}",
+		write(output, format, Markup::Html, options)
+	)
+}
+
+fn write(output: &Output, format: ShareFormat, markup: Markup, options: TextOptions) -> String {
+	let mut writer = Writer {
+		markup,
+		breakdown: format != ShareFormat::Plain,
+		options,
+		text: String::new(),
+	};
+	if format == ShareFormat::Breakdown {
+		let source = markup.escape(&output.source);
+		writer.text.push_str(&match markup {
+			Markup::Html => format!("<code>{}</code>", source),
+			_ => source,
+		});
+		writer.text.push_str(markup.line_break());
+	}
+	writer.fragments(&output.fragments);
+	writer.text
+}
+
+impl Writer {
+	fn fragments(&mut self, fragments: &[OutputFragment]) {
+		let markup = self.markup;
+		for (i, fragment) in fragments.iter().enumerate() {
+			match fragment {
+				OutputFragment::StringLit(s) => self.text.push_str(&markup.escape(s)),
+				OutputFragment::Roll(RollType::ExplicitRoll(roll))
+				| OutputFragment::Roll(RollType::InlineRoll(roll)) => {
+					// A roll of only symbols has no total worth showing
+					let only_symbols =
+						matches!(fragments.get(i + 1), Some(OutputFragment::Symbols(_)))
+							&& number_value(roll.result) == 0.0;
+					if self.breakdown {
+						self.text.push_str(&self.formula(roll));
+					}
+					if !only_symbols {
+						if self.breakdown {
+							self.text.push('=');
+						}
+						let total = markup.escape(&roll.result.to_string());
+						self.text.push_str(&markup.bold(&total));
+					}
+				}
+				OutputFragment::Symbols(symbols) => {
+					let symbols = markup.escape(&symbols.to_string());
+					self.text.push_str(&format!(": {}", markup.bold(&symbols)));
+				}
+				OutputFragment::Outcome(outcome) => {
+					let target = match self.breakdown {
+						true => {
+							let formula = self.formula(&outcome.target);
+							match formula == markup.escape(&outcome.target.result.to_string()) {
+								true => formula,
+								false => format!("{}={}", formula, outcome.target.result),
+							}
+						}
+						false => markup.escape(&outcome.target.result.to_string()),
+					};
+					self.text.push_str(&format!(
+						" {} {}: {}",
+						markup.escape(comparison_text(outcome.comparison)),
+						target,
+						markup.bold(&markup.escape(&outcome.to_string()))
+					));
+				}
+				OutputFragment::Band(band) => {
+					let label = markup.escape(&band.label);
+					let label = match (&band.colour, markup) {
+						(Some(colour), Markup::Html) => format!(
+							"<span style=\"color: {}\">{}</span>",
+							escape_html(colour),
+							label
+						),
+						_ => label,
+					};
+					self.text.push_str(&format!(" ({})", label));
+				}
+				OutputFragment::Repeated(repeated) => {
+					for (i, fragments) in repeated.iter().enumerate() {
+						if i > 0 {
+							self.text.push_str(markup.line_break());
+						}
+						self.fragments(fragments);
+					}
+					if self.options.repeat_summary && !repeated.is_empty() {
+						self.text.push_str(markup.line_break());
+						self.text
+							.push_str(&markup.escape(&repeat_summary(repeated)));
+					}
+				}
+				OutputFragment::Card(card) => self.card(card),
+			}
+		}
+	}
+
+	// The title in bold, a line for each row and the footer in italics.
+	fn card(&mut self, card: &Card) {
+		let markup = self.markup;
+		if !self.text.is_empty() && !self.text.ends_with('\n') {
+			self.text.push_str(markup.line_break());
+		}
+		let title = self.part(&card.title);
+		self.text.push_str(&markup.bold(&title));
+		for (label, value) in card.rows.iter() {
+			let value = self.part(value);
+			self.text.push_str(markup.line_break());
+			if markup == Markup::Markdown {
+				self.text.push_str("> ");
+			}
+			let label = markup.escape(&format!("{}:", label));
+			self.text
+				.push_str(&format!("{} {}", markup.bold(&label), value));
+		}
+		if !card.footer.is_empty() {
+			let footer = self.part(&card.footer);
+			self.text.push_str(markup.line_break());
+			self.text.push_str(&markup.italic(&footer));
+		}
+	}
+	// Fragments written on their own, for the parts of a card.
+	fn part(&mut self, fragments: &[OutputFragment]) -> String {
+		let text = std::mem::take(&mut self.text);
+		self.fragments(fragments);
+		std::mem::replace(&mut self.text, text)
+	}
+
+	// `1d20+5` rolled, `~4~+14+5` with the dropped dice struck through.
+	fn formula(&self, roll: &ExpressionOutput) -> String {
+		let markup = self.markup;
+		let roll_text = |roll: &NumberRoll| match roll {
+			NumberRoll::Counted(value) => markup.escape(&value.value().to_string()),
+			NumberRoll::NotCounted(value) => markup.struck(&value.value().to_string()),
+		};
+		let mut text = String::new();
+		for formula_fragment in roll.formula_fragments.iter() {
+			match formula_fragment {
+				FormulaFragment::NumberRolls(first, rest, tooltip) => {
+					let rolls = std::iter::once(first).chain(rest.iter());
+					let rolls: Vec<String> = match self.options.group_faces {
+						true => face_counts(rolls)
+							.into_iter()
+							.map(|(roll, count)| match count {
+								1 => roll_text(&roll),
+								_ => format!("{}×{}", roll_text(&roll), count),
+							})
+							.collect(),
+						false => rolls.map(roll_text).collect(),
+					};
+					text.push_str(&rolls.join("+"));
+					if let Some(tooltip) = tooltip {
+						text.push_str(&markup.escape(&format!("[{}]", tooltip)));
+					}
+				}
+				other => text.push_str(&markup.escape(&other.to_string())),
+			}
+		}
+		text
+	}
+}
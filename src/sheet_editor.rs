@@ -0,0 +1,238 @@
+// File: src/sheet_editor.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use roll_lang::macros::Macros;
+
+use super::character::{Attribute, Character};
+
+type InsertHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn(&str)>>>>;
+
+struct Row {
+	name: gtk::Entry,
+	value: gtk::Entry,
+	widgets: Vec<gtk::Widget>,
+}
+
+// A grid of name and value rows. Rows are built at runtime so it can hold
+// any number of them.
+#[derive(Clone)]
+struct RowList {
+	grid: gtk::Grid,
+	rows: Rc<RefCell<Vec<Row>>>,
+	next_row: Rc<Cell<i32>>,
+	on_insert: InsertHandler,
+	// How the insert button writes a name, `@{}` or `#{}`
+	sigil: char,
+	placeholders: (&'static str, &'static str),
+	value_width: i32,
+}
+impl RowList {
+	fn new(
+		on_insert: &InsertHandler,
+		sigil: char,
+		placeholders: (&'static str, &'static str),
+		value_width: i32,
+	) -> RowList {
+		let grid = gtk::Grid::new();
+		grid.set_row_spacing(5);
+		grid.set_column_spacing(5);
+		RowList {
+			grid,
+			rows: Rc::new(RefCell::new(Vec::new())),
+			next_row: Rc::new(Cell::new(0)),
+			on_insert: Rc::clone(on_insert),
+			sigil,
+			placeholders,
+			value_width,
+		}
+	}
+
+	fn clear(&self) {
+		for row in self.rows.borrow_mut().drain(..) {
+			for widget in row.widgets {
+				unsafe {
+					widget.destroy();
+				}
+			}
+		}
+		self.next_row.set(0);
+	}
+	// Rows without a name are left out.
+	fn values(&self) -> Vec<(String, String)> {
+		self.rows
+			.borrow()
+			.iter()
+			.map(|row| {
+				(
+					row.name.get_text().trim().to_owned(),
+					row.value.get_text().trim().to_owned(),
+				)
+			})
+			.filter(|(name, _)| !name.is_empty())
+			.collect()
+	}
+
+	fn add_row(&self, name_text: &str, value_text: &str) {
+		// Removed rows leave a gap in the grid, it collapses empty rows
+		let row = self.next_row.get();
+		self.next_row.set(row + 1);
+
+		let name = gtk::Entry::new();
+		name.set_width_chars(10);
+		name.set_placeholder_text(Some(self.placeholders.0));
+		name.set_text(name_text);
+		let value = gtk::Entry::new();
+		value.set_width_chars(self.value_width);
+		value.set_placeholder_text(Some(self.placeholders.1));
+		value.set_text(value_text);
+		let insert = gtk::Button::with_label("Insert");
+		let remove = gtk::Button::with_label("Remove");
+
+		self.grid.attach(&name, 0, row, 1, 1);
+		self.grid.attach(&value, 1, row, 1, 1);
+		self.grid.attach(&insert, 2, row, 1, 1);
+		self.grid.attach(&remove, 3, row, 1, 1);
+
+		{
+			let name = name.clone();
+			let on_insert = Rc::clone(&self.on_insert);
+			let sigil = self.sigil;
+			insert.connect_clicked(move |_| {
+				let row_name = name.get_text().trim().to_owned();
+				if row_name.is_empty() {
+					return;
+				}
+				if let Some(handler) = on_insert.borrow().as_ref() {
+					handler(&format!("{}{{{}}}", sigil, row_name));
+				}
+			});
+		}
+		{
+			let name = name.clone();
+			let rows = Rc::clone(&self.rows);
+			remove.connect_clicked(move |_| {
+				let mut rows = rows.borrow_mut();
+				if let Some(index) = rows.iter().position(|row| row.name == name) {
+					for widget in rows.remove(index).widgets {
+						unsafe {
+							widget.destroy();
+						}
+					}
+				}
+			});
+		}
+
+		let widgets = vec![
+			name.clone().upcast::<gtk::Widget>(),
+			value.clone().upcast(),
+			insert.upcast(),
+			remove.upcast(),
+		];
+		self.rows.borrow_mut().push(Row {
+			name,
+			value,
+			widgets,
+		});
+	}
+}
+
+// Editor for the attributes and macros of the active profile.
+pub struct SheetEditor {
+	name: gtk::Entry,
+	attributes: RowList,
+	macros: RowList,
+	save_button: gtk::Button,
+	on_insert: InsertHandler,
+}
+impl SheetEditor {
+	pub fn new(builder: &gtk::Builder) -> SheetEditor {
+		let container: gtk::Box = builder
+			.get_object("character_sheet")
+			.expect("Could not get box character_sheet.");
+
+		let name = gtk::Entry::new();
+		name.set_placeholder_text(Some("Character name"));
+
+		let on_insert: InsertHandler = Rc::new(RefCell::new(None));
+		let attributes = RowList::new(&on_insert, '@', ("str_mod", "3"), 6);
+		let macros = RowList::new(&on_insert, '#', ("attack", "/r 1d20 + @{str_mod}"), 16);
+
+		let add_attribute = gtk::Button::with_label("Add Attribute");
+		let add_macro = gtk::Button::with_label("Add Macro");
+		let save_button = gtk::Button::with_label("Save");
+		let actions = gtk::Box::new(Orientation::Horizontal, 5);
+		actions.pack_start(&add_attribute, false, true, 0);
+		actions.pack_start(&add_macro, false, true, 0);
+		actions.pack_end(&save_button, false, true, 0);
+
+		container.pack_start(&name, false, true, 0);
+		container.pack_start(&gtk::Label::new(Some("Attributes")), false, true, 0);
+		container.pack_start(&attributes.grid, false, true, 0);
+		container.pack_start(&gtk::Label::new(Some("Macros")), false, true, 0);
+		container.pack_start(&macros.grid, false, true, 0);
+		container.pack_start(&actions, false, true, 0);
+
+		for (button, list) in [(&add_attribute, &attributes), (&add_macro, &macros)].iter() {
+			let list = (*list).clone();
+			button.connect_clicked(move |_| {
+				list.add_row("", "");
+				list.grid.show_all();
+			});
+		}
+
+		SheetEditor {
+			name,
+			attributes,
+			macros,
+			save_button,
+			on_insert,
+		}
+	}
+
+	pub fn show(&self, character: &Character, macros: &Macros) {
+		self.name.set_text(&character.name);
+		self.attributes.clear();
+		for attribute in character.attributes.iter() {
+			self.attributes.add_row(&attribute.name, &attribute.value);
+		}
+		self.attributes.grid.show_all();
+
+		let mut macros: Vec<(&String, &String)> = macros.iter().collect();
+		macros.sort();
+		self.macros.clear();
+		for (name, source) in macros {
+			self.macros.add_row(name, source);
+		}
+		self.macros.grid.show_all();
+	}
+	// The character as it is shown, empty rows are left out.
+	pub fn character(&self) -> Character {
+		Character {
+			name: self.name.get_text().trim().to_owned(),
+			attributes: self
+				.attributes
+				.values()
+				.into_iter()
+				.map(|(name, value)| Attribute { name, value })
+				.collect(),
+		}
+	}
+	pub fn macros(&self) -> Macros {
+		self.macros.values().into_iter().collect()
+	}
+
+	// Called with `@{name}` or `#{name}` when a row's insert button is clicked.
+	pub fn connect_insert<F: Fn(&str) + 'static>(&self, f: F) {
+		*self.on_insert.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+	pub fn save_button(&self) -> &gtk::Button {
+		&self.save_button
+	}
+}
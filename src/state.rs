@@ -5,6 +5,9 @@
 use gtk::{EditableExt, EntryExt};
 use std::collections::VecDeque;
 
+use super::encounter::Encounter;
+use super::output::Mark;
+
 pub type RollResult = Result<String, String>;
 
 #[derive(Debug, Clone)]
@@ -12,6 +15,9 @@ pub struct State {
 	pub roll_entry_cursor: usize,
 	pub roll_entry: String,
 	roll_result: RollResult,
+	// The parts of the result shown in their own style
+	roll_marks: Vec<Mark>,
+	pub encounter: Encounter,
 }
 impl Default for State {
 	fn default() -> Self {
@@ -19,6 +25,8 @@ impl Default for State {
 			roll_entry_cursor: 4,
 			roll_entry: String::from("/r [] \\"),
 			roll_result: Ok(String::new()),
+			roll_marks: Vec::new(),
+			encounter: Encounter::default(),
 		}
 	}
 }
@@ -27,12 +35,16 @@ impl State {
 		self.roll_entry_cursor = entry.get_position() as usize;
 		self.roll_entry = entry.get_text().to_string();
 	}
-	pub fn update_from_roll_result(&mut self, res: &RollResult) {
+	pub fn update_from_roll_result(&mut self, res: &RollResult, marks: &[Mark]) {
 		self.roll_result = res.clone();
+		self.roll_marks = marks.to_vec();
 	}
 	pub fn roll_result(&self) -> &RollResult {
 		&self.roll_result
 	}
+	pub fn roll_marks(&self) -> &[Mark] {
+		&self.roll_marks
+	}
 	pub fn execute(&mut self, command: &mut Box<impl Command>) {
 		*self = command.execute(self);
 	}
@@ -133,6 +145,8 @@ impl<'a> Command for InsertCommand<'a> {
 				roll_entry_cursor: new_cursor_pos,
 				roll_entry: new_entry_val,
 				roll_result: state.roll_result.clone(),
+				roll_marks: state.roll_marks.clone(),
+				encounter: state.encounter.clone(),
 			}
 		} else {
 			state.clone()
@@ -146,7 +160,11 @@ impl Default for ClearCommand {
     }
 }
 impl Command for ClearCommand {
-	fn execute(&mut self, _state: &State) -> State {
-		State::default()
+	// The encounter is not part of the entry so it is kept
+	fn execute(&mut self, state: &State) -> State {
+		State {
+			encounter: state.encounter.clone(),
+			..State::default()
+		}
 	}
 }
@@ -0,0 +1,410 @@
+// File: src/stats.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::fmt;
+
+use super::history_export::{local_time, DateRange, ExportRow};
+use super::parser::parse;
+use super::syntax::Node;
+
+// Rolls further apart than this are in different sessions
+const SESSION_GAP: i64 = 6 * 60 * 60;
+
+// A stretch of rolls without a long break, named after the day it started.
+#[derive(Debug, Clone)]
+pub struct Session {
+	pub name: String,
+	pub range: DateRange,
+}
+
+// Splits rows, oldest first, into sessions. Rows without a time are in
+// none of them.
+pub fn sessions(rows: &[ExportRow]) -> Vec<Session> {
+	let mut sessions: Vec<Session> = Vec::new();
+	let mut last = None;
+	for time in rows.iter().filter_map(|row| row.entry.time) {
+		match (sessions.last_mut(), last) {
+			(Some(session), Some(last)) if time - last <= SESSION_GAP => {
+				session.range.end = Some(time + 1)
+			}
+			_ => {
+				let day = local_time(time, "%Y-%m-%d");
+				let same_day = sessions
+					.iter()
+					.filter(|session| session.name.starts_with(&day))
+					.count();
+				sessions.push(Session {
+					name: match same_day {
+						0 => day,
+						_ => format!("{} ({})", day, same_day + 1),
+					},
+					range: DateRange {
+						start: Some(time),
+						end: Some(time + 1),
+					},
+				});
+			}
+		}
+		last = Some(time);
+	}
+	sessions
+}
+
+// The names of the macros called in a roll, `#attack`.
+pub fn macro_names(input: &str) -> Vec<String> {
+	parse(input)
+		.into_iter()
+		.filter_map(|node| match node {
+			Node::Macro(my_macro, _) => Some(my_macro.name),
+			_ => None,
+		})
+		.collect()
+}
+
+// Which rows the statistics are of, no filter is every row.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+	pub character: Option<String>,
+	pub session: Option<DateRange>,
+	pub macro_name: Option<String>,
+}
+impl Filter {
+	pub fn matches(&self, row: &ExportRow) -> bool {
+		self.character
+			.as_ref()
+			.is_none_or(|character| character == row.character)
+			&& self
+				.session
+				.is_none_or(|session| row.entry.time.is_some() && session.contains(row.entry.time))
+			&& self
+				.macro_name
+				.as_ref()
+				.is_none_or(|name| macro_names(&row.entry.input).contains(name))
+	}
+}
+
+// How many times each face of one size of die came up.
+#[derive(Debug, Clone)]
+pub struct DieStats {
+	pub sides: i32,
+	// The count of the face one higher than the index
+	pub counts: Vec<u32>,
+}
+impl DieStats {
+	pub fn rolls(&self) -> u32 {
+		self.counts.iter().sum()
+	}
+	// Pearson's chi-square statistic against a fair die and the chance of
+	// a fair die being at least as far off. Nothing until every face is
+	// expected to have come up five times, before that the test says little.
+	pub fn fairness(&self) -> Option<(f64, f64)> {
+		let expected = self.rolls() as f64 / self.sides as f64;
+		if self.sides < 2 || expected < 5.0 {
+			return None;
+		}
+		let chi_square: f64 = self
+			.counts
+			.iter()
+			.map(|&count| (count as f64 - expected).powi(2) / expected)
+			.sum();
+		let degrees = (self.sides - 1) as f64;
+		Some((chi_square, upper_gamma(degrees / 2.0, chi_square / 2.0)))
+	}
+}
+
+// The natural 20s and 1s of a character and their average d20.
+#[derive(Debug, Clone, Default)]
+pub struct CharacterStats {
+	pub name: String,
+	pub rolls: usize,
+	pub d20s: u32,
+	pub d20_total: u32,
+	pub crits: u32,
+	pub fumbles: u32,
+}
+
+// The longest runs in the rows, in the order they were rolled.
+#[derive(Debug, Clone, Default)]
+pub struct Streaks {
+	// d20s of 11 or higher and of 10 or lower
+	pub high: u32,
+	pub low: u32,
+	// Checks against a target
+	pub successes: u32,
+	pub failures: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+	pub rolls: usize,
+	pub characters: Vec<CharacterStats>,
+	pub dice: Vec<DieStats>,
+	// The name of each session with damage rolls and their average
+	pub damage: Vec<(String, f32)>,
+	pub streaks: Streaks,
+}
+
+// The rows are filtered already and oldest first.
+pub fn compute(rows: &[ExportRow], sessions: &[Session]) -> Stats {
+	let mut stats = Stats {
+		rolls: rows.len(),
+		..Stats::default()
+	};
+	let mut high = 0;
+	let mut low = 0;
+	let mut successes = 0;
+	let mut failures = 0;
+	for row in rows.iter() {
+		let index = match stats
+			.characters
+			.iter()
+			.position(|character| character.name == row.character)
+		{
+			Some(index) => index,
+			None => {
+				stats.characters.push(CharacterStats {
+					name: row.character.to_owned(),
+					..CharacterStats::default()
+				});
+				stats.characters.len() - 1
+			}
+		};
+		let character = &mut stats.characters[index];
+		character.rolls += 1;
+
+		// Compounded dice add up past their sides and are left out
+		for die in row.entry.dice.iter() {
+			if die.sides < 1 || die.face < 1 || die.face > die.sides {
+				continue;
+			}
+			let index = match stats.dice.iter().position(|stats| stats.sides == die.sides) {
+				Some(index) => index,
+				None => {
+					stats.dice.push(DieStats {
+						sides: die.sides,
+						counts: vec![0; die.sides as usize],
+					});
+					stats.dice.len() - 1
+				}
+			};
+			stats.dice[index].counts[(die.face - 1) as usize] += 1;
+
+			if die.sides == 20 {
+				character.d20s += 1;
+				character.d20_total += die.face as u32;
+				match die.face {
+					20 => character.crits += 1,
+					1 => character.fumbles += 1,
+					_ => (),
+				}
+				let (run, other) = match die.face >= 11 {
+					true => (&mut high, &mut low),
+					false => (&mut low, &mut high),
+				};
+				*run += 1;
+				*other = 0;
+				stats.streaks.high = stats.streaks.high.max(high);
+				stats.streaks.low = stats.streaks.low.max(low);
+			}
+		}
+
+		if let Ok(result) = &row.entry.result {
+			for success in outcomes(result) {
+				let (run, other) = match success {
+					true => (&mut successes, &mut failures),
+					false => (&mut failures, &mut successes),
+				};
+				*run += 1;
+				*other = 0;
+				stats.streaks.successes = stats.streaks.successes.max(successes);
+				stats.streaks.failures = stats.streaks.failures.max(failures);
+			}
+		}
+	}
+	stats.dice.sort_by_key(|die| die.sides);
+
+	for session in sessions.iter() {
+		let damage: Vec<f32> = rows
+			.iter()
+			.filter(|row| row.entry.time.is_some() && session.range.contains(row.entry.time))
+			.flat_map(damage_totals)
+			.collect();
+		if !damage.is_empty() {
+			let average = damage.iter().sum::<f32>() / damage.len() as f32;
+			stats.damage.push((session.name.clone(), average));
+		}
+	}
+	stats
+}
+
+// Whether each check in a result succeeded, in order.
+fn outcomes(result: &str) -> Vec<bool> {
+	let mut found: Vec<(usize, bool)> = result
+		.match_indices("Success by ")
+		.map(|(i, _)| (i, true))
+		.chain(result.match_indices("Fail by ").map(|(i, _)| (i, false)))
+		.collect();
+	found.sort_by_key(|&(i, _)| i);
+	found.into_iter().map(|(_, success)| success).collect()
+}
+// A roll is damage when it or a macro it calls says so, `#damage` or
+// `[[2d6]] fire damage`. In cards the damage rows are, critical damage
+// is left out.
+fn damage_totals(row: &ExportRow) -> Vec<f32> {
+	let is_damage = |text: &str| {
+		let text = text.to_lowercase();
+		(text.contains("damage") || text.contains("dmg")) && !text.contains("crit")
+	};
+	if is_damage(&row.entry.input) {
+		return row.entry.totals.clone();
+	}
+	let result = match &row.entry.result {
+		Ok(result) => result,
+		Err(_) => return Vec::new(),
+	};
+	result
+		.lines()
+		.filter_map(|line| {
+			let row = line.strip_prefix("┃ ")?;
+			let colon = row.find(": ")?;
+			if !is_damage(&row[..colon]) {
+				return None;
+			}
+			row[(colon + 2)..].rsplit('=').next()?.trim().parse().ok()
+		})
+		.collect()
+}
+
+// The regularized upper incomplete gamma function Q(a, x), the chance of
+// a chi-square value of at least 2x with 2a degrees of freedom.
+fn upper_gamma(a: f64, x: f64) -> f64 {
+	if x <= 0.0 {
+		return 1.0;
+	}
+	let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+	if x < a + 1.0 {
+		// The series of the lower function converges quickly here
+		let mut term = 1.0 / a;
+		let mut sum = term;
+		let mut n = a;
+		for _ in 0..500 {
+			n += 1.0;
+			term *= x / n;
+			sum += term;
+			if term.abs() < sum.abs() * 1e-12 {
+				break;
+			}
+		}
+		(1.0 - sum * front).clamp(0.0, 1.0)
+	} else {
+		// Lentz's method for the continued fraction of the upper function
+		let tiny = 1e-300;
+		let mut b = x + 1.0 - a;
+		let mut c = 1.0 / tiny;
+		let mut d = 1.0 / b;
+		let mut h = d;
+		for i in 1..500 {
+			let an = -(i as f64) * (i as f64 - a);
+			b += 2.0;
+			d = an * d + b;
+			if d.abs() < tiny {
+				d = tiny;
+			}
+			c = b + an / c;
+			if c.abs() < tiny {
+				c = tiny;
+			}
+			d = 1.0 / d;
+			let step = d * c;
+			h *= step;
+			if (step - 1.0).abs() < 1e-12 {
+				break;
+			}
+		}
+		(front * h).clamp(0.0, 1.0)
+	}
+}
+// Lanczos' approximation of the logarithm of the gamma function.
+fn ln_gamma(x: f64) -> f64 {
+	let coefficients = [
+		76.180_091_729_471_46,
+		-86.505_320_329_416_77,
+		24.014_098_240_830_91,
+		-1.231_739_572_450_155,
+		0.120_865_097_386_617_9e-2,
+		-0.539_523_938_495_3e-5,
+	];
+	let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+	let mut series = 1.000_000_000_190_015;
+	for (i, coefficient) in coefficients.iter().enumerate() {
+		series += coefficient / (x + 1.0 + i as f64);
+	}
+	-tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+// The report shown in the statistics window.
+impl fmt::Display for Stats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "{} rolls", self.rolls)?;
+		if !self.characters.is_empty() {
+			writeln!(f, "\nCharacters")?;
+		}
+		for character in self.characters.iter() {
+			write!(f, "  {}: {} rolls", character.name, character.rolls)?;
+			if character.d20s > 0 {
+				write!(
+					f,
+					", d20 average {:.1}, {} natural 20s, {} natural 1s",
+					character.d20_total as f32 / character.d20s as f32,
+					character.crits,
+					character.fumbles
+				)?;
+			}
+			writeln!(f)?;
+		}
+		if !self.dice.is_empty() {
+			writeln!(f, "\nFaces")?;
+		}
+		for die in self.dice.iter() {
+			let faces: Vec<String> = die
+				.counts
+				.iter()
+				.enumerate()
+				.map(|(i, count)| format!("{}:{}", i + 1, count))
+				.collect();
+			writeln!(
+				f,
+				"  d{}, {} rolls: {}",
+				die.sides,
+				die.rolls(),
+				faces.join(" ")
+			)?;
+			match die.fairness() {
+				Some((chi_square, p)) => writeln!(
+					f,
+					"    chi-square {:.2}, p {:.3}: {}",
+					chi_square,
+					p,
+					match p {
+						p if p < 0.01 => "very unlikely from a fair die",
+						p if p < 0.05 => "unlikely from a fair die",
+						_ => "looks fair",
+					}
+				)?,
+				None => writeln!(f, "    too few rolls to test fairness")?,
+			}
+		}
+		if !self.damage.is_empty() {
+			writeln!(f, "\nAverage damage per session")?;
+		}
+		for (session, average) in self.damage.iter() {
+			writeln!(f, "  {}: {:.1}", session, average)?;
+		}
+		writeln!(f, "\nStreaks")?;
+		writeln!(f, "  d20s of 11 or higher in a row: {}", self.streaks.high)?;
+		writeln!(f, "  d20s of 10 or lower in a row: {}", self.streaks.low)?;
+		writeln!(f, "  Successes in a row: {}", self.streaks.successes)?;
+		write!(f, "  Failures in a row: {}", self.streaks.failures)
+	}
+}
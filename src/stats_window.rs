@@ -0,0 +1,181 @@
+// File: src/stats_window.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use super::history_export::{self, DateRange, ExportRow};
+use super::profile::{HistoryEntry, Profile, ProfileBook};
+use super::stats::{self, Filter, Session};
+
+// Statistics of the history of every character, filtered by character,
+// session and macro. The first entry of each filter is everything.
+#[derive(Clone)]
+pub struct StatsWindow {
+	window: gtk::Window,
+	character: gtk::ComboBoxText,
+	session: gtk::ComboBoxText,
+	macro_name: gtk::ComboBoxText,
+	report: gtk::Label,
+	// The character of each entry, oldest first
+	entries: Rc<RefCell<Vec<(String, HistoryEntry)>>>,
+	sessions: Rc<RefCell<Vec<Session>>>,
+	// Set while the filters are filled so they do not update the report
+	updating: Rc<Cell<bool>>,
+}
+impl StatsWindow {
+	pub fn new(parent: &gtk::Window) -> StatsWindow {
+		let window = gtk::Window::new(WindowType::Toplevel);
+		window.set_title("Statistics");
+		window.set_transient_for(Some(parent));
+		window.set_default_size(600, 500);
+
+		let character = gtk::ComboBoxText::new();
+		character.set_tooltip_text(Some("Character"));
+		let session = gtk::ComboBoxText::new();
+		session.set_tooltip_text(Some("Session, rolls less than six hours apart"));
+		let macro_name = gtk::ComboBoxText::new();
+		macro_name.set_tooltip_text(Some("Rolls that call the macro"));
+		let filters = gtk::Box::new(Orientation::Horizontal, 5);
+		filters.pack_start(&character, true, true, 0);
+		filters.pack_start(&session, true, true, 0);
+		filters.pack_start(&macro_name, true, true, 0);
+
+		let report = gtk::Label::new(None);
+		report.set_halign(Align::Start);
+		report.set_valign(Align::Start);
+		report.set_xalign(0.0);
+		report.set_line_wrap(true);
+		report.set_selectable(true);
+		let scrolled = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+		scrolled.set_policy(PolicyType::Never, PolicyType::Automatic);
+		scrolled.add(&report);
+
+		let column = gtk::Box::new(Orientation::Vertical, 5);
+		column.set_border_width(5);
+		column.pack_start(&filters, false, true, 0);
+		column.pack_start(&scrolled, true, true, 0);
+		window.add(&column);
+		// Closing it only hides it so it can be shown again
+		window.connect_delete_event(|window, _| {
+			window.hide();
+			Inhibit(true)
+		});
+
+		let stats_window = StatsWindow {
+			window,
+			character,
+			session,
+			macro_name,
+			report,
+			entries: Rc::new(RefCell::new(Vec::new())),
+			sessions: Rc::new(RefCell::new(Vec::new())),
+			updating: Rc::new(Cell::new(false)),
+		};
+		for combo in [
+			&stats_window.character,
+			&stats_window.session,
+			&stats_window.macro_name,
+		]
+		.iter()
+		{
+			let stats_window = stats_window.clone();
+			combo.connect_changed(move |_| {
+				if !stats_window.updating.get() {
+					stats_window.update();
+				}
+			});
+		}
+		stats_window
+	}
+
+	// Takes the history of every profile and shows the statistics of all of
+	// it.
+	pub fn show(&self, profiles: &ProfileBook) {
+		let all: Vec<&Profile> = profiles.profiles().iter().collect();
+		let rows = history_export::rows(&all, DateRange::default());
+		let sessions = stats::sessions(&rows);
+		let mut characters: Vec<String> = Vec::new();
+		let mut macros: Vec<String> = Vec::new();
+		for row in rows.iter() {
+			if !characters.iter().any(|name| name == row.character) {
+				characters.push(row.character.to_owned());
+			}
+			for name in stats::macro_names(&row.entry.input) {
+				if !macros.contains(&name) {
+					macros.push(name);
+				}
+			}
+		}
+		characters.sort();
+		macros.sort();
+
+		self.updating.set(true);
+		fill(&self.character, "Every character", &characters);
+		let session_names: Vec<String> = sessions
+			.iter()
+			.map(|session| session.name.clone())
+			.collect();
+		fill(&self.session, "Every session", &session_names);
+		let macro_names: Vec<String> = macros.iter().map(|name| format!("#{}", name)).collect();
+		fill(&self.macro_name, "Every roll", &macro_names);
+		self.updating.set(false);
+
+		*self.entries.borrow_mut() = rows
+			.iter()
+			.map(|row| (row.character.to_owned(), row.entry.clone()))
+			.collect();
+		*self.sessions.borrow_mut() = sessions;
+		self.update();
+		self.window.show_all();
+		self.window.present();
+	}
+
+	fn filter(&self) -> Filter {
+		let selected = |combo: &gtk::ComboBoxText| match combo.get_active() {
+			None | Some(0) => None,
+			Some(_) => combo.get_active_text().map(|text| text.to_string()),
+		};
+		let sessions = self.sessions.borrow();
+		Filter {
+			character: selected(&self.character),
+			session: self
+				.session
+				.get_active()
+				.filter(|&index| index > 0)
+				.and_then(|index| sessions.get(index as usize - 1))
+				.map(|session| session.range),
+			macro_name: selected(&self.macro_name)
+				.map(|name| name.trim_start_matches('#').to_owned()),
+		}
+	}
+	fn update(&self) {
+		let filter = self.filter();
+		let entries = self.entries.borrow();
+		let rows: Vec<ExportRow> = entries
+			.iter()
+			.map(|(character, entry)| ExportRow { character, entry })
+			.filter(|row| filter.matches(row))
+			.collect();
+		let stats = stats::compute(&rows, &self.sessions.borrow());
+		self.report.set_markup(&format!(
+			"<tt>{}</tt>",
+			glib::markup_escape_text(&stats.to_string())
+		));
+	}
+}
+
+// Replaces the entries with everything followed by the names and selects
+// everything.
+fn fill(combo: &gtk::ComboBoxText, everything: &str, names: &[String]) {
+	combo.remove_all();
+	combo.append_text(everything);
+	for name in names.iter() {
+		combo.append_text(name);
+	}
+	combo.set_active(Some(0));
+}
@@ -0,0 +1,88 @@
+// File: src/storage.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml::value::Table;
+use toml::Value;
+
+use super::APP_NAME;
+
+#[derive(Debug)]
+pub enum StorageError {
+	Io(io::Error),
+	Parse(toml::de::Error),
+	NotATable,
+}
+impl fmt::Display for StorageError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			StorageError::Io(error) => write!(f, "{}", error),
+			StorageError::Parse(error) => write!(f, "{}", error),
+			StorageError::NotATable => write!(f, "Expected a table"),
+		}
+	}
+}
+impl From<io::Error> for StorageError {
+	fn from(error: io::Error) -> Self {
+		StorageError::Io(error)
+	}
+}
+impl From<toml::de::Error> for StorageError {
+	fn from(error: toml::de::Error) -> Self {
+		StorageError::Parse(error)
+	}
+}
+
+// Everything the app saves lives under the user data directory.
+pub fn data_dir() -> PathBuf {
+	glib::get_user_data_dir()
+		.unwrap_or_else(|| PathBuf::from("."))
+		.join(APP_NAME)
+}
+
+pub fn load_table(path: &Path) -> Result<Table, StorageError> {
+	let source = fs::read_to_string(path)?;
+	match source.parse::<Value>()? {
+		Value::Table(table) => Ok(table),
+		_ => Err(StorageError::NotATable),
+	}
+}
+pub fn save_table(path: &Path, table: &Table) -> Result<(), StorageError> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	fs::write(path, Value::Table(table.clone()).to_string())?;
+	Ok(())
+}
+
+// Turns a user given name into something that is safe to use as a file name.
+pub fn file_stem(name: &str) -> String {
+	let stem: String = name
+		.chars()
+		.map(|c| {
+			if c.is_alphanumeric() || c == '-' {
+				c
+			} else {
+				'_'
+			}
+		})
+		.collect();
+	if stem.is_empty() {
+		String::from("unnamed")
+	} else {
+		stem
+	}
+}
+
+pub fn get_str(table: &Table, key: &str) -> String {
+	table
+		.get(key)
+		.and_then(Value::as_str)
+		.unwrap_or_default()
+		.to_owned()
+}
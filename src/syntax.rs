@@ -0,0 +1,213 @@
+// File: src/syntax.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+// The syntax tree rolls are parsed into. It follows roll_lang's tree and
+// reuses its leaves, with additions roll_lang does not have.
+
+pub use roll_lang::ast::number::*;
+pub use roll_lang::ast::{
+	Comparison, Compounding, Expanding, Exploding, Fate, Macro, Normal, Penetrating, PostModifier,
+	Reroll, RerollT, RollQuery,
+};
+pub use roll_lang::parser::ParseError;
+
+#[derive(Debug, Clone)]
+pub enum Node {
+	StringLiteral(String),
+	// `#move|bands:pbta` gives the rolls of the macro bands
+	Macro(Macro, Option<String>),
+	// `@{weapon}` in text is replaced by the attribute rolled like text
+	Attribute(String),
+	Roll(Roll),
+	ParseError(ParseError),
+}
+// `&{template:attack} {{name=Longsword}} {{attack=[[1d20+5]]}}`, a card
+// with the fields in it. The value of a field is rolled like a message.
+#[derive(Debug, Clone)]
+pub struct TemplateCall {
+	pub name: String,
+	pub fields: Vec<(String, String)>,
+	// What follows a `{{` that is never closed, shown as it is
+	pub unclosed: String,
+}
+#[derive(Debug, Clone)]
+pub enum Roll {
+	ExplicitRoll(Check),
+	InlineRoll(Check),
+}
+// An expression with an optional target, `1d20+5 vs 15`.
+#[derive(Debug, Clone)]
+pub struct Check {
+	// `$atk = 1d20+5;` before the expression
+	pub bindings: Vec<Binding>,
+	pub expression: Box<Expression>,
+	pub target: Option<Box<Target>>,
+	// `6x(4d6kh3)` and `6# 4d6kh3` roll the check six times
+	pub repeat: Option<Integer>,
+	// `[[2d6+2]]|bands:pbta` names what the total means
+	pub bands: Option<String>,
+}
+#[derive(Debug, Clone)]
+pub struct Target {
+	pub comparison: Comparison,
+	pub expression: Expression,
+	// `1d8w vs 4 raises` counts a raise for every 4 over the target
+	pub raises: Option<Integer>,
+}
+// The value can be used as `$atk` in the rest of the roll.
+#[derive(Debug, Clone)]
+pub struct Binding {
+	pub name: String,
+	pub expression: Expression,
+}
+#[derive(Debug, Clone)]
+pub enum Expression {
+	Add(Box<Expression>, MulDiv),
+	Subtract(Box<Expression>, MulDiv),
+	MulDiv(MulDiv),
+}
+#[derive(Debug, Clone)]
+pub enum MulDiv {
+	Multiply(Box<MulDiv>, Power),
+	Divide(Box<MulDiv>, Power),
+	Power(Power),
+}
+#[derive(Debug, Clone)]
+pub enum Power {
+	Pow(Unary, Box<Power>),
+	Unary(Unary),
+}
+#[derive(Debug, Clone)]
+pub enum Unary {
+	Minus(Option<String>, Box<Unary>),
+	Atom(Option<String>, Atom, Option<String>),
+}
+#[derive(Debug, Clone)]
+pub enum Atom {
+	Number(Number),
+	Dice(Dice),
+	Function(Function),
+	RollQuery(RollQuery),
+	ParenthesesExpression(Box<Expression>),
+	InlineRoll(Box<Expression>),
+	Macro(Macro),
+	Group(Group),
+	Table(TableRoll),
+	Variable(String),
+	Conditional(Box<Conditional>),
+	// `@{str_mod}`, the attribute of the character rolled for
+	Attribute(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Dice {
+	Normal(Normal, Modifiers, Option<String>),
+	Fate(Fate, Modifiers, Option<String>),
+	Computed(Computed, Modifiers, Option<String>),
+	Custom(CustomDice, Modifiers, Option<String>),
+}
+// roll_lang's dice modifiers along with the ones it does not have.
+#[derive(Debug, Clone, Default)]
+pub struct Modifiers {
+	pub expanding: Option<Expanding>,
+	pub reroll_modifiers: Vec<Reroll>,
+	pub post_modifiers: Vec<PostModifier>,
+	pub sort: Option<Sort>,
+	// The sides of the wild die of `1d8w` and `1d8w8`
+	pub wild: Option<Integer>,
+}
+// `10d6s` and `10d6sd` show the dice lowest or highest first.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sort {
+	Ascending,
+	Descending,
+}
+
+#[derive(Debug, Clone)]
+pub struct Computed {
+	pub count: Box<Expression>,
+	pub sides: Box<Expression>,
+}
+
+// `3d{ability}` rolls three of the dice named ability in the dice library,
+// `2d[1,2,2,3]` two dice with the faces listed.
+#[derive(Debug, Clone)]
+pub struct CustomDice {
+	pub count: Box<Expression>,
+	pub faces: CustomFaces,
+}
+#[derive(Debug, Clone)]
+pub enum CustomFaces {
+	Named(String),
+	Listed(Vec<i32>),
+}
+
+// `{4d6+3, 4d6+2}kh1`, the modifiers keep, drop or count whole members.
+#[derive(Debug, Clone)]
+pub struct Group {
+	pub members: Vec<Expression>,
+	pub modifiers: Vec<PostModifier>,
+}
+
+// `2t[loot]` picks two rows of the table named loot.
+#[derive(Debug, Clone)]
+pub struct TableRoll {
+	pub count: Integer,
+	pub name: String,
+}
+
+// `$atk >= 20 ? 4d6 : 2d6`, only the branch that is chosen is rolled.
+#[derive(Debug, Clone)]
+pub struct Conditional {
+	pub lhs: Expression,
+	pub comparison: Comparison,
+	pub rhs: Expression,
+	pub then: Expression,
+	pub otherwise: Expression,
+}
+
+// A call to one of the functions in functions.rs, `clamp(1d20, 5, 15)`.
+#[derive(Debug, Clone)]
+pub struct Function {
+	pub name: String,
+	pub arguments: Vec<Expression>,
+}
+
+impl Modifiers {
+	pub fn new() -> Modifiers {
+		Modifiers::default()
+	}
+	// The modifiers roll_lang knows how to roll with.
+	pub fn dice_modifiers(&self) -> roll_lang::ast::Modifiers {
+		roll_lang::ast::Modifiers {
+			expanding: self.expanding,
+			reroll_modifiers: self.reroll_modifiers.clone(),
+			post_modifiers: self.post_modifiers.clone(),
+		}
+	}
+	// The dice rolled along with the dice the modifiers are on.
+	pub fn extra_dice(&self) -> u32 {
+		match self.wild {
+			Some(_) => 1,
+			None => 0,
+		}
+	}
+}
+
+impl Expression {
+	// The atom when the expression is nothing else, without comments.
+	pub fn single_atom(&self) -> Option<&Atom> {
+		match self {
+			Expression::MulDiv(MulDiv::Power(Power::Unary(Unary::Atom(None, atom, None)))) => {
+				Some(atom)
+			}
+			_ => None,
+		}
+	}
+}
+impl From<Atom> for Expression {
+	fn from(atom: Atom) -> Expression {
+		Expression::MulDiv(MulDiv::Power(Power::Unary(Unary::Atom(None, atom, None))))
+	}
+}
@@ -0,0 +1,166 @@
+// File: src/system_panel.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use super::presets::Preset;
+
+type InsertHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn(&str)>>>>;
+type SelectedHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn(Option<&Preset>)>>>>;
+
+// Picks the game system of the active profile and shows its buttons. The
+// first entry of the selector is no system.
+#[derive(Clone)]
+pub struct SystemPanel {
+	selector: gtk::ComboBoxText,
+	description: gtk::Label,
+	buttons: gtk::FlowBox,
+	presets: Rc<RefCell<Vec<Preset>>>,
+	// Set while the selector is filled so it does not report a selection
+	updating: Rc<Cell<bool>>,
+	add_macros_button: gtk::Button,
+	on_insert: InsertHandler,
+	on_selected: SelectedHandler,
+}
+impl SystemPanel {
+	pub fn new(builder: &gtk::Builder) -> SystemPanel {
+		let container: gtk::Box = builder
+			.get_object("system_panel")
+			.expect("Could not get box system_panel.");
+
+		let selector = gtk::ComboBoxText::new();
+
+		let description = gtk::Label::new(None);
+		description.set_halign(Align::Start);
+		description.set_xalign(0.0);
+		description.set_line_wrap(true);
+		description.set_no_show_all(true);
+
+		let buttons = gtk::FlowBox::new();
+		buttons.set_selection_mode(SelectionMode::None);
+		buttons.set_column_spacing(5);
+		buttons.set_row_spacing(5);
+
+		let add_macros_button = gtk::Button::with_label("Add System Macros");
+		add_macros_button.set_tooltip_text(Some(
+			"Adds the system's macros to the character sheet, keeping macros with the same name",
+		));
+		let actions = gtk::Box::new(Orientation::Horizontal, 5);
+		actions.pack_end(&add_macros_button, false, true, 0);
+
+		container.pack_start(&selector, false, true, 0);
+		container.pack_start(&description, false, true, 0);
+		container.pack_start(&buttons, false, true, 0);
+		container.pack_start(&actions, false, true, 0);
+
+		let panel = SystemPanel {
+			selector,
+			description,
+			buttons,
+			presets: Rc::new(RefCell::new(Vec::new())),
+			updating: Rc::new(Cell::new(false)),
+			add_macros_button,
+			on_insert: Rc::new(RefCell::new(None)),
+			on_selected: Rc::new(RefCell::new(None)),
+		};
+
+		{
+			let panel_clone = panel.clone();
+			panel.selector.connect_changed(move |_| {
+				let panel = &panel_clone;
+				if panel.updating.get() {
+					return;
+				}
+				panel.show_selected();
+				if let Some(handler) = panel.on_selected.borrow().as_ref() {
+					handler(panel.selected().as_ref());
+				}
+			});
+		}
+
+		panel
+	}
+
+	pub fn set_presets(&self, presets: Vec<Preset>) {
+		self.updating.set(true);
+		self.selector.remove_all();
+		self.selector.append_text("No system");
+		for preset in presets.iter() {
+			self.selector.append_text(&preset.name);
+		}
+		*self.presets.borrow_mut() = presets;
+		self.selector.set_active(Some(0));
+		self.updating.set(false);
+		self.show_selected();
+	}
+	// Selects the preset with the name, a name that is not one of them
+	// selects no system.
+	pub fn select(&self, name: &str) {
+		let index = self
+			.presets
+			.borrow()
+			.iter()
+			.position(|preset| preset.name == name)
+			.map_or(0, |index| index + 1);
+		self.updating.set(true);
+		self.selector.set_active(Some(index as u32));
+		self.updating.set(false);
+		self.show_selected();
+	}
+	pub fn selected(&self) -> Option<Preset> {
+		let index = self.selector.get_active()? as usize;
+		match index {
+			0 => None,
+			_ => self.presets.borrow().get(index - 1).cloned(),
+		}
+	}
+
+	// Called with the text of a preset button when it is clicked.
+	pub fn connect_insert<F: Fn(&str) + 'static>(&self, f: F) {
+		*self.on_insert.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+	// Called when another system is picked, not when one is selected from
+	// code.
+	pub fn connect_selected<F: Fn(Option<&Preset>) + 'static>(&self, f: F) {
+		*self.on_selected.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+	pub fn add_macros_button(&self) -> &gtk::Button {
+		&self.add_macros_button
+	}
+
+	fn show_selected(&self) {
+		for child in self.buttons.get_children() {
+			self.buttons.remove(&child);
+		}
+		let preset = match self.selected() {
+			Some(preset) => preset,
+			None => {
+				self.description.set_text("");
+				self.description.hide();
+				self.add_macros_button.set_sensitive(false);
+				return;
+			}
+		};
+		self.description.set_text(&preset.description);
+		self.description.show();
+		self.add_macros_button
+			.set_sensitive(!preset.macros.is_empty());
+		for preset_button in preset.buttons.into_iter() {
+			let button = gtk::Button::with_label(&preset_button.label);
+			button.set_tooltip_text(Some(&preset_button.insert));
+			let on_insert = Rc::clone(&self.on_insert);
+			button.connect_clicked(move |_| {
+				if let Some(handler) = on_insert.borrow().as_ref() {
+					handler(&preset_button.insert);
+				}
+			});
+			self.buttons.add(&button);
+		}
+		self.buttons.show_all();
+	}
+}
@@ -0,0 +1,342 @@
+// File: src/table_editor.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use crate::gtk::prelude::*;
+use gtk::*;
+
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::rc::Rc;
+
+use super::tables::{parse_weight, RollTable, TableRow, Tables};
+
+type InsertHandler = Rc<RefCell<Option<std::boxed::Box<dyn Fn(&str)>>>>;
+
+struct Row {
+	weight: gtk::Entry,
+	text: gtk::Entry,
+	widgets: Vec<gtk::Widget>,
+}
+
+// Editor for the tables of the active profile. One table is shown at a
+// time, its rows are read back when another one is selected.
+#[derive(Clone)]
+pub struct TableEditor {
+	window: gtk::Window,
+	selector: gtk::ComboBoxText,
+	name: gtk::Entry,
+	grid: gtk::Grid,
+	rows: Rc<RefCell<Vec<Row>>>,
+	next_row: Rc<Cell<i32>>,
+	status: gtk::Label,
+	tables: Rc<RefCell<Vec<(String, RollTable)>>>,
+	selected: Rc<Cell<Option<usize>>>,
+	// Set while the selector is filled so it does not report a selection
+	updating: Rc<Cell<bool>>,
+	save_button: gtk::Button,
+	on_insert: InsertHandler,
+}
+impl TableEditor {
+	pub fn new(builder: &gtk::Builder) -> TableEditor {
+		let container: gtk::Box = builder
+			.get_object("table_editor")
+			.expect("Could not get box table_editor.");
+		let window: gtk::Window = builder
+			.get_object("main_window")
+			.expect("Could not get window main_window.");
+
+		let selector = gtk::ComboBoxText::new();
+		let new_button = gtk::Button::with_label("New Table");
+		let delete_button = gtk::Button::with_label("Delete");
+		let tables_box = gtk::Box::new(Orientation::Horizontal, 5);
+		tables_box.pack_start(&selector, true, true, 0);
+		tables_box.pack_start(&new_button, false, true, 0);
+		tables_box.pack_start(&delete_button, false, true, 0);
+
+		let name = gtk::Entry::new();
+		name.set_placeholder_text(Some("Table name"));
+		let insert_button = gtk::Button::with_label("Insert");
+		let name_box = gtk::Box::new(Orientation::Horizontal, 5);
+		name_box.pack_start(&name, true, true, 0);
+		name_box.pack_start(&insert_button, false, true, 0);
+
+		let grid = gtk::Grid::new();
+		grid.set_row_spacing(5);
+		grid.set_column_spacing(5);
+
+		let status = gtk::Label::new(None);
+		status.set_halign(Align::Start);
+		status.set_line_wrap(true);
+
+		let add_row = gtk::Button::with_label("Add Row");
+		let import_button = gtk::Button::with_label("Import CSV");
+		let save_button = gtk::Button::with_label("Save");
+		let actions = gtk::Box::new(Orientation::Horizontal, 5);
+		actions.pack_start(&add_row, false, true, 0);
+		actions.pack_start(&import_button, false, true, 0);
+		actions.pack_end(&save_button, false, true, 0);
+
+		container.pack_start(&tables_box, false, true, 0);
+		container.pack_start(&name_box, false, true, 0);
+		container.pack_start(&grid, false, true, 0);
+		container.pack_start(&status, false, true, 0);
+		container.pack_start(&actions, false, true, 0);
+
+		let editor = TableEditor {
+			window,
+			selector,
+			name,
+			grid,
+			rows: Rc::new(RefCell::new(Vec::new())),
+			next_row: Rc::new(Cell::new(0)),
+			status,
+			tables: Rc::new(RefCell::new(Vec::new())),
+			selected: Rc::new(Cell::new(None)),
+			updating: Rc::new(Cell::new(false)),
+			save_button,
+			on_insert: Rc::new(RefCell::new(None)),
+		};
+
+		{
+			let editor_clone = editor.clone();
+			editor.selector.connect_changed(move |selector| {
+				let editor = &editor_clone;
+				if editor.updating.get() {
+					return;
+				}
+				editor.read_rows();
+				editor.select(selector.get_active().map(|index| index as usize));
+			});
+		}
+		{
+			let editor = editor.clone();
+			new_button.connect_clicked(move |_| {
+				editor.add_table(String::new(), RollTable::default());
+				editor.name.grab_focus();
+			});
+		}
+		{
+			let editor = editor.clone();
+			delete_button.connect_clicked(move |_| {
+				if let Some(index) = editor.selected.get() {
+					editor.tables.borrow_mut().remove(index);
+					let count = editor.tables.borrow().len();
+					editor.select(match count {
+						0 => None,
+						_ => Some(index.min(count - 1)),
+					});
+				}
+			});
+		}
+		{
+			let editor = editor.clone();
+			add_row.connect_clicked(move |_| {
+				if editor.selected.get().is_none() {
+					editor.add_table(String::new(), RollTable::default());
+				}
+				editor.add_row("1", "");
+				editor.grid.show_all();
+			});
+		}
+		{
+			let editor = editor.clone();
+			insert_button.connect_clicked(move |_| {
+				let name = editor.name.get_text().trim().to_owned();
+				if name.is_empty() {
+					return;
+				}
+				if let Some(handler) = editor.on_insert.borrow().as_ref() {
+					handler(&format!("[[1t[{}]]]", name));
+				}
+			});
+		}
+		{
+			let editor = editor.clone();
+			import_button.connect_clicked(move |_| editor.import_csv());
+		}
+
+		editor
+	}
+
+	pub fn show(&self, tables: &Tables) {
+		let mut tables: Vec<(String, RollTable)> = tables
+			.iter()
+			.map(|(name, table)| (name.clone(), table.clone()))
+			.collect();
+		tables.sort_by(|a, b| a.0.cmp(&b.0));
+		let first = if tables.is_empty() { None } else { Some(0) };
+		*self.tables.borrow_mut() = tables;
+		self.select(first);
+	}
+	// The tables as they are shown, tables without a name are left out.
+	pub fn tables(&self) -> Tables {
+		self.read_rows();
+		self.tables
+			.borrow()
+			.iter()
+			.filter(|(name, _)| !name.is_empty())
+			.cloned()
+			.collect()
+	}
+
+	// Called with `[[1t[name]]]` when the insert button is clicked.
+	pub fn connect_insert<F: Fn(&str) + 'static>(&self, f: F) {
+		*self.on_insert.borrow_mut() = Some(std::boxed::Box::new(f));
+	}
+	pub fn save_button(&self) -> &gtk::Button {
+		&self.save_button
+	}
+
+	fn add_table(&self, name: String, table: RollTable) {
+		self.read_rows();
+		self.tables.borrow_mut().push((name, table));
+		let index = self.tables.borrow().len() - 1;
+		self.select(Some(index));
+	}
+	// Writes the shown name and rows back to the selected table. Rows
+	// without text are left out.
+	fn read_rows(&self) {
+		let index = match self.selected.get() {
+			Some(index) => index,
+			None => return,
+		};
+		let rows = self
+			.rows
+			.borrow()
+			.iter()
+			.map(|row| TableRow {
+				weight: parse_weight(&row.weight.get_text()).unwrap_or(1),
+				text: row.text.get_text().trim().to_owned(),
+			})
+			.filter(|row| !row.text.is_empty())
+			.collect();
+		if let Some(table) = self.tables.borrow_mut().get_mut(index) {
+			*table = (self.name.get_text().trim().to_owned(), RollTable { rows });
+		}
+	}
+	fn select(&self, index: Option<usize>) {
+		self.selected.set(index);
+		self.status.set_text("");
+
+		self.updating.set(true);
+		self.selector.remove_all();
+		for (name, _) in self.tables.borrow().iter() {
+			self.selector
+				.append_text(if name.is_empty() { "(unnamed)" } else { name });
+		}
+		self.selector.set_active(index.map(|index| index as u32));
+		self.updating.set(false);
+
+		for row in self.rows.borrow_mut().drain(..) {
+			for widget in row.widgets {
+				unsafe {
+					widget.destroy();
+				}
+			}
+		}
+		self.next_row.set(0);
+		let tables = self.tables.borrow();
+		match index.and_then(|index| tables.get(index)) {
+			Some((name, table)) => {
+				self.name.set_text(name);
+				for row in table.rows.iter() {
+					self.add_row(&row.weight.to_string(), &row.text);
+				}
+			}
+			None => self.name.set_text(""),
+		}
+		self.grid.show_all();
+	}
+
+	fn add_row(&self, weight_text: &str, row_text: &str) {
+		// Removed rows leave a gap in the grid, it collapses empty rows
+		let row = self.next_row.get();
+		self.next_row.set(row + 1);
+
+		let weight = gtk::Entry::new();
+		weight.set_width_chars(5);
+		weight.set_placeholder_text(Some("1-3"));
+		weight.set_tooltip_text(Some("A weight or the range of a die the row covers"));
+		weight.set_text(weight_text);
+		let text = gtk::Entry::new();
+		text.set_width_chars(24);
+		text.set_placeholder_text(Some("[[2d6]] gold pieces"));
+		text.set_text(row_text);
+		let remove = gtk::Button::with_label("Remove");
+
+		self.grid.attach(&weight, 0, row, 1, 1);
+		self.grid.attach(&text, 1, row, 1, 1);
+		self.grid.attach(&remove, 2, row, 1, 1);
+
+		{
+			let text = text.clone();
+			let rows = Rc::clone(&self.rows);
+			remove.connect_clicked(move |_| {
+				let mut rows = rows.borrow_mut();
+				if let Some(index) = rows.iter().position(|row| row.text == text) {
+					for widget in rows.remove(index).widgets {
+						unsafe {
+							widget.destroy();
+						}
+					}
+				}
+			});
+		}
+
+		let widgets = vec![
+			weight.clone().upcast::<gtk::Widget>(),
+			text.clone().upcast(),
+			remove.upcast(),
+		];
+		self.rows.borrow_mut().push(Row {
+			weight,
+			text,
+			widgets,
+		});
+	}
+
+	// Adds the chosen file as a new table named after it.
+	fn import_csv(&self) {
+		let dialog = gtk::FileChooserDialog::with_buttons(
+			Some("Import Table"),
+			Some(&self.window),
+			FileChooserAction::Open,
+			&[
+				("_Cancel", ResponseType::Cancel),
+				("_Open", ResponseType::Accept),
+			],
+		);
+		let filter = gtk::FileFilter::new();
+		filter.set_name(Some("CSV files"));
+		filter.add_pattern("*.csv");
+		dialog.add_filter(&filter);
+		let path = match dialog.run() {
+			ResponseType::Accept => dialog.get_filename(),
+			_ => None,
+		};
+		unsafe {
+			dialog.destroy();
+		}
+		let path = match path {
+			Some(path) => path,
+			None => return,
+		};
+
+		let imported = fs::read_to_string(&path)
+			.map_err(|e| e.to_string())
+			.and_then(|source| RollTable::from_csv(&source).map_err(|e| e.to_string()));
+		match imported {
+			Ok(table) => {
+				let name = path
+					.file_stem()
+					.map(|stem| stem.to_string_lossy().into_owned())
+					.unwrap_or_default();
+				self.add_table(name, table);
+			}
+			Err(e) => self
+				.status
+				.set_text(&format!("Could not import {}: {}", path.display(), e)),
+		}
+	}
+}
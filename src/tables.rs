@@ -0,0 +1,172 @@
+// File: src/tables.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::collections::HashMap;
+use std::fmt;
+
+use toml::value::Table;
+use toml::Value;
+
+use super::storage;
+
+#[derive(Debug, Clone)]
+pub struct TableRow {
+	pub weight: u32,
+	// May hold rolls and macros, they are rolled when the row is picked
+	pub text: String,
+}
+
+// A weighted table rolled with `1t[name]`.
+#[derive(Debug, Clone, Default)]
+pub struct RollTable {
+	pub rows: Vec<TableRow>,
+}
+
+pub type Tables = HashMap<String, RollTable>;
+
+#[derive(Debug, Clone)]
+pub enum ImportError {
+	BadWeight(usize),
+	NoRows,
+}
+impl fmt::Display for ImportError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ImportError::BadWeight(line) => {
+				write!(f, "Line {} does not start with a weight or range", line)
+			}
+			ImportError::NoRows => write!(f, "The file has no rows"),
+		}
+	}
+}
+
+// A weight is a number, `3`, or the range of a die it covers, `1-3`.
+pub fn parse_weight(text: &str) -> Option<u32> {
+	let text = text.trim();
+	match text.find('-') {
+		Some(dash) => {
+			let low: u32 = text[..dash].trim().parse().ok()?;
+			let high: u32 = text[(dash + 1)..].trim().parse().ok()?;
+			high.checked_sub(low).map(|difference| difference + 1)
+		}
+		None => text.parse().ok(),
+	}
+}
+
+impl RollTable {
+	// `roll` is in [0, 1), as from roll_lang's rand.
+	pub fn pick(&self, roll: f64) -> Option<&TableRow> {
+		let total: u32 = self.rows.iter().map(|row| row.weight).sum();
+		if total == 0 {
+			return None;
+		}
+		let mut target = (roll * total as f64) as u32;
+		for row in self.rows.iter() {
+			if target < row.weight {
+				return Some(row);
+			}
+			target -= row.weight;
+		}
+		self.rows.iter().rev().find(|row| row.weight > 0)
+	}
+
+	// Reads `weight,text` lines. The text may be quoted to hold commas, line
+	// breaks or quotes, and a first line without a weight is taken as a
+	// header. When any row gives a range a lone number is the range of one
+	// face, as in `4,Goblins` between `1-3` and `5-6`.
+	pub fn from_csv(source: &str) -> Result<RollTable, ImportError> {
+		let records = csv_records(source);
+		let ranges = records
+			.iter()
+			.any(|record| parse_weight(&record[0]).is_some() && record[0].contains('-'));
+		let mut rows = Vec::new();
+		for (i, record) in records.into_iter().enumerate() {
+			let mut fields = record.into_iter();
+			let weight = fields.next().unwrap_or_default();
+			let text: Vec<String> = fields.collect();
+			let weight = match parse_weight(&weight) {
+				Some(_) if ranges && !weight.contains('-') => 1,
+				Some(weight) => weight,
+				None if i == 0 => continue,
+				None => return Err(ImportError::BadWeight(i + 1)),
+			};
+			rows.push(TableRow {
+				weight,
+				text: text.join(",").trim().to_owned(),
+			});
+		}
+		if rows.is_empty() {
+			return Err(ImportError::NoRows);
+		}
+		Ok(RollTable { rows })
+	}
+
+	pub fn to_value(&self) -> Value {
+		let rows = self
+			.rows
+			.iter()
+			.map(|row| {
+				let mut table = Table::new();
+				table.insert(String::from("weight"), Value::Integer(row.weight as i64));
+				table.insert(String::from("text"), Value::String(row.text.clone()));
+				Value::Table(table)
+			})
+			.collect();
+		Value::Array(rows)
+	}
+	pub fn from_value(value: &Value) -> RollTable {
+		let rows = value
+			.as_array()
+			.map(|rows| {
+				rows.iter()
+					.filter_map(Value::as_table)
+					.map(|row| TableRow {
+						weight: row
+							.get("weight")
+							.and_then(Value::as_integer)
+							.unwrap_or(1)
+							.max(0) as u32,
+						text: storage::get_str(row, "text"),
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		RollTable { rows }
+	}
+}
+
+// Splits CSV into records of fields. Blank lines are skipped.
+fn csv_records(source: &str) -> Vec<Vec<String>> {
+	let mut records = Vec::new();
+	let mut record = Vec::new();
+	let mut field = String::new();
+	let mut quoted = false;
+	let mut chars = source.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'"' if quoted && chars.peek() == Some(&'"') => {
+				field.push('"');
+				chars.next();
+			}
+			'"' if quoted => quoted = false,
+			'"' if field.trim().is_empty() => {
+				field.clear();
+				quoted = true;
+			}
+			',' if !quoted => record.push(std::mem::take(&mut field)),
+			'\n' if !quoted => {
+				record.push(std::mem::take(&mut field));
+				records.push(std::mem::take(&mut record));
+			}
+			'\r' if !quoted => (),
+			c => field.push(c),
+		}
+	}
+	record.push(field);
+	records.push(record);
+	records
+		.into_iter()
+		.filter(|record| record.iter().any(|field| !field.trim().is_empty()))
+		.collect()
+}
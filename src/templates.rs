@@ -0,0 +1,252 @@
+// File: src/templates.rs
+// Author: Jacob Guenther
+// Date: January 2020
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use toml::value::Table;
+use toml::Value;
+
+use super::output::{Card, OutputFragment};
+use super::roll::{fragments_string, TextOptions};
+use super::storage;
+
+static BUILTIN: [&str; 3] = [
+	include_str!("../data/templates/default.toml"),
+	include_str!("../data/templates/attack.toml"),
+	include_str!("../data/templates/spell.toml"),
+];
+
+// A row of a card, the value of the field under a label.
+#[derive(Debug, Clone)]
+pub struct TemplateRow {
+	pub label: String,
+	pub field: String,
+}
+
+// How the fields of `&{template:attack} {{name=Longsword}}` are laid out.
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+	pub name: String,
+	// The fields that are the title and the footer of the card
+	pub title: String,
+	pub footer: String,
+	pub colour: Option<String>,
+	// Every other field under its own name when there are none
+	pub rows: Vec<TemplateRow>,
+}
+impl Template {
+	pub fn from_table(table: &Table) -> Template {
+		let rows = table
+			.get("rows")
+			.and_then(Value::as_array)
+			.map(|rows| {
+				rows.iter()
+					.filter_map(Value::as_table)
+					.map(|row| TemplateRow {
+						label: storage::get_str(row, "label"),
+						field: storage::get_str(row, "field"),
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+		let title = match storage::get_str(table, "title") {
+			title if title.is_empty() => String::from("name"),
+			title => title,
+		};
+		Template {
+			name: storage::get_str(table, "name"),
+			title,
+			footer: storage::get_str(table, "footer"),
+			colour: table
+				.get("colour")
+				.and_then(Value::as_str)
+				.filter(|colour| is_colour(colour))
+				.map(str::to_owned),
+			rows,
+		}
+	}
+
+	// Lays out the rolled fields. Rows of fields that are not given are left
+	// out and a card without a title field is titled with the template name.
+	pub fn card(&self, mut fields: Vec<(String, Vec<OutputFragment>)>) -> Card {
+		let title = take_field(&mut fields, &self.title)
+			.unwrap_or_else(|| vec![OutputFragment::StringLit(self.name.clone())]);
+		let footer = take_field(&mut fields, &self.footer).unwrap_or_default();
+		let rows = match self.rows.is_empty() {
+			true => fields,
+			false => self
+				.rows
+				.iter()
+				.filter_map(|row| Some((row.label.clone(), take_field(&mut fields, &row.field)?)))
+				.collect(),
+		};
+		Card {
+			title,
+			colour: self.colour.clone(),
+			rows,
+			footer,
+		}
+	}
+}
+
+// Colours are put in markup and HTML as they are, so only `#rrggbb` and the
+// names both know are taken.
+static COLOUR_NAMES: [&str; 24] = [
+	"black",
+	"white",
+	"grey",
+	"gray",
+	"silver",
+	"red",
+	"darkred",
+	"maroon",
+	"orange",
+	"gold",
+	"yellow",
+	"olive",
+	"green",
+	"darkgreen",
+	"lime",
+	"teal",
+	"cyan",
+	"blue",
+	"darkblue",
+	"navy",
+	"purple",
+	"magenta",
+	"pink",
+	"brown",
+];
+fn is_colour(colour: &str) -> bool {
+	match colour.strip_prefix('#') {
+		Some(hex) => hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+		None => COLOUR_NAMES.contains(&colour.to_ascii_lowercase().as_str()),
+	}
+}
+
+fn take_field(
+	fields: &mut Vec<(String, Vec<OutputFragment>)>,
+	name: &str,
+) -> Option<Vec<OutputFragment>> {
+	let index = fields.iter().position(|(field, _)| field == name)?;
+	Some(fields.remove(index).1)
+}
+
+// The templates rolls can be laid out with by name.
+pub type Templates = HashMap<String, Template>;
+
+fn dir() -> PathBuf {
+	storage::data_dir().join("templates")
+}
+
+// The built in templates followed by the ones in the templates directory.
+pub fn load() -> Vec<Template> {
+	let mut templates: Vec<Template> = BUILTIN
+		.iter()
+		.filter_map(|source| match source.parse::<Value>() {
+			Ok(Value::Table(table)) => Some(Template::from_table(&table)),
+			_ => None,
+		})
+		.collect();
+	let mut paths: Vec<PathBuf> = fs::read_dir(dir())
+		.map(|entries| {
+			entries
+				.filter_map(Result::ok)
+				.map(|entry| entry.path())
+				.filter(|path| {
+					path.extension()
+						.is_some_and(|extension| extension == "toml")
+				})
+				.collect()
+		})
+		.unwrap_or_default();
+	paths.sort();
+	for path in paths.iter() {
+		let template = match storage::load_table(path) {
+			Ok(table) => Template::from_table(&table),
+			Err(e) => {
+				eprintln!("Could not load template {}: {}", path.display(), e);
+				continue;
+			}
+		};
+		if template.name.is_empty() {
+			eprintln!("Could not load template {}: it has no name", path.display());
+			continue;
+		}
+		templates.retain(|other| other.name != template.name);
+		templates.push(template);
+	}
+	templates
+}
+
+// Every card in the fragments, including ones in repeated rolls.
+pub fn cards(fragments: &[OutputFragment]) -> Vec<&Card> {
+	let mut found = Vec::new();
+	for fragment in fragments.iter() {
+		match fragment {
+			OutputFragment::Card(card) => found.push(card),
+			OutputFragment::Repeated(repeated) => {
+				for fragments in repeated.iter() {
+					found.append(&mut cards(fragments));
+				}
+			}
+			_ => (),
+		}
+	}
+	found
+}
+
+// A page with the cards on it that needs nothing else to be shown.
+pub fn cards_html(cards: &[&Card], options: TextOptions) -> String {
+	let mut html = String::from(
+		"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Roll</title>\n<style>\n\
+		body { font-family: sans-serif; background: #eeeeee; }\n\
+		.card { max-width: 30em; margin: 1em auto; background: #ffffff; border: 1px solid #9e9e9e; border-radius: 4px; overflow: hidden; }\n\
+		.card h2 { margin: 0; padding: 0.4em 0.6em; color: #ffffff; background: #424242; font-size: 1.2em; }\n\
+		.card table { width: 100%; border-collapse: collapse; }\n\
+		.card th, .card td { padding: 0.3em 0.6em; text-align: left; vertical-align: top; border-top: 1px solid #e0e0e0; }\n\
+		.card p { margin: 0; padding: 0.4em 0.6em; border-top: 1px solid #e0e0e0; font-style: italic; }\n\
+		</style>\n</head>\n<body>\n",
+	);
+	for card in cards.iter() {
+		html.push_str("<div class=\"card\">\n");
+		match &card.colour {
+			Some(colour) => html.push_str(&format!(
+				"<h2 style=\"background: {}\">",
+				escape_html(colour)
+			)),
+			None => html.push_str("<h2>"),
+		}
+		html.push_str(&escape_html(&fragments_string(&card.title, options)));
+		html.push_str("</h2>\n");
+		if !card.rows.is_empty() {
+			html.push_str("<table>\n");
+			for (label, value) in card.rows.iter() {
+				html.push_str(&format!(
+					"<tr><th>{}</th><td>{}</td></tr>\n",
+					escape_html(label),
+					escape_html(&fragments_string(value, options))
+				));
+			}
+			html.push_str("</table>\n");
+		}
+		if !card.footer.is_empty() {
+			html.push_str(&format!(
+				"<p>{}</p>\n",
+				escape_html(&fragments_string(&card.footer, options))
+			));
+		}
+		html.push_str("</div>\n");
+	}
+	html.push_str("</body>\n</html>\n");
+	html
+}
+pub fn escape_html(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}